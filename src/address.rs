@@ -0,0 +1,121 @@
+use core::fmt::Display;
+
+/// The full address of a PCI function: segment group, bus, device, and function (SBDF).
+/// Legacy PCI only has segment group 0.
+///
+/// Displays like `lspci -D`, for example `0000:00:1f.2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    /// `0..32`
+    pub device: u8,
+    /// `0..8`
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        assert!((0..32).contains(&device));
+        assert!((0..8).contains(&function));
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// Displays the address without the segment group, like `lspci`, for example `00:1f.2`.
+    /// Only use this if there is only 1 segment group.
+    pub fn bdf(&self) -> Bdf {
+        Bdf(*self)
+    }
+}
+
+/// See [`PciAddress::bdf`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bdf(pub PciAddress);
+
+impl Display for Bdf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}.{:x}",
+            self.0.bus, self.0.device, self.0.function
+        )
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}.{:x}",
+            DeviceLocation {
+                segment: self.segment,
+                bus: self.bus,
+                device: self.device,
+            },
+            self.function
+        )
+    }
+}
+
+/// Displays like the start of a [`PciAddress`], for example `0000:00`
+pub(super) struct BusLocation {
+    pub(super) segment: u16,
+    pub(super) bus: u8,
+}
+
+impl Display for BusLocation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:04x}:{:02x}", self.segment, self.bus)
+    }
+}
+
+/// Displays like a [`PciAddress`] without the function, for example `0000:00:1f`
+pub(super) struct DeviceLocation {
+    pub(super) segment: u16,
+    pub(super) bus: u8,
+    pub(super) device: u8,
+}
+
+impl Display for DeviceLocation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}:{:02x}",
+            BusLocation {
+                segment: self.segment,
+                bus: self.bus,
+            },
+            self.device
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+
+    use super::*;
+
+    #[test]
+    fn formats_locations() {
+        let address = PciAddress::new(0x1, 0x3, 0x1F, 0x2);
+        assert_eq!(format!("{address}"), "0001:03:1f.2");
+        assert_eq!(format!("{}", address.bdf()), "03:1f.2");
+        let bus = BusLocation {
+            segment: 0x10,
+            bus: 0xAB,
+        };
+        assert_eq!(format!("{bus}"), "0010:ab");
+        let device = DeviceLocation {
+            segment: 0,
+            bus: 0,
+            device: 0x3,
+        };
+        assert_eq!(format!("{device}"), "0000:00:03");
+    }
+}
@@ -1,6 +1,9 @@
-use core::fmt::Debug;
+use core::{fmt::Debug, num::NonZero, ops::Range, ptr::NonNull};
 
 use bitfield::bitfield;
+use volatile::VolatileRef;
+
+use super::*;
 
 bitfield! {
     #[derive( Clone, Copy)]
@@ -37,19 +40,29 @@ impl Debug for IoSpaceBar {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MemoryBarAddrAndSizeU32 {
     pub addr: u32,
     pub size: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MemoryBarAddrAndSizeU64 {
     pub addr: u64,
     pub size: u64,
+    /// How many bits of address the BAR can decode, from probing the upper half of the BAR.
+    /// Some devices can't decode all 64 bits, so the BAR must be placed below `1 << addressable_bits`.
+    pub addressable_bits: u8,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl MemoryBarAddrAndSizeU64 {
+    /// The highest address that the BAR can be placed at, so that the whole BAR is addressable
+    pub fn max_addr(&self) -> u64 {
+        (u64::MAX >> (u64::BITS - self.addressable_bits as u32)) - self.size.saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MemoryBarAddrAndSize {
     U32(MemoryBarAddrAndSizeU32),
     U64(MemoryBarAddrAndSizeU64),
@@ -72,20 +85,53 @@ impl MemoryBarAddrAndSize {
         }
     }
 
+    pub fn phys_addr(&self) -> PhysAddr {
+        PhysAddr::new(self.addr_u64())
+    }
+
+    /// The physical memory of the BAR, which you can map
+    pub fn phys_range(&self) -> Range<PhysAddr> {
+        self.phys_addr()..self.phys_addr() + self.size_u64()
+    }
+
     pub fn addr_and_size_u64(self) -> MemoryBarAddrAndSizeU64 {
         match self {
             Self::U32(addr_and_size) => MemoryBarAddrAndSizeU64 {
                 addr: addr_and_size.addr as u64,
                 size: addr_and_size.size as u64,
+                addressable_bits: 32,
             },
             Self::U64(addr_and_size) => addr_and_size,
         }
     }
 }
 
-#[derive(Debug)]
+/// The type bits (2:1) of a memory BAR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryBarKind {
+    Bit32,
+    Bit64,
+    /// Only in older versions of the PCI spec. The BAR must be mapped below 1 MiB. It is decoded like a 32-bit BAR.
+    Below1MiB,
+    /// The type bits have a value that is reserved by the spec, so the layout of the BAR is unknown
+    Reserved(u8),
+}
+
+impl MemoryBarKind {
+    pub(super) fn from_type_bits(type_bits: u8) -> Self {
+        match type_bits {
+            0x0 => Self::Bit32,
+            0x1 => Self::Below1MiB,
+            0x2 => Self::Bit64,
+            type_bits => Self::Reserved(type_bits),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MemoryBarInfo {
     pub addr_and_size: MemoryBarAddrAndSize,
+    pub kind: MemoryBarKind,
     /// CPUs can pre-fetch memory, which can result in memory being fetched earlier than your code reads it, fetched multiple times, or memory that your code doesn't read being fetched.
     /// Pre-fetching memory is good for performance, but can cause bad side-effects if the memory is not prefetchable.
     ///
@@ -94,16 +140,48 @@ pub struct MemoryBarInfo {
     pub prefetchable: bool,
 }
 
-#[derive(Debug)]
+impl MemoryBarInfo {
+    /// Treat the mapped BAR as a `#[repr(C)]` struct of registers, similar to how [`MsiXTable`](crate::MsiXTable) works.
+    ///
+    /// # Safety
+    /// The virtual address must be mapped to the **start** of the BAR, using the correct memory type.
+    /// `T` must match the layout of the device's registers.
+    pub unsafe fn as_volatile<T>(&self, virt_addr: NonZero<usize>) -> VolatileRef<'static, T> {
+        debug_assert!(
+            size_of::<T>() as u64 <= self.addr_and_size.size_u64(),
+            "T is bigger than the BAR"
+        );
+        let ptr = NonNull::new(virt_addr.get() as *mut T).expect("ptr is not null");
+        unsafe { VolatileRef::new(ptr) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct IoBarInfo {
     pub addr: u32,
     pub size: u32,
 }
 
-#[derive(Debug)]
+/// A BAR that reported a size which can't be right, or a 64-bit BAR in the last slot, so it must not be mapped or assigned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InvalidBarInfo {
+    pub is_io: bool,
+    pub addr: u64,
+    /// The decoded size, which is 0 or not a power of 2. It is 0 for a 64-bit BAR in the last slot, which isn't sized.
+    pub size: u64,
+    pub slots_len: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BarWithSize {
     Memory(MemoryBarInfo),
     Io(IoBarInfo),
+    /// The BAR is implemented, but the size that it reported is 0 or not a power of 2, or it is a 64-bit BAR in the last slot
+    Invalid(InvalidBarInfo),
+    /// A memory BAR with reserved type bits (see [`MemoryBarKind::Reserved`]). It isn't sized, and must not be mapped or assigned.
+    Unknown {
+        raw: u32,
+    },
 }
 
 impl BarWithSize {
@@ -115,6 +193,380 @@ impl BarWithSize {
                 MemoryBarAddrAndSize::U64(_) => 2,
             },
             BarWithSize::Io(_) => 1,
+            BarWithSize::Invalid(invalid_bar_info) => invalid_bar_info.slots_len,
+            BarWithSize::Unknown { .. } => 1,
+        }
+    }
+
+    /// Turns the BAR into [`Self::Invalid`] if its size is not a power of 2
+    pub(super) fn validated(self) -> Self {
+        let (is_io, addr, size) = match self {
+            Self::Memory(memory_bar_info) => (
+                false,
+                memory_bar_info.addr_and_size.addr_u64(),
+                memory_bar_info.addr_and_size.size_u64(),
+            ),
+            Self::Io(io_bar_info) => (true, io_bar_info.addr as u64, io_bar_info.size as u64),
+            Self::Invalid(_) | Self::Unknown { .. } => return self,
+        };
+        if size.is_power_of_two() {
+            self
+        } else {
+            Self::Invalid(InvalidBarInfo {
+                is_io,
+                addr,
+                size,
+                slots_len: self.slots_len(),
+            })
+        }
+    }
+}
+
+/// Iterates through the BARs that are present, with the index of their first slot.
+/// The high slot of a 64-bit BAR is skipped, so each BAR is only yielded once.
+pub struct Bars<'a, 'b> {
+    function: &'a mut PciFunction<'b>,
+    bar_index: u8,
+    max_bars: u8,
+}
+
+impl Iterator for Bars<'_, '_> {
+    type Item = (u8, BarWithSize);
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bar_index < self.max_bars {
+            let bar_index = self.bar_index;
+            match self.function.read_bar_with_size(bar_index).flatten() {
+                Some(bar) => {
+                    self.bar_index += bar.slots_len();
+                    return Some((bar_index, bar));
+                }
+                None => self.bar_index += 1,
+            }
+        }
+        None
+    }
+}
+
+impl<'b> PciFunction<'b> {
+    /// Iterates through the BARs with [`Self::read_bar_with_size`].
+    /// If the header type is not known, this iterates through nothing.
+    pub fn bars(&mut self) -> Bars<'_, 'b> {
+        let max_bars = self.max_bars().unwrap_or_default();
+        Bars {
+            function: self,
+            bar_index: 0,
+            max_bars,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 4,
+        function: 0,
+    };
+
+    fn mock_device(f: impl FnOnce(&mut MockFunction)) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        f(mock
+            .function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0));
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn below_1_mib_bar() {
+        let mut pci = mock_device(|function| {
+            function
+                .set_u32(0x10, 0x000D_0000 | 0b010)
+                .writable_u32(0x10, !(0x1000 - 1));
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Memory(MemoryBarInfo {
+                addr_and_size: MemoryBarAddrAndSize::U32(MemoryBarAddrAndSizeU32 {
+                    addr: 0x000D_0000,
+                    size: 0x1000,
+                }),
+                kind: MemoryBarKind::Below1MiB,
+                prefetchable: false,
+            })))
+        );
+    }
+
+    #[test]
+    fn io_bar_size_ignores_the_low_2_bits() {
+        let mut pci = mock_device(|function| {
+            // Bit 1 is reserved, but this device reads it as 1
+            function
+                .set_u32(0x14, 0xC000 | 0b11)
+                .writable_u32(0x14, !(0x20 - 1));
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(1),
+            Some(Some(BarWithSize::Io(IoBarInfo {
+                addr: 0xC000,
+                size: 0x20,
+            })))
+        );
+    }
+
+    #[test]
+    fn bit_64_bar_in_the_last_slot_of_a_bridge() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x06, 0x04, 0x00], 0x1)
+            .set_u32(0x14, 0xFE00_0000 | 0b100)
+            .writable_u32(0x14, !(0x1000 - 1))
+            .set_bytes(0x18, &[0, 1, 1, 0]);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(1),
+            Some(Some(BarWithSize::Invalid(InvalidBarInfo {
+                is_io: false,
+                addr: 0xFE00_0000,
+                size: 0,
+                slots_len: 1,
+            })))
+        );
+        // 0x18 is the bus number register, not the upper half of the BAR
+        assert!(!pci.mock().wrote_to(ADDRESS, 0x18));
+        assert_eq!(pci.mock().function(ADDRESS).u32(0x18), 0x0001_0100);
+    }
+
+    #[test]
+    fn bar_of_a_40_bit_device() {
+        let mut pci = mock_device(|function| {
+            function
+                .memory_bar_64(0, 0x80_0000_0000, 0x10_0000, false)
+                .writable_u32(0x14, 0x0000_00FF)
+                .set_u16(0x4, 0x6);
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Memory(MemoryBarInfo {
+                addr_and_size: MemoryBarAddrAndSize::U64(MemoryBarAddrAndSizeU64 {
+                    addr: 0x80_0000_0000,
+                    size: 0x10_0000,
+                    addressable_bits: 40,
+                }),
+                kind: MemoryBarKind::Bit64,
+                prefetchable: false,
+            })))
+        );
+        // Memory decoding is turned off before the BAR is written, and turned back on after it is restored
+        let writes = pci
+            .mock()
+            .writes()
+            .map(|access| (access.register_offset, access.value))
+            .collect::<Vec<_>>();
+        assert_eq!(writes.first(), Some(&(0x4, 0x4)));
+        assert_eq!(writes.last(), Some(&(0x4, 0x6)));
+        let function = pci.mock().function(ADDRESS);
+        assert_eq!(function.u32(0x10), 0x0000_0004);
+        assert_eq!(function.u32(0x14), 0x80);
+        assert_eq!(function.u16(0x4), 0x6);
+    }
+
+    #[test]
+    fn bar_that_decodes_all_64_bits() {
+        let mut pci = mock_device(|function| {
+            function.memory_bar_64(0, 0x8000_0000_0000, 0x1000, true);
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Memory(MemoryBarInfo {
+                addr_and_size: MemoryBarAddrAndSize::U64(MemoryBarAddrAndSizeU64 {
+                    addr: 0x8000_0000_0000,
+                    size: 0x1000,
+                    addressable_bits: 64,
+                }),
+                kind: MemoryBarKind::Bit64,
+                prefetchable: true,
+            })))
+        );
+        // Decoding was already off, so the command register isn't touched
+        assert!(!pci.mock().wrote_to(ADDRESS, 0x4));
+    }
+
+    #[test]
+    fn bar_that_reads_all_ones() {
+        let mut pci = mock_device(|function| {
+            function.set_u32(0x10, u32::MAX);
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(function.read_bar_with_size(0), Some(None));
+        assert_eq!(pci.mock().writes().count(), 0);
+    }
+
+    #[test]
+    fn bar_at_address_0_is_present() {
+        let mut pci = mock_device(|function| {
+            function.memory_bar_32(0, 0x0, 0x1000, false);
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Memory(MemoryBarInfo {
+                addr_and_size: MemoryBarAddrAndSize::U32(MemoryBarAddrAndSizeU32 {
+                    addr: 0x0,
+                    size: 0x1000,
+                }),
+                kind: MemoryBarKind::Bit32,
+                prefetchable: false,
+            })))
+        );
+        // A BAR that isn't implemented is hardwired to 0
+        assert_eq!(function.read_bar_with_size(1), Some(None));
+    }
+
+    #[test]
+    fn reserved_memory_bar_type() {
+        let mut pci = mock_device(|function| {
+            function
+                .set_u32(0x10, 0xFE00_0000 | 0b110)
+                .writable_u32(0x10, !(0x1000 - 1));
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Unknown {
+                raw: 0xFE00_0000 | 0b110
+            }))
+        );
+        assert_eq!(pci.mock().writes().count(), 0);
+    }
+
+    #[test]
+    fn restores_the_high_dword_before_the_low_dword() {
+        let mut pci = mock_device(|function| {
+            function.memory_bar_64(2, 0x12_3456_7000, 0x1000, true);
+        });
+        let (low, high) = {
+            let function = pci.mock().function(ADDRESS);
+            (function.u32(0x18), function.u32(0x1C))
+        };
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert!(function.read_bar_with_size(2).flatten().is_some());
+        let writes = pci
+            .mock()
+            .writes()
+            .map(|access| (access.register_offset, access.value as u32))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            writes,
+            [
+                (0x18, u32::MAX),
+                (0x1C, u32::MAX),
+                (0x1C, high),
+                (0x18, low)
+            ]
+        );
+        let function = pci.mock().function(ADDRESS);
+        assert_eq!((function.u32(0x18), function.u32(0x1C)), (low, high));
+    }
+
+    #[test]
+    fn io_bar_that_only_decodes_16_bits() {
+        let mut pci = mock_device(|function| {
+            function
+                .set_u32(0x10, 0xE000 | 0b1)
+                .writable_u32(0x10, 0xFFE0);
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Io(IoBarInfo {
+                addr: 0xE000,
+                size: 0x20,
+            })))
+        );
+    }
+
+    #[test]
+    fn io_bar_that_decodes_32_bits() {
+        let mut pci = mock_device(|function| {
+            function.io_bar(0, 0xE000, 0x10);
+        });
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.read_bar_with_size(0),
+            Some(Some(BarWithSize::Io(IoBarInfo {
+                addr: 0xE000,
+                size: 0x10,
+            })))
+        );
+    }
+
+    #[test]
+    fn bars_are_sized_with_decoding_disabled() {
+        let mut pci = mock_device(|function| {
+            function
+                .set_u16(0x4, 0b111)
+                .io_bar(0, 0xE000, 0x100)
+                .memory_bar_64(1, 0x8_0000_0000, 0x10_0000, true)
+                .memory_bar_32(4, 0xFD00_0000, 0x1000, false);
+        });
+        let before = *pci.mock().function(ADDRESS).bytes();
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        let bars = function.bars().collect::<Vec<_>>();
+        assert_eq!(
+            bars,
+            [
+                (
+                    0,
+                    BarWithSize::Io(IoBarInfo {
+                        addr: 0xE000,
+                        size: 0x100,
+                    })
+                ),
+                (
+                    1,
+                    BarWithSize::Memory(MemoryBarInfo {
+                        addr_and_size: MemoryBarAddrAndSize::U64(MemoryBarAddrAndSizeU64 {
+                            addr: 0x8_0000_0000,
+                            size: 0x10_0000,
+                            addressable_bits: 64,
+                        }),
+                        kind: MemoryBarKind::Bit64,
+                        prefetchable: true,
+                    })
+                ),
+                (
+                    4,
+                    BarWithSize::Memory(MemoryBarInfo {
+                        addr_and_size: MemoryBarAddrAndSize::U32(MemoryBarAddrAndSizeU32 {
+                            addr: 0xFD00_0000,
+                            size: 0x1000,
+                        }),
+                        kind: MemoryBarKind::Bit32,
+                        prefetchable: false,
+                    })
+                ),
+            ]
+        );
+        // Every BAR write happened while I/O and memory decoding were disabled, and everything was restored
+        let mut command = 0b111;
+        for access in pci.mock().writes() {
+            match access.register_offset {
+                0x4 => command = access.value,
+                0x10..0x28 => assert_eq!(command & 0b11, 0),
+                _ => {}
+            }
         }
+        assert_eq!(command, 0b111);
+        assert_eq!(pci.mock().function(ADDRESS).bytes(), &before);
     }
 }
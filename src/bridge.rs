@@ -0,0 +1,244 @@
+use core::ops::Range;
+
+use bitfield::bitfield;
+
+use super::*;
+
+bitfield! {
+    /// PCI-to-PCI Bridge Architecture Specification -> 3.2.5.18. Bridge Control Register
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct BridgeControl(u16);
+    impl Debug;
+
+    pub parity_error_response_enable, set_parity_error_response_enable: 0;
+    pub serr_enable, set_serr_enable: 1;
+    pub isa_enable, set_isa_enable: 2;
+    /// If this is 1, the bridge forwards the legacy VGA memory (`0xA0000..=0xBFFFF`) and I/O ports (`0x3B0..=0x3BB` and `0x3C0..=0x3DF`)
+    pub vga_enable, set_vga_enable: 3;
+    /// If this is 1, the bridge decodes all 16 bits of VGA I/O addresses. If this is 0, it only decodes the lower 10 bits, so aliases of the VGA ports are forwarded too.
+    pub vga_16_bit_decode, set_vga_16_bit_decode: 4;
+    pub master_abort_mode, set_master_abort_mode: 5;
+    /// To reset everything behind the bridge, set this to 1, wait, and then set it back to 0.
+    /// The PCI spec requires the reset to be asserted for at least 1 ms, and devices need time to be ready after it is cleared.
+    pub secondary_bus_reset, set_secondary_bus_reset: 6;
+    pub fast_back_to_back_enable, set_fast_back_to_back_enable: 7;
+    pub primary_discard_timeout, _: 8;
+    pub secondary_discard_timeout, _: 9;
+    pub discard_timer_status, _: 10;
+    pub discard_timer_serr_enable, _: 11;
+    // bits 12..=15 are reserved
+}
+
+/// The I/O window of a PCI-to-PCI bridge, which is the range of I/O addresses that it forwards to its secondary bus
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IoWindow {
+    /// This is empty if the window is disabled (the base is above the limit)
+    pub range: Range<u32>,
+    /// Whether the bridge decodes 32-bit I/O addresses. If this is `false`, the window is below 64 KiB.
+    pub is_32_bit: bool,
+}
+
+/// Why [`PciFunction::set_io_window`] failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IoWindowError {
+    /// The header type is not [`HeaderType::PciToPciBridge`]
+    NotABridge,
+    /// The start and end must be multiples of 4 KiB
+    Unaligned,
+    /// The window goes above 64 KiB, but the bridge only decodes 16-bit I/O addresses
+    Requires32BitIo,
+}
+
+/// The low nibble of the I/O base and I/O limit registers is `0x1` if the bridge decodes 32-bit I/O addresses
+const IO_32_BIT: u8 = 0x1;
+
+impl PciFunction<'_> {
+    /// Returns `None` if the header type is not [`HeaderType::PciToPciBridge`]
+    #[must_use]
+    pub fn secondary_latency_timer(&mut self) -> Option<u8> {
+        match self.header_type() {
+            HeaderType::PciToPciBridge => Some(self.pci.read_u8(self.address, 0x1B)),
+            _ => None,
+        }
+    }
+
+    /// Returns `None` if the header type is not [`HeaderType::PciToPciBridge`]
+    pub fn set_secondary_latency_timer(&mut self, secondary_latency_timer: u8) -> Option<()> {
+        match self.header_type() {
+            HeaderType::PciToPciBridge => {
+                self.pci
+                    .write_u8(self.address, 0x1B, secondary_latency_timer);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    /// The upper 16 bits of the I/O base and I/O limit (`0x30` and `0x32`) are only read if the bridge decodes 32-bit I/O addresses.
+    ///
+    /// PCI-to-PCI Bridge Architecture Specification -> 3.2.5.6. I/O Base Register and I/O Limit Register
+    ///
+    /// Returns `None` if the header type is not [`HeaderType::PciToPciBridge`]
+    #[must_use]
+    pub fn io_window(&mut self) -> Option<IoWindow> {
+        if self.header_type() != HeaderType::PciToPciBridge {
+            return None;
+        }
+        let reg = self.pci.read_u16(self.address, 0x1C);
+        let (io_base, io_limit) = (reg as u8, (reg >> 8) as u8);
+        let is_32_bit = io_base & 0xF == IO_32_BIT;
+        let (upper_base, upper_limit) = if is_32_bit {
+            let reg = self.pci.read_u32(self.address, 0x30);
+            (reg & 0xFFFF, reg >> 16)
+        } else {
+            (0, 0)
+        };
+        let base = upper_base << 16 | u32::from(io_base & 0xF0) << 8;
+        let limit = upper_limit << 16 | u32::from(io_limit & 0xF0) << 8 | 0xFFF;
+        let range = if base <= limit {
+            // A window that ends at the very top of the 32-bit I/O space loses its last byte, because it can't be represented with a `Range<u32>`
+            base..limit.saturating_add(1)
+        } else {
+            base..base
+        };
+        Some(IoWindow { range, is_32_bit })
+    }
+
+    /// Sets the I/O window. An empty range disables the window.
+    /// The upper 16 bits of the I/O base and I/O limit are only written if the bridge decodes 32-bit I/O addresses.
+    pub fn set_io_window(&mut self, range: Range<u32>) -> Result<(), IoWindowError> {
+        if self.header_type() != HeaderType::PciToPciBridge {
+            return Err(IoWindowError::NotABridge);
+        }
+        if !range.start.is_multiple_of(0x1000) || !range.end.is_multiple_of(0x1000) {
+            return Err(IoWindowError::Unaligned);
+        }
+        let is_32_bit = self.pci.read_u8(self.address, 0x1C) & 0xF == IO_32_BIT;
+        let (base, limit) = if range.is_empty() {
+            // A base above the limit disables the window
+            (0xF000, 0x0FFF)
+        } else {
+            (range.start, range.end - 1)
+        };
+        if !is_32_bit && (base > 0xFFFF || limit > 0xFFFF) {
+            return Err(IoWindowError::Requires32BitIo);
+        }
+        if is_32_bit {
+            self.pci
+                .write_u32(self.address, 0x30, limit & 0xFFFF_0000 | base >> 16);
+        }
+        self.pci.write_u16(
+            self.address,
+            0x1C,
+            ((limit >> 8) as u16 & 0xF0) << 8 | (base >> 8) as u16 & 0xF0,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRIDGE: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 1,
+        function: 0,
+    };
+
+    /// A PCI-to-PCI bridge whose I/O base and limit have the addressing capability in their low nibble, and Secondary Status has a latched error
+    fn mock_bridge(io_addressing: u8) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(BRIDGE)
+            .header(0x8086, 0x1234, [0x06, 0x04, 0x00], 0x1)
+            .set_bytes(0x1C, &[io_addressing, io_addressing])
+            .writable_u8s(0x1C, 2, 0xF0)
+            .set_u16(0x1E, 1 << 13)
+            .read_only(0x1E, 2)
+            .write_1_to_clear_u16(0x1E, StatusRegister::ERROR_BITS);
+        if io_addressing != IO_32_BIT {
+            mock.function(BRIDGE).read_only(0x30, 4);
+        }
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn set_16_bit_io_window() {
+        let mut pci = mock_bridge(0x0);
+        let mut bridge = pci.function_at(0, 1, 0).unwrap();
+        bridge.set_io_window(0x2000..0x4000).unwrap();
+        assert_eq!(
+            bridge.io_window(),
+            Some(IoWindow {
+                range: 0x2000..0x4000,
+                is_32_bit: false
+            })
+        );
+        assert_eq!(
+            bridge.set_io_window(0x1_0000..0x1_1000),
+            Err(IoWindowError::Requires32BitIo)
+        );
+        assert_eq!(
+            bridge.set_io_window(0x2800..0x3000),
+            Err(IoWindowError::Unaligned)
+        );
+        let function = pci.mock().function(BRIDGE);
+        assert_eq!(function.bytes()[0x1C..0x1E], [0x20, 0x30]);
+        assert_eq!(function.u16(0x1E), 1 << 13);
+        assert!(!pci.mock().wrote_to(BRIDGE, 0x1E));
+        assert!(!pci.mock().wrote_to(BRIDGE, 0x30));
+    }
+
+    #[test]
+    fn set_32_bit_io_window() {
+        let mut pci = mock_bridge(IO_32_BIT);
+        let mut bridge = pci.function_at(0, 1, 0).unwrap();
+        bridge.set_io_window(0x1_2000..0x2_0000).unwrap();
+        assert_eq!(
+            bridge.io_window(),
+            Some(IoWindow {
+                range: 0x1_2000..0x2_0000,
+                is_32_bit: true
+            })
+        );
+        let function = pci.mock().function(BRIDGE);
+        assert_eq!(function.bytes()[0x1C..0x1E], [0x21, 0xF1]);
+        assert_eq!(function.u16(0x30), 0x1);
+        assert_eq!(function.u16(0x32), 0x1);
+        assert_eq!(function.u16(0x1E), 1 << 13);
+    }
+
+    #[test]
+    fn disable_io_window() {
+        let mut pci = mock_bridge(IO_32_BIT);
+        let mut bridge = pci.function_at(0, 1, 0).unwrap();
+        bridge.set_io_window(0x1_2000..0x2_0000).unwrap();
+        bridge.set_io_window(0..0).unwrap();
+        assert_eq!(
+            bridge.io_window(),
+            Some(IoWindow {
+                range: 0xF000..0xF000,
+                is_32_bit: true
+            })
+        );
+        let function = pci.mock().function(BRIDGE);
+        assert_eq!(function.bytes()[0x1C..0x1E], [0xF1, 0x01]);
+        assert_eq!(function.u32(0x30), 0);
+        assert_eq!(function.u16(0x1E), 1 << 13);
+    }
+
+    #[test]
+    fn io_window_of_a_general_device() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(BRIDGE)
+            .header(0x8086, 0x1234, [0x02, 0x00, 0x00], 0x0);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert_eq!(function.io_window(), None);
+        assert_eq!(
+            function.set_io_window(0x2000..0x3000),
+            Err(IoWindowError::NotABridge)
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Helpers for computing the I/O and memory windows of PCI-to-PCI bridges, for when you assign resources yourself instead of using what the firmware assigned.
+//!
+//! Build a [`WindowRequirements`] for each bridge from the BARs behind it (and the requirements of bridges behind it), and then use [`WindowRequirements::fit`] to get the windows and the address of every BAR.
+use core::ops::Range;
+
+use super::*;
+
+/// I/O windows have a 4 KiB granularity
+const IO_GRANULARITY: u64 = 0x1000;
+/// Memory windows have a 1 MiB granularity
+const MEMORY_GRANULARITY: u64 = 0x10_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WindowKind {
+    Io,
+    /// The non-prefetchable memory window, which is always below 4 GiB
+    Memory,
+    /// The prefetchable memory window, which can be 64-bit.
+    /// Only 64-bit prefetchable BARs are placed in it. 32-bit prefetchable BARs go in [`Self::Memory`], because the prefetchable window can be above 4 GiB.
+    PrefetchableMemory,
+}
+
+impl WindowKind {
+    const ALL: [Self; 3] = [Self::Io, Self::Memory, Self::PrefetchableMemory];
+
+    fn granularity(self) -> u64 {
+        match self {
+            Self::Io => IO_GRANULARITY,
+            Self::Memory | Self::PrefetchableMemory => MEMORY_GRANULARITY,
+        }
+    }
+
+    /// The end of the highest window of this kind that a bridge can have
+    fn limit(self) -> u64 {
+        match self {
+            Self::Io | Self::Memory => 1 << 32,
+            // The end of the range must fit in a `u64`
+            Self::PrefetchableMemory => u64::MAX / MEMORY_GRANULARITY * MEMORY_GRANULARITY,
+        }
+    }
+
+    /// Which window a BAR goes in, and its size. Returns `None` for invalid and unknown BARs.
+    fn of_bar(bar: &BarWithSize) -> Option<(Self, u64)> {
+        let (kind, size) = match bar {
+            BarWithSize::Io(io_bar_info) => (Self::Io, io_bar_info.size as u64),
+            BarWithSize::Memory(memory_bar_info) => (
+                match memory_bar_info.addr_and_size {
+                    MemoryBarAddrAndSize::U64(_) if memory_bar_info.prefetchable => {
+                        Self::PrefetchableMemory
+                    }
+                    _ => Self::Memory,
+                },
+                memory_bar_info.addr_and_size.size_u64(),
+            ),
+            BarWithSize::Invalid(_) | BarWithSize::Unknown { .. } => return None,
+        };
+        (size != 0).then_some((kind, size))
+    }
+}
+
+/// The size and alignment that one window needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct WindowRequirement {
+    /// The total size of everything in the window, where each item is padded to a multiple of its alignment
+    sum: u64,
+    max_alignment: u64,
+}
+
+impl WindowRequirement {
+    /// Sizes that don't fit in a `u64` saturate, so that [`WindowRequirements::fit`] reports them as overflowing
+    fn add(&mut self, size: u64, alignment: u64) {
+        self.sum = self
+            .sum
+            .saturating_add(size.checked_next_multiple_of(alignment).unwrap_or(u64::MAX));
+        self.max_alignment = self.max_alignment.max(alignment);
+    }
+
+    /// The size of the window, rounded up to the window granularity. This is 0 if nothing is in the window.
+    pub fn size(&self, kind: WindowKind) -> u64 {
+        self.sum
+            .checked_next_multiple_of(kind.granularity())
+            .unwrap_or(u64::MAX)
+    }
+
+    /// The alignment that the start of the window needs
+    pub fn alignment(&self, kind: WindowKind) -> u64 {
+        self.max_alignment.max(kind.granularity())
+    }
+}
+
+/// The windows that a bridge needs, accumulated from the BARs behind it.
+///
+/// Everything placed in a window is naturally aligned. Items are placed from the biggest alignment to the smallest, so BARs don't need any padding between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct WindowRequirements {
+    io: WindowRequirement,
+    memory: WindowRequirement,
+    prefetchable_memory: WindowRequirement,
+}
+
+/// The base addresses of each window, or the resulting windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct WindowBases {
+    pub io: u64,
+    pub memory: u64,
+    pub prefetchable_memory: u64,
+}
+
+impl WindowBases {
+    fn get(&self, kind: WindowKind) -> u64 {
+        match kind {
+            WindowKind::Io => self.io,
+            WindowKind::Memory => self.memory,
+            WindowKind::PrefetchableMemory => self.prefetchable_memory,
+        }
+    }
+
+    fn get_mut(&mut self, kind: WindowKind) -> &mut u64 {
+        match kind {
+            WindowKind::Io => &mut self.io,
+            WindowKind::Memory => &mut self.memory,
+            WindowKind::PrefetchableMemory => &mut self.prefetchable_memory,
+        }
+    }
+}
+
+/// A window didn't fit below the highest address that it can have.
+/// The I/O and non-prefetchable memory windows must be below 4 GiB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WindowOverflow {
+    pub kind: WindowKind,
+}
+
+/// The windows that [`WindowRequirements::fit`] planned. A window with nothing in it is an empty range.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BridgeWindows {
+    pub io: Range<u64>,
+    pub memory: Range<u64>,
+    pub prefetchable_memory: Range<u64>,
+}
+
+impl WindowRequirements {
+    pub fn requirement(&self, kind: WindowKind) -> WindowRequirement {
+        match kind {
+            WindowKind::Io => self.io,
+            WindowKind::Memory => self.memory,
+            WindowKind::PrefetchableMemory => self.prefetchable_memory,
+        }
+    }
+
+    fn requirement_mut(&mut self, kind: WindowKind) -> &mut WindowRequirement {
+        match kind {
+            WindowKind::Io => &mut self.io,
+            WindowKind::Memory => &mut self.memory,
+            WindowKind::PrefetchableMemory => &mut self.prefetchable_memory,
+        }
+    }
+
+    /// Add a BAR of a function that is directly behind the bridge
+    pub fn add_bar(&mut self, bar: &BarWithSize) {
+        if let Some((kind, size)) = WindowKind::of_bar(bar) {
+            // BAR sizes are powers of 2, and BARs must be aligned to their size
+            self.requirement_mut(kind).add(size, size);
+        }
+    }
+
+    /// Add the windows of a bridge that is behind this bridge
+    pub fn merge(&mut self, child: &WindowRequirements) {
+        for kind in WindowKind::ALL {
+            let requirement = child.requirement(kind);
+            let size = requirement.size(kind);
+            if size != 0 {
+                self.requirement_mut(kind)
+                    .add(size, requirement.alignment(kind));
+            }
+        }
+    }
+
+    /// Plans the windows and assigns an address to each BAR and child bridge.
+    /// `bars` and `children` must be the same BARs and child requirements that were added to `self`.
+    /// Each base is rounded up to the alignment that the window needs.
+    ///
+    /// The address of each BAR is written to the same index in `bar_addrs`, and the window bases of each child to the same index in `child_bases`.
+    /// Invalid and unknown BARs are not assigned an address.
+    /// Returns an error if a window doesn't fit below the highest address that it can have.
+    ///
+    /// # Panics
+    /// Panics if the output slices are not the same length as the input slices.
+    pub fn fit(
+        &self,
+        bases: WindowBases,
+        bars: &[BarWithSize],
+        children: &[WindowRequirements],
+        bar_addrs: &mut [u64],
+        child_bases: &mut [WindowBases],
+    ) -> Result<BridgeWindows, WindowOverflow> {
+        assert_eq!(
+            bars.len(),
+            bar_addrs.len(),
+            "Every BAR needs an output address"
+        );
+        assert_eq!(
+            children.len(),
+            child_bases.len(),
+            "Every child needs an output base"
+        );
+        let mut windows = BridgeWindows::default();
+        for kind in WindowKind::ALL {
+            let requirement = self.requirement(kind);
+            let overflow = WindowOverflow { kind };
+            let start = bases
+                .get(kind)
+                .checked_next_multiple_of(requirement.alignment(kind))
+                .ok_or(overflow)?;
+            let size = requirement.size(kind);
+            let end = start
+                .checked_add(size)
+                .filter(|end| size == 0 || *end <= kind.limit())
+                .ok_or(overflow)?;
+            let mut next = start;
+            for alignment in (0..u64::BITS).rev().map(|bit| 1 << bit) {
+                for (bar, bar_addr) in bars.iter().zip(bar_addrs.iter_mut()) {
+                    if WindowKind::of_bar(bar) == Some((kind, alignment)) {
+                        *bar_addr = next;
+                        next += alignment;
+                    }
+                }
+                for (child, child_base) in children.iter().zip(child_bases.iter_mut()) {
+                    let child_requirement = child.requirement(kind);
+                    let size = child_requirement.size(kind);
+                    if size != 0 && child_requirement.alignment(kind) == alignment {
+                        *child_base.get_mut(kind) = next;
+                        next += size.next_multiple_of(alignment);
+                    }
+                }
+            }
+            let range = start..end;
+            match kind {
+                WindowKind::Io => windows.io = range,
+                WindowKind::Memory => windows.memory = range,
+                WindowKind::PrefetchableMemory => windows.prefetchable_memory = range,
+            }
+        }
+        Ok(windows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_bar(size: u64, prefetchable: bool, is_64_bit: bool) -> BarWithSize {
+        BarWithSize::Memory(MemoryBarInfo {
+            addr_and_size: if is_64_bit {
+                MemoryBarAddrAndSize::U64(MemoryBarAddrAndSizeU64 {
+                    addr: 0,
+                    size,
+                    addressable_bits: 64,
+                })
+            } else {
+                MemoryBarAddrAndSize::U32(MemoryBarAddrAndSizeU32 {
+                    addr: 0,
+                    size: size as u32,
+                })
+            },
+            kind: if is_64_bit {
+                MemoryBarKind::Bit64
+            } else {
+                MemoryBarKind::Bit32
+            },
+            prefetchable,
+        })
+    }
+
+    fn io_bar(size: u32) -> BarWithSize {
+        BarWithSize::Io(IoBarInfo { addr: 0, size })
+    }
+
+    fn requirements(bars: &[BarWithSize]) -> WindowRequirements {
+        let mut requirements = WindowRequirements::default();
+        for bar in bars {
+            requirements.add_bar(bar);
+        }
+        requirements
+    }
+
+    #[test]
+    fn packs_from_the_biggest_alignment() {
+        let bars = [
+            memory_bar(0x1000, false, false),
+            memory_bar(0x10_0000, false, false),
+            memory_bar(0x4000, false, true),
+            memory_bar(0x1000, false, false),
+            io_bar(0x20),
+            io_bar(0x100),
+        ];
+        let mut child = WindowRequirements::default();
+        child.add_bar(&memory_bar(0x8_0000, false, false));
+        let mut requirements = requirements(&bars);
+        requirements.merge(&child);
+        let mut bar_addrs = [0; 6];
+        let mut child_bases = [WindowBases::default()];
+        let windows = requirements
+            .fit(
+                WindowBases {
+                    io: 0x1000,
+                    memory: 0xE000_0000,
+                    prefetchable_memory: 0,
+                },
+                &bars,
+                &[child],
+                &mut bar_addrs,
+                &mut child_bases,
+            )
+            .unwrap();
+        // The child's window is rounded up to 1 MiB, so it is placed with the biggest BAR
+        assert_eq!(
+            bar_addrs,
+            [
+                0xE020_4000,
+                0xE000_0000,
+                0xE020_0000,
+                0xE020_5000,
+                0x1100,
+                0x1000
+            ]
+        );
+        assert_eq!(child_bases[0].memory, 0xE010_0000);
+        assert_eq!(
+            windows,
+            BridgeWindows {
+                io: 0x1000..0x2000,
+                memory: 0xE000_0000..0xE030_0000,
+                prefetchable_memory: 0..0,
+            }
+        );
+    }
+
+    #[test]
+    fn rounds_the_base_up_to_the_alignment() {
+        let bars = [memory_bar(0x40_0000, false, false)];
+        let mut bar_addrs = [0; 1];
+        let windows = requirements(&bars)
+            .fit(
+                WindowBases {
+                    memory: 0xE010_0000,
+                    ..Default::default()
+                },
+                &bars,
+                &[],
+                &mut bar_addrs,
+                &mut [],
+            )
+            .unwrap();
+        assert_eq!(bar_addrs, [0xE040_0000]);
+        assert_eq!(windows.memory, 0xE040_0000..0xE080_0000);
+    }
+
+    #[test]
+    fn mixed_32_and_64_bit_prefetchable_bars() {
+        let bars = [
+            memory_bar(0x1000_0000, true, true),
+            memory_bar(0x10_0000, true, false),
+            memory_bar(0x10_0000, false, false),
+        ];
+        let mut bar_addrs = [0; 3];
+        let windows = requirements(&bars)
+            .fit(
+                WindowBases {
+                    io: 0,
+                    memory: 0xE000_0000,
+                    prefetchable_memory: 0x40_0000_0000,
+                },
+                &bars,
+                &[],
+                &mut bar_addrs,
+                &mut [],
+            )
+            .unwrap();
+        // The 32-bit prefetchable BAR can't go above 4 GiB, so it goes in the non-prefetchable window
+        assert_eq!(bar_addrs, [0x40_0000_0000, 0xE000_0000, 0xE010_0000]);
+        assert_eq!(windows.memory, 0xE000_0000..0xE020_0000);
+        assert_eq!(windows.prefetchable_memory, 0x40_0000_0000..0x40_1000_0000);
+    }
+
+    #[test]
+    fn memory_window_above_4_gib() {
+        let bars = [memory_bar(0x8000_0000, false, false)];
+        let mut bar_addrs = [0; 1];
+        assert_eq!(
+            requirements(&bars).fit(
+                WindowBases {
+                    memory: 0xC000_0000,
+                    ..Default::default()
+                },
+                &bars,
+                &[],
+                &mut bar_addrs,
+                &mut [],
+            ),
+            Err(WindowOverflow {
+                kind: WindowKind::Memory
+            })
+        );
+    }
+
+    #[test]
+    fn prefetchable_window_past_the_end_of_the_address_space() {
+        let bars = [
+            memory_bar(1 << 63, true, true),
+            memory_bar(1 << 63, true, true),
+        ];
+        let mut bar_addrs = [0; 2];
+        assert_eq!(
+            requirements(&bars).fit(WindowBases::default(), &bars, &[], &mut bar_addrs, &mut [],),
+            Err(WindowOverflow {
+                kind: WindowKind::PrefetchableMemory
+            })
+        );
+        let bars = [memory_bar(0x1000, true, true)];
+        assert_eq!(
+            requirements(&bars).fit(
+                WindowBases {
+                    prefetchable_memory: u64::MAX - 0x1000,
+                    ..Default::default()
+                },
+                &bars,
+                &[],
+                &mut bar_addrs[..1],
+                &mut [],
+            ),
+            Err(WindowOverflow {
+                kind: WindowKind::PrefetchableMemory
+            })
+        );
+    }
+
+    #[test]
+    fn empty_window_above_4_gib() {
+        let bars = [io_bar(0x100)];
+        let mut bar_addrs = [0; 1];
+        let windows = requirements(&bars)
+            .fit(
+                WindowBases {
+                    io: 0x1000,
+                    memory: 0x1_0000_0000,
+                    prefetchable_memory: 0,
+                },
+                &bars,
+                &[],
+                &mut bar_addrs,
+                &mut [],
+            )
+            .unwrap();
+        assert!(windows.memory.is_empty());
+    }
+}
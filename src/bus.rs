@@ -1,28 +1,145 @@
+use core::{fmt::Debug, ops::Range};
+
 use super::*;
 
 pub struct PciBus<'a> {
     pub(super) pci: &'a mut PciAccess,
+    pub(super) segment: u16,
     pub(super) bus_number: u8,
 }
 
+/// Returns whether the device is multi-function, or `None` if the device is not present
+fn probe_device(pci: &mut PciAccess, address: PciAddress) -> Option<bool> {
+    let vendor_id = pci.read_u32(address, 0x0) as u16;
+    if vendor_id != u16::MAX {
+        Some(HeaderTypeByte((pci.read_u32(address, 0xC) >> 16) as u8).multi_function())
+    } else {
+        None
+    }
+}
+
+impl Debug for PciBus<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PciBus")
+            .field(
+                "location",
+                &format_args!(
+                    "{}",
+                    BusLocation {
+                        segment: self.segment,
+                        bus: self.bus_number,
+                    }
+                ),
+            )
+            .finish()
+    }
+}
+
+impl<'a> PciBus<'a> {
+    pub(super) fn into_device(self, device_number: u8) -> Option<PciDevice<'a>> {
+        let address = PciAddress::new(self.segment, self.bus_number, device_number, 0);
+        let multi_function = probe_device(self.pci, address)?;
+        Some(PciDevice {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number: self.bus_number,
+            device_number,
+            multi_function,
+        })
+    }
+}
+
 impl PciBus<'_> {
+    #[must_use]
+    pub fn number(&self) -> u8 {
+        self.bus_number
+    }
+
+    /// A bitmap of the device numbers that respond, where bit N is device N.
+    /// This only reads the vendor ID of each device, so it is cheap to compare before and after a hotplug event.
+    #[must_use]
+    pub fn present_devices(&mut self) -> u32 {
+        (0..32).fold(0, |bitmap, device_number| {
+            let address = PciAddress::new(self.segment, self.bus_number, device_number, 0);
+            if self.pci.read_u16(address, 0x0) != u16::MAX {
+                bitmap | 1 << device_number
+            } else {
+                bitmap
+            }
+        })
+    }
+
+    /// Whether no devices respond on this bus
+    #[must_use]
+    pub fn is_empty(&mut self) -> bool {
+        self.present_devices() == 0
+    }
+
     pub fn device(&mut self, device_number: u8) -> Option<PciDevice> {
-        assert!((0..32).contains(&device_number));
-        let vendor_id = self.pci.read_u32(self.bus_number, device_number, 0, 0x0) as u16;
-        if vendor_id != u16::MAX {
-            let multi_function = HeaderTypeByte(
-                (self.pci.read_u32(self.bus_number, device_number, 0, 0xC) >> 16) as u8,
-            )
-            .multi_function();
-            let pci_device = PciDevice {
-                pci: self.pci,
-                bus_number: self.bus_number,
-                device_number,
-                multi_function,
-            };
-            Some(pci_device)
-        } else {
-            None
+        let address = PciAddress::new(self.segment, self.bus_number, device_number, 0);
+        let multi_function = probe_device(self.pci, address)?;
+        Some(PciDevice {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number: self.bus_number,
+            device_number,
+            multi_function,
+        })
+    }
+
+    /// Iterates through the devices that are present, skipping device numbers that have no device
+    pub fn devices(&mut self) -> PciDevices {
+        PciDevices {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number: self.bus_number,
+            devices: 0..32,
+        }
+    }
+}
+
+/// A lending iterator over the devices on a bus. Use it with `while let Some(device) = devices.next()`.
+pub struct PciDevices<'a> {
+    pci: &'a mut PciAccess,
+    segment: u16,
+    bus_number: u8,
+    devices: Range<u8>,
+}
+
+impl PciDevices<'_> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<PciDevice> {
+        let (device_number, multi_function) = self.devices.by_ref().find_map(|device_number| {
+            let address = PciAddress::new(self.segment, self.bus_number, device_number, 0);
+            probe_device(self.pci, address).map(|multi_function| (device_number, multi_function))
+        })?;
+        Some(PciDevice {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number: self.bus_number,
+            device_number,
+            multi_function,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_devices_reads_one_vendor_id_per_slot() {
+        let mut mock = MockConfig::new(AccessKind::Ecam).with_ecam_buses(0..=1);
+        for device_number in [0, 3, 31] {
+            mock.function(PciAddress::new(0, 1, device_number, 0))
+                .header(0x8086, 0x1234, [0x02, 0x00, 0x00], 0x0);
         }
+        let mut pci = mock.into_pci_access();
+        let mut bus = pci.bus(1);
+        assert_eq!(bus.number(), 1);
+        assert_eq!(bus.present_devices(), 1 << 0 | 1 << 3 | 1 << 31);
+        assert_eq!(pci.mock().read_count(), 32);
+        assert!(!pci.bus(1).is_empty());
+        assert!(pci.bus(0).is_empty());
     }
 }
@@ -1,39 +1,215 @@
+use core::fmt::Debug;
+
 use super::*;
 
-pub struct Capabilities<'a> {
-    pub(super) pci: &'a mut PciAccess,
-    pub(super) bus_number: u8,
-    pub(super) device_number: u8,
-    pub(super) function_number: u8,
-    pub(super) ptr: u8,
+/// A broken capability list could loop forever. There can't be more than this many capabilities after the 64 byte header.
+const MAX_CAPABILITIES: u8 = ((0x100 - 0x40) / 4) as u8;
+
+/// The state of walking the capabilities list, shared by [`Capabilities`] and [`ConfigSpaceImage::capabilities`].
+/// The walk stops early if the list is malformed, for example if a pointer points inside the standard header.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct CapabilityWalk {
+    ptr: u8,
+    remaining: u8,
 }
 
-impl Iterator for Capabilities<'_> {
-    type Item = Capability;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.ptr == 0 {
+impl CapabilityWalk {
+    pub(super) fn new(ptr: u8) -> Self {
+        Self {
+            ptr,
+            remaining: MAX_CAPABILITIES,
+        }
+    }
+
+    /// `read_u32` reads the register at the offset
+    pub(super) fn next(&mut self, read_u32: impl FnOnce(u8) -> u32) -> Option<Capability> {
+        // The bottom 2 bits are reserved
+        self.ptr &= !0b11;
+        // Capabilities can't be inside the standard header
+        if self.ptr < 0x40 || self.remaining == 0 {
+            self.ptr = 0;
+            return None;
+        }
+        self.remaining -= 1;
+        let reg = read_u32(self.ptr);
+        if reg == u32::MAX {
+            // The device was removed, so there is no valid capability here
+            self.ptr = 0;
             return None;
         }
-        let reg = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr,
-        );
         let capability = Capability {
             ptr_to_self: self.ptr,
             id: reg as u8,
-            next_ptr: (reg << 8) as u8,
+            next_ptr: (reg >> 8) as u8,
         };
         self.ptr = capability.next_ptr;
         Some(capability)
     }
 }
 
-#[derive(Debug)]
+/// Iterates through the capabilities list.
+/// The iteration stops early if the list is malformed, for example if a pointer points inside the standard header.
+pub struct Capabilities<'a> {
+    pci: &'a mut PciAccess,
+    address: PciAddress,
+    walk: CapabilityWalk,
+}
+
+impl<'a> Capabilities<'a> {
+    pub(super) fn new(pci: &'a mut PciAccess, address: PciAddress, ptr: u8) -> Self {
+        Self {
+            pci,
+            address,
+            walk: CapabilityWalk::new(ptr),
+        }
+    }
+}
+
+impl Capabilities<'_> {
+    /// Read the contents of a capability that was returned by this iterator
+    pub fn reader(&mut self, capability: &Capability) -> CapabilityReader {
+        CapabilityReader {
+            pci: self.pci,
+            address: self.address,
+            ptr: capability.ptr_to_self.into(),
+        }
+    }
+}
+
+impl Debug for Capabilities<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Capabilities")
+            .field("address", &format_args!("{}", self.address))
+            .finish()
+    }
+}
+
+impl Iterator for Capabilities<'_> {
+    type Item = Capability;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk
+            .next(|ptr| self.pci.read_u32(self.address, ptr.into()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Capability {
     pub ptr_to_self: u8,
     pub id: u8,
     /// The offset in the function's memory where the next capability is
     pub next_ptr: u8,
 }
+
+/// Reads registers relative to the start of a capability.
+/// This is useful for capabilities that this crate doesn't know about, such as vendor-specific capabilities (ID `0x09`).
+pub struct CapabilityReader<'a> {
+    pub(super) pci: &'a mut PciAccess,
+    pub(super) address: PciAddress,
+    pub(super) ptr: u16,
+}
+
+impl CapabilityReader<'_> {
+    pub fn read_u32_at(&mut self, offset_from_cap: u8) -> u32 {
+        self.pci
+            .read_u32(self.address, self.ptr + u16::from(offset_from_cap))
+    }
+
+    pub fn read_u16_at(&mut self, offset_from_cap: u8) -> u16 {
+        self.pci
+            .read_u16(self.address, self.ptr + u16::from(offset_from_cap))
+    }
+
+    /// For vendor-specific capabilities (ID `0x09`), the byte at offset `0x2` is the length of the capability, including the header.
+    pub fn vendor_specific_len(&mut self) -> u8 {
+        self.read_u16_at(0x2) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 2,
+        function: 0,
+    };
+
+    /// A function whose capabilities pointer is `ptr`
+    fn mock_list(ptr: u8, f: impl FnOnce(&mut MockFunction)) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        f(mock
+            .function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .set_u16(0x6, 1 << 4)
+            .set_u8(0x34, ptr));
+        mock.into_pci_access()
+    }
+
+    fn ids(pci: &mut PciAccess) -> Vec<u8> {
+        let mut function = pci.function_at(0, 2, 0).unwrap();
+        function
+            .capabilities()
+            .unwrap()
+            .map(|capability| capability.id)
+            .collect()
+    }
+
+    fn assert_no_msi(pci: &mut PciAccess) {
+        let mut function = pci.function_at(0, 2, 0).unwrap();
+        assert!(function.msi().unwrap().is_none());
+        assert!(function.msi_x().unwrap().is_none());
+    }
+
+    #[test]
+    fn list() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .capability(0x40, 0x1, &[0; 6])
+            .capability(0x50, 0x5, &[0; 10]);
+        let mut pci = mock.into_pci_access();
+        assert_eq!(ids(&mut pci), [0x1, 0x5]);
+    }
+
+    #[test]
+    fn cyclic_list_stops() {
+        let mut pci = mock_list(0x40, |function| {
+            // 0x40 -> 0x50 -> 0x40
+            function
+                .set_bytes(0x40, &[0x1, 0x50])
+                .set_bytes(0x50, &[0x10, 0x40]);
+        });
+        assert_eq!(ids(&mut pci).len(), MAX_CAPABILITIES.into());
+        assert_no_msi(&mut pci);
+    }
+
+    #[test]
+    fn pointer_into_the_header() {
+        for ptr in [0x03, 0x20] {
+            let mut pci = mock_list(ptr, |_| {});
+            assert_eq!(ids(&mut pci), []);
+            assert_no_msi(&mut pci);
+            // Nothing in the header is read as a capability
+            assert!(
+                pci.mock()
+                    .log()
+                    .iter()
+                    .all(|access| access.register_offset <= 0x34)
+            );
+        }
+    }
+
+    #[test]
+    fn riser_returning_all_ones() {
+        // The pointer reads as 0xFF, which is unaligned, and the register it points to reads as all ones
+        let mut pci = mock_list(0xFF, |function| {
+            function.set_u32(0xFC, u32::MAX);
+        });
+        assert_eq!(ids(&mut pci), []);
+        assert_no_msi(&mut pci);
+    }
+}
@@ -0,0 +1,493 @@
+use core::fmt::{self, Display};
+
+use num_enum::FromPrimitive;
+
+use super::*;
+
+/// The class code of a function. See the PCI Code and ID Assignment Specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum PciClass {
+    Unclassified = 0x00,
+    MassStorage = 0x01,
+    Network = 0x02,
+    Display = 0x03,
+    Multimedia = 0x04,
+    Memory = 0x05,
+    Bridge = 0x06,
+    SimpleCommunication = 0x07,
+    BaseSystemPeripheral = 0x08,
+    InputDevice = 0x09,
+    DockingStation = 0x0A,
+    Processor = 0x0B,
+    SerialBus = 0x0C,
+    Wireless = 0x0D,
+    IntelligentIo = 0x0E,
+    SatelliteCommunication = 0x0F,
+    Encryption = 0x10,
+    SignalProcessing = 0x11,
+    ProcessingAccelerator = 0x12,
+    NonEssentialInstrumentation = 0x13,
+    Coprocessor = 0x40,
+    // This has to come before `Unassigned`, so that its discriminant doesn't overflow
+    #[num_enum(catch_all)]
+    Unknown(u8),
+    Unassigned = 0xFF,
+}
+
+impl PciClass {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Unclassified => "Unclassified",
+            Self::MassStorage => "Mass Storage Controller",
+            Self::Network => "Network Controller",
+            Self::Display => "Display Controller",
+            Self::Multimedia => "Multimedia Controller",
+            Self::Memory => "Memory Controller",
+            Self::Bridge => "Bridge",
+            Self::SimpleCommunication => "Simple Communication Controller",
+            Self::BaseSystemPeripheral => "Base System Peripheral",
+            Self::InputDevice => "Input Device Controller",
+            Self::DockingStation => "Docking Station",
+            Self::Processor => "Processor",
+            Self::SerialBus => "Serial Bus Controller",
+            Self::Wireless => "Wireless Controller",
+            Self::IntelligentIo => "Intelligent I/O Controller",
+            Self::SatelliteCommunication => "Satellite Communication Controller",
+            Self::Encryption => "Encryption Controller",
+            Self::SignalProcessing => "Signal Processing Controller",
+            Self::ProcessingAccelerator => "Processing Accelerator",
+            Self::NonEssentialInstrumentation => "Non-Essential Instrumentation",
+            Self::Coprocessor => "Co-Processor",
+            Self::Unassigned => "Unassigned Class",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The sub class of [`PciClass::MassStorage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum MassStorageSubClass {
+    Scsi = 0x00,
+    Ide = 0x01,
+    Floppy = 0x02,
+    IpiBus = 0x03,
+    Raid = 0x04,
+    Ata = 0x05,
+    Sata = 0x06,
+    Sas = 0x07,
+    NonVolatileMemory = 0x08,
+    Ufs = 0x09,
+    Other = 0x80,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl MassStorageSubClass {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Scsi => "SCSI",
+            Self::Ide => "IDE",
+            Self::Floppy => "Floppy Disk",
+            Self::IpiBus => "IPI Bus",
+            Self::Raid => "RAID",
+            Self::Ata => "ATA",
+            Self::Sata => "SATA",
+            Self::Sas => "Serial Attached SCSI",
+            Self::NonVolatileMemory => "Non-Volatile Memory (NVMe)",
+            Self::Ufs => "UFS",
+            Self::Other => "Other",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The sub class of [`PciClass::Network`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum NetworkSubClass {
+    Ethernet = 0x00,
+    TokenRing = 0x01,
+    Fddi = 0x02,
+    Atm = 0x03,
+    Isdn = 0x04,
+    WorldFip = 0x05,
+    Picmg = 0x06,
+    Infiniband = 0x07,
+    Fabric = 0x08,
+    Other = 0x80,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl NetworkSubClass {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Ethernet => "Ethernet",
+            Self::TokenRing => "Token Ring",
+            Self::Fddi => "FDDI",
+            Self::Atm => "ATM",
+            Self::Isdn => "ISDN",
+            Self::WorldFip => "WorldFip",
+            Self::Picmg => "PICMG 2.14 Multi Computing",
+            Self::Infiniband => "InfiniBand",
+            Self::Fabric => "Fabric",
+            Self::Other => "Other",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The sub class of [`PciClass::Display`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum DisplaySubClass {
+    Vga = 0x00,
+    Xga = 0x01,
+    ThreeD = 0x02,
+    Other = 0x80,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl DisplaySubClass {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Vga => "VGA Compatible",
+            Self::Xga => "XGA",
+            Self::ThreeD => "3D",
+            Self::Other => "Other",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The sub class of [`PciClass::Bridge`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum BridgeSubClass {
+    Host = 0x00,
+    Isa = 0x01,
+    Eisa = 0x02,
+    Mca = 0x03,
+    PciToPci = 0x04,
+    Pcmcia = 0x05,
+    NuBus = 0x06,
+    CardBus = 0x07,
+    RaceWay = 0x08,
+    SemiTransparentPciToPci = 0x09,
+    InfiniBandToPci = 0x0A,
+    Other = 0x80,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl BridgeSubClass {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Host => "Host",
+            Self::Isa => "ISA",
+            Self::Eisa => "EISA",
+            Self::Mca => "MCA",
+            Self::PciToPci => "PCI-to-PCI",
+            Self::Pcmcia => "PCMCIA",
+            Self::NuBus => "NuBus",
+            Self::CardBus => "CardBus",
+            Self::RaceWay => "RACEway",
+            Self::SemiTransparentPciToPci => "Semi-Transparent PCI-to-PCI",
+            Self::InfiniBandToPci => "InfiniBand-to-PCI",
+            Self::Other => "Other",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The sub class of [`PciClass::SerialBus`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum SerialBusSubClass {
+    FireWire = 0x00,
+    AccessBus = 0x01,
+    Ssa = 0x02,
+    Usb = 0x03,
+    FibreChannel = 0x04,
+    SmBus = 0x05,
+    InfiniBand = 0x06,
+    Ipmi = 0x07,
+    Sercos = 0x08,
+    CanBus = 0x09,
+    Other = 0x80,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl SerialBusSubClass {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::FireWire => "FireWire (IEEE 1394)",
+            Self::AccessBus => "ACCESS Bus",
+            Self::Ssa => "SSA",
+            Self::Usb => "USB",
+            Self::FibreChannel => "Fibre Channel",
+            Self::SmBus => "SMBus",
+            Self::InfiniBand => "InfiniBand",
+            Self::Ipmi => "IPMI",
+            Self::Sercos => "SERCOS (IEC 61491)",
+            Self::CanBus => "CANbus",
+            Self::Other => "Other",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The programming interface of [`SerialBusSubClass::Usb`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum UsbController {
+    Uhci = 0x00,
+    Ohci = 0x10,
+    Ehci = 0x20,
+    Xhci = 0x30,
+    Usb4 = 0x40,
+    Unspecified = 0x80,
+    Device = 0xFE,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl UsbController {
+    /// A human-readable name. Returns `None` for [`Self::Unknown`].
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Uhci => "UHCI",
+            Self::Ohci => "OHCI",
+            Self::Ehci => "EHCI",
+            Self::Xhci => "xHCI",
+            Self::Usb4 => "USB4 Host Interface",
+            Self::Unspecified => "Unspecified",
+            Self::Device => "USB Device",
+            Self::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The decoded sub class. Classes that don't have a sub class enum yet are [`SubClass::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubClass {
+    MassStorage(MassStorageSubClass),
+    Network(NetworkSubClass),
+    Display(DisplaySubClass),
+    Bridge(BridgeSubClass),
+    SerialBus(SerialBusSubClass),
+    Other(u8),
+}
+
+impl SubClass {
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::MassStorage(sub_class) => sub_class.name(),
+            Self::Network(sub_class) => sub_class.name(),
+            Self::Display(sub_class) => sub_class.name(),
+            Self::Bridge(sub_class) => sub_class.name(),
+            Self::SerialBus(sub_class) => sub_class.name(),
+            Self::Other(_) => None,
+        }
+    }
+}
+
+/// The class code, sub class, and programming interface of a function.
+/// The raw values are kept, so unknown codes can still be matched and printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClassInfo {
+    pub class_code: u8,
+    pub sub_class: u8,
+    pub prog_if: u8,
+}
+
+impl ClassInfo {
+    pub fn class(&self) -> PciClass {
+        self.class_code.into()
+    }
+
+    pub fn sub_class(&self) -> SubClass {
+        match self.class() {
+            PciClass::MassStorage => SubClass::MassStorage(self.sub_class.into()),
+            PciClass::Network => SubClass::Network(self.sub_class.into()),
+            PciClass::Display => SubClass::Display(self.sub_class.into()),
+            PciClass::Bridge => SubClass::Bridge(self.sub_class.into()),
+            PciClass::SerialBus => SubClass::SerialBus(self.sub_class.into()),
+            _ => SubClass::Other(self.sub_class),
+        }
+    }
+
+    /// Returns `None` if the function is not a USB controller
+    pub fn usb_controller(&self) -> Option<UsbController> {
+        match self.sub_class() {
+            SubClass::SerialBus(SerialBusSubClass::Usb) => Some(self.prog_if.into()),
+            _ => None,
+        }
+    }
+
+    /// Returns `None` if the function is not one of the controllers in [`ControllerKind`]
+    pub fn controller_kind(&self) -> Option<ControllerKind> {
+        Some(match (self.sub_class(), self.prog_if) {
+            (SubClass::MassStorage(MassStorageSubClass::Ide), _) => ControllerKind::Ide,
+            (SubClass::MassStorage(MassStorageSubClass::Sata), 0x01) => ControllerKind::SataAhci,
+            (SubClass::MassStorage(MassStorageSubClass::NonVolatileMemory), 0x02) => {
+                ControllerKind::NvmExpress
+            }
+            (SubClass::SerialBus(SerialBusSubClass::Usb), _) => match self.usb_controller()? {
+                UsbController::Uhci => ControllerKind::UsbUhci,
+                UsbController::Ohci => ControllerKind::UsbOhci,
+                UsbController::Ehci => ControllerKind::UsbEhci,
+                UsbController::Xhci => ControllerKind::UsbXhci,
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Common controllers that are identified by their class code, sub class, and programming interface together.
+/// Drivers for these work with any vendor's controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControllerKind {
+    /// Any programming interface of [`MassStorageSubClass::Ide`]
+    Ide,
+    SataAhci,
+    NvmExpress,
+    UsbUhci,
+    UsbOhci,
+    UsbEhci,
+    UsbXhci,
+}
+
+/// Prints human-readable names, for example `Serial Bus Controller: USB (xHCI)`
+impl Display for ClassInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.class().name() {
+            Some(name) => write!(f, "{name}")?,
+            None => write!(f, "Class 0x{:02X}", self.class_code)?,
+        }
+        match self.sub_class().name() {
+            Some(name) => write!(f, ": {name}")?,
+            None => write!(f, ": Sub class 0x{:02X}", self.sub_class)?,
+        }
+        match self.usb_controller() {
+            Some(usb_controller) => match usb_controller.name() {
+                Some(name) => write!(f, " ({name})"),
+                None => write!(f, " (Prog IF 0x{:02X})", self.prog_if),
+            },
+            None if self.prog_if != 0 => write!(f, " (Prog IF 0x{:02X})", self.prog_if),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PciFunction<'_> {
+    /// The class code, sub class, and programming interface, which you can decode or print
+    pub fn class(&mut self) -> ClassInfo {
+        ClassInfo {
+            class_code: self.class_code(),
+            sub_class: self.sub_class(),
+            prog_if: self.prog_if(),
+        }
+    }
+
+    /// Returns `None` if the function is not one of the controllers in [`ControllerKind`]
+    pub fn controller_kind(&mut self) -> Option<ControllerKind> {
+        self.class().controller_kind()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+
+    use super::*;
+
+    fn class(class_code: u8, sub_class: u8, prog_if: u8) -> ClassInfo {
+        ClassInfo {
+            class_code,
+            sub_class,
+            prog_if,
+        }
+    }
+
+    #[test]
+    fn class_codes() {
+        assert_eq!(PciClass::from(0x01), PciClass::MassStorage);
+        assert_eq!(PciClass::from(0x40), PciClass::Coprocessor);
+        assert_eq!(PciClass::from(0xFF), PciClass::Unassigned);
+        assert_eq!(PciClass::from(0x41), PciClass::Unknown(0x41));
+        assert_eq!(PciClass::Unknown(0x41).name(), None);
+        assert_eq!(PciClass::Unassigned.name(), Some("Unassigned Class"));
+    }
+
+    #[test]
+    fn sub_classes() {
+        assert_eq!(
+            class(0x01, 0x08, 0x02).sub_class(),
+            SubClass::MassStorage(MassStorageSubClass::NonVolatileMemory)
+        );
+        assert_eq!(
+            class(0x06, 0x42, 0x00).sub_class(),
+            SubClass::Bridge(BridgeSubClass::Unknown(0x42))
+        );
+        // Classes without a sub class enum keep the raw value
+        assert_eq!(class(0x04, 0x03, 0x00).sub_class(), SubClass::Other(0x03));
+        assert_eq!(class(0x04, 0x03, 0x00).sub_class().name(), None);
+    }
+
+    #[test]
+    fn controller_kinds() {
+        let cases = [
+            (class(0x01, 0x01, 0x8A), Some(ControllerKind::Ide)),
+            (class(0x01, 0x06, 0x01), Some(ControllerKind::SataAhci)),
+            (class(0x01, 0x08, 0x02), Some(ControllerKind::NvmExpress)),
+            (class(0x0C, 0x03, 0x00), Some(ControllerKind::UsbUhci)),
+            (class(0x0C, 0x03, 0x10), Some(ControllerKind::UsbOhci)),
+            (class(0x0C, 0x03, 0x20), Some(ControllerKind::UsbEhci)),
+            (class(0x0C, 0x03, 0x30), Some(ControllerKind::UsbXhci)),
+            // Vendor-specific SATA, NVMHCI, a USB device, and a network controller
+            (class(0x01, 0x06, 0x00), None),
+            (class(0x01, 0x08, 0x01), None),
+            (class(0x0C, 0x03, 0xFE), None),
+            (class(0x02, 0x00, 0x00), None),
+        ];
+        for (class, controller_kind) in cases {
+            assert_eq!(class.controller_kind(), controller_kind, "{class:?}");
+        }
+    }
+
+    #[test]
+    fn formatting() {
+        let cases = [
+            (class(0x0C, 0x03, 0x30), "Serial Bus Controller: USB (xHCI)"),
+            (class(0x02, 0x00, 0x00), "Network Controller: Ethernet"),
+            (
+                class(0x01, 0x06, 0x01),
+                "Mass Storage Controller: SATA (Prog IF 0x01)",
+            ),
+            (
+                class(0x0C, 0x03, 0x42),
+                "Serial Bus Controller: USB (Prog IF 0x42)",
+            ),
+            (
+                class(0x04, 0x03, 0x00),
+                "Multimedia Controller: Sub class 0x03",
+            ),
+            (class(0x41, 0x02, 0x00), "Class 0x41: Sub class 0x02"),
+        ];
+        for (class, formatted) in cases {
+            assert_eq!(format!("{class}"), formatted);
+        }
+    }
+}
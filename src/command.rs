@@ -1,18 +1,121 @@
 use bitfield::bitfield;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 bitfield! {
+    #[derive(Clone, Copy)]
     pub struct CommandRegister(u16);
+    impl Debug;
 
     pub io_space, set_io_space: 0;
     pub memory_space, set_memory_space: 1;
     pub bus_master, set_bus_master: 2;
     pub special_cycles, _: 3;
-    pub memory_write_and_invalidate_enable, _: 4;
-    pub vga_palette_snoop, _: 5;
+    pub memory_write_and_invalidate_enable, set_memory_write_and_invalidate_enable: 4;
+    pub vga_palette_snoop, set_vga_palette_snoop: 5;
     pub parity_error_response, set_parity_error_response: 6;
     // bit 7 is reserved
     pub serr_enable, set_serr_enable: 8;
-    pub fast_back_to_back_enable, _: 9;
+    pub fast_back_to_back_enable, set_fast_back_to_back_enable: 9;
     pub interrupt_disable, set_interrupt_disable: 10;
     // bits 11..=15 are reserved
 }
+
+impl CommandRegister {
+    /// Bits 7 and 11-15 are reserved
+    pub const RESERVED_BITS: u16 = 1 << 7 | 0xF800;
+}
+
+/// The defined bits of [`CommandRegister`], for accessing them by name with [`CommandRegister::get`] and [`CommandRegister::set`].
+/// Convert from and to the bit index with [`TryFrom<u8>`] and [`Into<u8>`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum CommandBit {
+    IoSpace = 0,
+    MemorySpace = 1,
+    BusMaster = 2,
+    SpecialCycles = 3,
+    MemoryWriteAndInvalidateEnable = 4,
+    VgaPaletteSnoop = 5,
+    ParityErrorResponse = 6,
+    SerrEnable = 8,
+    FastBackToBackEnable = 9,
+    InterruptDisable = 10,
+}
+
+impl CommandBit {
+    pub const ALL: [Self; 10] = [
+        Self::IoSpace,
+        Self::MemorySpace,
+        Self::BusMaster,
+        Self::SpecialCycles,
+        Self::MemoryWriteAndInvalidateEnable,
+        Self::VgaPaletteSnoop,
+        Self::ParityErrorResponse,
+        Self::SerrEnable,
+        Self::FastBackToBackEnable,
+        Self::InterruptDisable,
+    ];
+}
+
+impl CommandRegister {
+    pub fn get(&self, bit: CommandBit) -> bool {
+        self.0 & (1 << u8::from(bit)) != 0
+    }
+
+    pub fn set(&mut self, bit: CommandBit, value: bool) {
+        let mask = 1 << u8::from(bit);
+        if value {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
+}
+
+/// Builder-style setters, so you can write `CommandRegister(0).with_memory_space(true).with_bus_master(true)`.
+impl CommandRegister {
+    pub fn with_io_space(mut self, value: bool) -> Self {
+        self.set_io_space(value);
+        self
+    }
+
+    pub fn with_memory_space(mut self, value: bool) -> Self {
+        self.set_memory_space(value);
+        self
+    }
+
+    pub fn with_bus_master(mut self, value: bool) -> Self {
+        self.set_bus_master(value);
+        self
+    }
+
+    pub fn with_memory_write_and_invalidate_enable(mut self, value: bool) -> Self {
+        self.set_memory_write_and_invalidate_enable(value);
+        self
+    }
+
+    pub fn with_vga_palette_snoop(mut self, value: bool) -> Self {
+        self.set_vga_palette_snoop(value);
+        self
+    }
+
+    pub fn with_parity_error_response(mut self, value: bool) -> Self {
+        self.set_parity_error_response(value);
+        self
+    }
+
+    pub fn with_serr_enable(mut self, value: bool) -> Self {
+        self.set_serr_enable(value);
+        self
+    }
+
+    pub fn with_fast_back_to_back_enable(mut self, value: bool) -> Self {
+        self.set_fast_back_to_back_enable(value);
+        self
+    }
+
+    pub fn with_interrupt_disable(mut self, value: bool) -> Self {
+        self.set_interrupt_disable(value);
+        self
+    }
+}
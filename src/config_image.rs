@@ -0,0 +1,240 @@
+use super::*;
+
+/// A copy of a function's config space as bytes, for example from [`PciFunction::dump_config`].
+/// This decodes the same registers as [`PciFunction`], but offline and read-only, so you can analyze dumps from other machines.
+///
+/// Reads past the end of the bytes return all 1s, like reads of a function that isn't present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConfigSpaceImage<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ConfigSpaceImage<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn read<const N: usize>(&self, register_offset: u16) -> [u8; N] {
+        let start = register_offset as usize;
+        self.bytes
+            .get(start..start + N)
+            .map_or([u8::MAX; N], |bytes| bytes.try_into().unwrap())
+    }
+
+    #[must_use]
+    pub fn read_u32(&self, register_offset: u16) -> u32 {
+        u32::from_le_bytes(self.read(register_offset))
+    }
+
+    #[must_use]
+    pub fn read_u16(&self, register_offset: u16) -> u16 {
+        u16::from_le_bytes(self.read(register_offset))
+    }
+
+    #[must_use]
+    pub fn read_u8(&self, register_offset: u16) -> u8 {
+        u8::from_le_bytes(self.read(register_offset))
+    }
+
+    #[must_use]
+    pub fn vendor_id(&self) -> u16 {
+        self.read_u16(0x0)
+    }
+
+    #[must_use]
+    pub fn device_id(&self) -> u16 {
+        self.read_u16(0x2)
+    }
+
+    #[must_use]
+    pub fn command(&self) -> CommandRegister {
+        CommandRegister(self.read_u16(0x4))
+    }
+
+    #[must_use]
+    pub fn status(&self) -> StatusRegister {
+        StatusRegister(self.read_u16(0x6))
+    }
+
+    #[must_use]
+    pub fn class(&self) -> ClassInfo {
+        ClassInfo {
+            class_code: self.read_u8(0xB),
+            sub_class: self.read_u8(0xA),
+            prog_if: self.read_u8(0x9),
+        }
+    }
+
+    #[must_use]
+    pub fn header_type_byte(&self) -> HeaderTypeByte {
+        HeaderTypeByte(self.read_u8(0xE))
+    }
+
+    #[must_use]
+    pub fn header_type(&self) -> HeaderType {
+        self.header_type_byte().header_type().into()
+    }
+
+    /// The raw value of a BAR register. BARs can't be sized from an image, since sizing needs writes.
+    ///
+    /// Returns `None` if the header type is unknown or the BAR index is out of range
+    #[must_use]
+    pub fn raw_bar(&self, bar_index: u8) -> Option<u32> {
+        if bar_index >= self.header_type().bar_count()? {
+            return None;
+        }
+        Some(self.read_u32(0x10 + size_of::<u32>() as u16 * bar_index as u16))
+    }
+
+    /// Walks the capabilities list, with the same protections against malformed lists as [`PciFunction::capabilities`].
+    ///
+    /// Returns `None` if the header type is unknown
+    pub fn capabilities(&self) -> Option<impl Iterator<Item = Capability> + use<'a>> {
+        let register_offset = self.header_type().capabilities_ptr_offset()?;
+        let ptr = if self.status().capabilities_list() {
+            self.read_u8(register_offset.into())
+        } else {
+            0
+        };
+        let image = *self;
+        let mut walk = CapabilityWalk::new(ptr);
+        Some(core::iter::from_fn(move || {
+            walk.next(|ptr| image.read_u32(ptr.into()))
+        }))
+    }
+
+    /// See [`PciFunction::find_capability`]
+    #[must_use]
+    pub fn find_capability(&self, id: u8) -> Option<Option<Capability>> {
+        Some(self.capabilities()?.find(|capability| capability.id == id))
+    }
+
+    /// The MSI-X message control register, or `None` if the function doesn't have MSI-X
+    #[must_use]
+    pub fn msi_x_message_control(&self) -> Option<MsiXMessageControl> {
+        let capability = self.find_capability(0x11)??;
+        Some(MsiXMessageControl(
+            self.read_u16(u16::from(capability.ptr_to_self) + 0x2),
+        ))
+    }
+
+    /// The MSI message control register, or `None` if the function doesn't have MSI
+    #[must_use]
+    pub fn msi_message_control(&self) -> Option<MessageControlRegister> {
+        let capability = self.find_capability(0x5)??;
+        Some(MessageControlRegister(
+            self.read_u16(u16::from(capability.ptr_to_self) + 0x2),
+        ))
+    }
+}
+
+impl PciFunction<'_> {
+    /// Copies the config space into `out`, reading 1 `u32` at a time.
+    /// This copies up to [`AccessKind::config_space_len`] bytes, rounded down to a multiple of 4.
+    /// If [`Self::has_extended_config`] is `false`, only the first 256 bytes are copied.
+    /// Use [`ConfigSpaceImage`] to decode the bytes.
+    ///
+    /// Returns the number of bytes that were copied, or an error if the function is not present.
+    pub fn dump_config(&mut self, out: &mut [u8]) -> Result<usize, DeviceGone> {
+        if !self.is_present() {
+            return Err(DeviceGone {
+                address: self.address,
+            });
+        }
+        let config_space_len = if self.has_extended_config() {
+            self.pci.access_kind().config_space_len()
+        } else {
+            0x100
+        };
+        let len = out.len().min(config_space_len);
+        let len = len - len % size_of::<u32>();
+        for (i, chunk) in out[..len].chunks_exact_mut(size_of::<u32>()).enumerate() {
+            let register_offset = (i * size_of::<u32>()) as u16;
+            chunk.copy_from_slice(
+                &self
+                    .pci
+                    .read_u32(self.address, register_offset)
+                    .to_le_bytes(),
+            );
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 7,
+        function: 0,
+    };
+
+    #[test]
+    fn decode_a_dump() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        let mut msi_x = [0; 10];
+        msi_x[0..2].copy_from_slice(&(0x8000u16 | 15).to_le_bytes());
+        mock.function(ADDRESS)
+            .header(0x8086, 0x10D3, [0x02, 0x00, 0x00], 0x0)
+            .memory_bar_32(0, 0xFEB0_0000, 0x2_0000, false)
+            .io_bar(2, 0xE000, 0x20)
+            .set_u16(0x4, 0x0406)
+            .capability(0x50, 0x5, &[0x80, 0x00])
+            .capability(0xA0, 0x11, &msi_x);
+        let mut pci = mock.into_pci_access();
+        let mut bytes = [0; 0x1000];
+        let mut function = pci.function_at(0, 7, 0).unwrap();
+        // The function isn't PCIe, so it has no extended config space
+        assert_eq!(function.dump_config(&mut bytes), Ok(0x100));
+        assert_eq!(pci.mock().writes().count(), 0);
+
+        let image = ConfigSpaceImage::new(&bytes);
+        assert_eq!((image.vendor_id(), image.device_id()), (0x8086, 0x10D3));
+        assert_eq!(image.command().0, 0x0406);
+        assert_eq!(
+            image.class(),
+            ClassInfo {
+                class_code: 0x02,
+                sub_class: 0x00,
+                prog_if: 0x00,
+            }
+        );
+        assert_eq!(image.header_type(), HeaderType::GeneralDevice);
+        assert_eq!(image.raw_bar(0), Some(0xFEB0_0000));
+        assert_eq!(image.raw_bar(2), Some(0xE001));
+        assert_eq!(image.raw_bar(6), None);
+        assert!(
+            image
+                .capabilities()
+                .unwrap()
+                .map(|capability| (capability.ptr_to_self, capability.id))
+                .eq([(0x50, 0x5), (0xA0, 0x11)])
+        );
+        assert!(
+            image
+                .msi_message_control()
+                .unwrap()
+                .supports_64_bit_addresses()
+        );
+        let msi_x_message_control = image.msi_x_message_control().unwrap();
+        assert!(msi_x_message_control.enable());
+        assert_eq!(msi_x_message_control.table_size(), 16);
+    }
+
+    #[test]
+    fn truncated_image_reads_all_ones() {
+        let bytes = [0x86, 0x80, 0xD3, 0x10, 0x06];
+        let image = ConfigSpaceImage::new(&bytes);
+        assert_eq!(image.vendor_id(), 0x8086);
+        assert_eq!(image.read_u16(0x4), u16::MAX);
+        assert_eq!(image.read_u32(0x4), u32::MAX);
+        assert_eq!(image.header_type_byte().0, u8::MAX);
+    }
+}
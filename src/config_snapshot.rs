@@ -0,0 +1,281 @@
+use super::*;
+
+/// A saved copy of a function's config space, which can be restored after S3 suspend or a reset.
+/// This does not borrow [`PciAccess`], so it can be stored anywhere.
+///
+/// The MSI-X table and pending bit array are in a BAR, not in config space, so they are not saved.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigSnapshot {
+    /// The first 64 bytes of config space
+    header: [u32; 16],
+    msi: Option<MsiConfigSnapshot>,
+    msi_x: Option<MsiXConfigSnapshot>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MsiConfigSnapshot {
+    ptr: u16,
+    message_control: u16,
+    message_addr_low: u32,
+    /// Only if 64-bit addresses are supported
+    message_addr_high: Option<u32>,
+    message_data: u16,
+    /// Only if per-message masking is supported
+    mask_bits: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MsiXConfigSnapshot {
+    ptr: u16,
+    message_control: u16,
+}
+
+impl PciFunction<'_> {
+    /// Saves the standard header and the MSI and MSI-X capabilities
+    pub fn save_config(&mut self) -> ConfigSnapshot {
+        let mut header = [0; 16];
+        for (i, dword) in header.iter_mut().enumerate() {
+            *dword = self
+                .pci
+                .read_u32(self.address, (i * size_of::<u32>()) as u16);
+        }
+        let msi = self
+            .find_capability_ptr(0x5)
+            .map(|ptr| self.save_msi_config(ptr));
+        let msi_x = self
+            .find_capability_ptr(0x11)
+            .map(|ptr| MsiXConfigSnapshot {
+                ptr,
+                message_control: self.pci.read_u16(self.address, ptr + 0x2),
+            });
+        ConfigSnapshot { header, msi, msi_x }
+    }
+
+    /// Restores config space in an order that is safe for the device:
+    /// 1. Memory space, I/O space, and bus mastering are disabled
+    /// 2. The BARs and the rest of the header are restored
+    /// 3. The MSI address, data, and mask are restored before MSI is re-enabled
+    /// 4. MSI-X message control is restored
+    /// 5. The command register is restored, re-enabling decoding and bus mastering
+    ///
+    /// The status register is not written, because its bits are cleared by writing 1s to them.
+    /// For the same reason, the secondary status register of a PCI-to-PCI bridge is not written.
+    /// The BIST register is not written, because writing to it can start a self-test.
+    /// A bridge's [`BridgeControl`] is restored with [`BridgeControl::secondary_bus_reset`] cleared, so that restoring doesn't reset the secondary bus.
+    pub fn restore_config(&mut self, snapshot: &ConfigSnapshot) {
+        let saved_command = CommandRegister(snapshot.header[1] as u16);
+        let mut command = self.command();
+        command.set_io_space(false);
+        command.set_memory_space(false);
+        command.set_bus_master(false);
+        self.set_command(command);
+
+        // BARs, expansion ROM base address, and interrupt line
+        let is_bridge = self.header_type() == HeaderType::PciToPciBridge;
+        for (i, &value) in snapshot.header.iter().enumerate().skip(4) {
+            let register_offset = (i * size_of::<u32>()) as u16;
+            if is_bridge && matches!(register_offset, 0x1C | 0x3C) {
+                // The upper halves are the secondary status and bridge control registers
+                if self.pci.read_u16(self.address, register_offset) != value as u16 {
+                    self.pci
+                        .write_u16(self.address, register_offset, value as u16);
+                }
+            } else if self.pci.read_u32(self.address, register_offset) != value {
+                self.pci.write_u32(self.address, register_offset, value);
+            }
+        }
+        if is_bridge {
+            let mut bridge_control = BridgeControl((snapshot.header[15] >> 16) as u16);
+            bridge_control.set_secondary_bus_reset(false);
+            // Discard Timer Status is cleared by writing 1 to it
+            bridge_control.0 &= !(1 << 10);
+            self.pci.write_u16(self.address, 0x3E, bridge_control.0);
+        }
+        // Cache line size and latency timer
+        self.pci
+            .write_u16(self.address, 0xC, snapshot.header[3] as u16);
+
+        if let Some(msi) = snapshot.msi {
+            self.restore_msi_config(&msi);
+        }
+        if let Some(msi_x) = snapshot.msi_x {
+            self.pci
+                .write_u16(self.address, msi_x.ptr + 0x2, msi_x.message_control);
+        }
+
+        self.set_command(saved_command);
+    }
+
+    fn find_capability_ptr(&mut self, id: u8) -> Option<u16> {
+        self.find_capability(id)?
+            .map(|capability| capability.ptr_to_self.into())
+    }
+
+    fn save_msi_config(&mut self, ptr: u16) -> MsiConfigSnapshot {
+        let message_control = self.pci.read_u16(self.address, ptr + 0x2);
+        let control = MessageControlRegister(message_control);
+        let message_addr_low = self.pci.read_u32(self.address, ptr + 0x4);
+        let (message_addr_high, data_offset) = if control.supports_64_bit_addresses() {
+            (Some(self.pci.read_u32(self.address, ptr + 0x8)), 0xC)
+        } else {
+            (None, 0x8)
+        };
+        let message_data = self.pci.read_u16(self.address, ptr + data_offset);
+        let mask_bits = control
+            .per_message_masking()
+            .then(|| self.pci.read_u32(self.address, ptr + data_offset + 0x4));
+        MsiConfigSnapshot {
+            ptr,
+            message_control,
+            message_addr_low,
+            message_addr_high,
+            message_data,
+            mask_bits,
+        }
+    }
+
+    fn restore_msi_config(&mut self, msi: &MsiConfigSnapshot) {
+        let mut control = MessageControlRegister(msi.message_control);
+        let enable = control.enable();
+        control.set_enable(false);
+        self.pci.write_u16(self.address, msi.ptr + 0x2, control.0);
+        self.pci
+            .write_u32(self.address, msi.ptr + 0x4, msi.message_addr_low);
+        let data_offset = match msi.message_addr_high {
+            Some(message_addr_high) => {
+                self.pci
+                    .write_u32(self.address, msi.ptr + 0x8, message_addr_high);
+                0xC
+            }
+            None => 0x8,
+        };
+        self.pci
+            .write_u16(self.address, msi.ptr + data_offset, msi.message_data);
+        if let Some(mask_bits) = msi.mask_bits {
+            self.pci
+                .write_u32(self.address, msi.ptr + data_offset + 0x4, mask_bits);
+        }
+        control.set_enable(enable);
+        self.pci.write_u16(self.address, msi.ptr + 0x2, control.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 6,
+        function: 0,
+    };
+
+    const BRIDGE: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 1,
+        function: 0,
+    };
+
+    /// The offset and value of each write
+    fn writes(pci: &mut PciAccess) -> Vec<(u16, u64)> {
+        pci.mock()
+            .writes()
+            .map(|access| (access.register_offset, access.value))
+            .collect()
+    }
+
+    #[test]
+    fn restore_order() {
+        let mut msi = [0; 12];
+        // 64-bit addresses, enabled
+        msi[0..2].copy_from_slice(&0x0081u16.to_le_bytes());
+        msi[2..6].copy_from_slice(&0xFEE0_0000u32.to_le_bytes());
+        msi[10..12].copy_from_slice(&0x0041u16.to_le_bytes());
+        let mut msi_x = [0; 10];
+        msi_x[0..2].copy_from_slice(&(0x8000u16 | 3).to_le_bytes());
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x8086, 0x10D3, [0x02, 0x00, 0x00], 0x0)
+            .memory_bar_32(0, 0xFEB0_0000, 0x2_0000, false)
+            .set_u16(0x4, 0x0406)
+            .set_u8(0xC, 0x10)
+            .set_u8(0x3C, 0x0B)
+            .capability(0x50, 0x5, &msi)
+            .capability(0xA0, 0x11, &msi_x);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 6, 0).unwrap();
+        let snapshot = function.save_config();
+
+        // A reset clears everything
+        let mock_function = pci.mock().function(ADDRESS);
+        for (register_offset, len) in [(0x4, 2), (0xC, 1), (0x10, 4), (0x3C, 1), (0x52, 2)] {
+            mock_function.set_bytes(register_offset, &[0; 4][..len]);
+        }
+        mock_function.set_bytes(0x54, &[0; 10]).set_u16(0xA2, 3);
+        pci.mock().clear_log();
+
+        let mut function = pci.function_at(0, 6, 0).unwrap();
+        function.restore_config(&snapshot);
+        assert_eq!(
+            writes(&mut pci),
+            [
+                // Decoding and bus mastering off
+                (0x4, 0x0000),
+                // BAR, interrupt line, and cache line size
+                (0x10, 0xFEB0_0000),
+                (0x3C, 0x0000_000B),
+                (0xC, 0x0010),
+                // MSI is disabled until its address and data are written
+                (0x52, 0x0080),
+                (0x54, 0xFEE0_0000),
+                (0x58, 0x0),
+                (0x5C, 0x0041),
+                (0x52, 0x0081),
+                (0xA2, 0x8003),
+                // Decoding and bus mastering back on
+                (0x4, 0x0406),
+            ]
+        );
+        let function = pci.mock().function(ADDRESS);
+        assert_eq!(function.u32(0x10), 0xFEB0_0000);
+        assert_eq!(function.u32(0x54), 0xFEE0_0000);
+        assert_eq!(function.u16(0x5C), 0x0041);
+        assert_eq!(function.u16(0xA2), 0x8003);
+    }
+
+    #[test]
+    fn restore_bridge() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(BRIDGE)
+            .header(0x8086, 0x7C90, [0x06, 0x04, 0x00], 0x1)
+            .set_bytes(0x18, &[0, 1, 1, 0])
+            // I/O window, and a Secondary Status with an error latched
+            .set_bytes(0x1C, &[0x20, 0x30])
+            .set_u16(0x1E, 1 << 13)
+            .write_1_to_clear_u16(0x1E, StatusRegister::ERROR_BITS)
+            // VGA enable, and Secondary Bus Reset, which was in progress when the config was saved
+            .set_u16(0x3E, 1 << 3 | 1 << 6);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        let snapshot = function.save_config();
+
+        pci.mock()
+            .function(BRIDGE)
+            .set_bytes(0x18, &[0; 6])
+            .set_u16(0x3E, 0);
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        function.restore_config(&snapshot);
+        assert!(!pci.mock().wrote_to(BRIDGE, 0x1E));
+        assert!(writes(&mut pci).contains(&(0x1C, 0x3020)));
+        let function = pci.mock().function(BRIDGE);
+        assert_eq!(function.bytes()[0x18..0x1C], [0, 1, 1, 0]);
+        assert_eq!(function.bytes()[0x1C..0x1E], [0x20, 0x30]);
+        // The error bit is still latched
+        assert_eq!(function.u16(0x1E), 1 << 13);
+        assert_eq!(function.u16(0x3E), 1 << 3);
+    }
+}
@@ -1,33 +1,117 @@
-use core::ops::RangeInclusive;
+use core::{fmt::Debug, ops::RangeInclusive};
 
 use super::*;
 
 pub struct PciDevice<'a> {
     pub(super) pci: &'a mut PciAccess,
+    pub(super) segment: u16,
     pub(super) bus_number: u8,
     pub(super) device_number: u8,
     pub(super) multi_function: bool,
 }
 
+impl Debug for PciDevice<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PciDevice")
+            .field(
+                "location",
+                &format_args!(
+                    "{}",
+                    DeviceLocation {
+                        segment: self.segment,
+                        bus: self.bus_number,
+                        device: self.device_number,
+                    }
+                ),
+            )
+            .field("multi_function", &self.multi_function)
+            .finish()
+    }
+}
+
 impl PciDevice<'_> {
+    /// If this is `false`, the device only has function 0
+    pub fn is_multi_function(&self) -> bool {
+        self.multi_function
+    }
+
+    /// The header type of function 0
+    pub fn header_type(&mut self) -> HeaderType {
+        let address = PciAddress::new(self.segment, self.bus_number, self.device_number, 0);
+        HeaderTypeByte(self.pci.read_u16(address, 0xE) as u8)
+            .header_type()
+            .into()
+    }
+
     pub fn possible_functions(&self) -> RangeInclusive<u8> {
         if self.multi_function { 0..=7 } else { 0..=0 }
     }
 
+    /// Iterates through the functions that are present, skipping function numbers that have no function
+    pub fn functions(&mut self) -> PciFunctions {
+        let functions = self.possible_functions();
+        PciFunctions {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number: self.bus_number,
+            device_number: self.device_number,
+            functions,
+        }
+    }
+
+    /// Like [`Self::functions`], but checks all 8 function numbers, ignoring the multi-function bit.
+    /// Use this for devices that implement functions other than function 0 without setting the multi-function bit.
+    pub fn probe_all_functions(&mut self) -> PciFunctions {
+        PciFunctions {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number: self.bus_number,
+            device_number: self.device_number,
+            functions: 0..=7,
+        }
+    }
+
     pub fn function(&mut self, function_number: u8) -> Option<PciFunction> {
-        assert!((0..=7).contains(&function_number));
-        let vendor_id =
-            self.pci
-                .read_u16(self.bus_number, self.device_number, function_number, 0x0);
-        if vendor_id != u16::MAX {
-            Some(PciFunction {
-                pci: self.pci,
-                bus_number: self.bus_number,
-                device_number: self.device_number,
+        let address = PciAddress::new(
+            self.segment,
+            self.bus_number,
+            self.device_number,
+            function_number,
+        );
+        let identity = FunctionIdentity::read(self.pci, address)?;
+        Some(PciFunction {
+            pci: self.pci,
+            address,
+            identity,
+        })
+    }
+}
+
+/// A lending iterator over the functions of a device. Use it with `while let Some(function) = functions.next()`.
+pub struct PciFunctions<'a> {
+    pci: &'a mut PciAccess,
+    segment: u16,
+    bus_number: u8,
+    device_number: u8,
+    functions: RangeInclusive<u8>,
+}
+
+impl PciFunctions<'_> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<PciFunction> {
+        let (address, identity) = self.functions.by_ref().find_map(|function_number| {
+            let address = PciAddress::new(
+                self.segment,
+                self.bus_number,
+                self.device_number,
                 function_number,
-            })
-        } else {
-            None
-        }
+            );
+            FunctionIdentity::read(self.pci, address).map(|identity| (address, identity))
+        })?;
+        Some(PciFunction {
+            pci: self.pci,
+            address,
+            identity,
+        })
     }
 }
@@ -0,0 +1,41 @@
+use super::*;
+
+/// The DMA-related settings of a function, gathered from the command register, MSI, MSI-X, and PCIe capabilities.
+/// See [`PciFunction::dma_summary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DmaSummary {
+    pub bus_master_enabled: bool,
+    /// `None` if the function doesn't have the MSI capability
+    pub msi_64bit_capable: Option<bool>,
+    pub msix_present: bool,
+    /// In bytes. `None` if the function is not PCIe.
+    pub max_payload_size: Option<u16>,
+    /// In bytes. `None` if the function is not PCIe.
+    pub max_read_request_size: Option<u16>,
+}
+
+impl PciFunction<'_> {
+    /// Reads everything that you need to know before programming the function for DMA
+    #[must_use]
+    pub fn dma_summary(&mut self) -> DmaSummary {
+        let msi_64bit_capable = self
+            .msi()
+            .flatten()
+            .map(|msi| msi.get_message_control().supports_64_bit_addresses());
+        let msix_present = self.msi_x().flatten().is_some();
+        let (max_payload_size, max_read_request_size) = match self.pci_express().flatten() {
+            Some(mut pci_express) => (
+                Some(pci_express.max_payload_size()),
+                Some(pci_express.max_read_request_size()),
+            ),
+            None => (None, None),
+        };
+        DmaSummary {
+            bus_master_enabled: self.command().bus_master(),
+            msi_64bit_capable,
+            msix_present,
+            max_payload_size,
+            max_read_request_size,
+        }
+    }
+}
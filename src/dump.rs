@@ -0,0 +1,179 @@
+//! Writes everything this crate knows how to decode, like `lspci -vv`.
+//! This doesn't need `alloc`. Everything is written directly to a [`Write`].
+use core::fmt::{self, Write};
+
+use super::*;
+
+fn capability_name(id: u8) -> &'static str {
+    match id {
+        0x01 => "Power Management",
+        0x02 => "AGP",
+        0x03 => "VPD",
+        0x04 => "Slot Identification",
+        0x05 => "MSI",
+        0x06 => "CompactPCI Hot Swap",
+        0x07 => "PCI-X",
+        0x08 => "HyperTransport",
+        0x09 => "Vendor Specific",
+        0x0A => "Debug Port",
+        0x0B => "CompactPCI Central Resource Control",
+        0x0C => "PCI Hot-Plug",
+        0x0D => "PCI Bridge Subsystem Vendor ID",
+        0x0E => "AGP 8x",
+        0x0F => "Secure Device",
+        0x10 => "PCI Express",
+        0x11 => "MSI-X",
+        0x12 => "SATA Data/Index Configuration",
+        0x13 => "Advanced Features",
+        0x14 => "Enhanced Allocation",
+        0x15 => "Flattening Portal Bridge",
+        _ => "Unknown",
+    }
+}
+
+/// Writes the address and type of each BAR that has an address.
+/// The BARs are not sized, because sizing a BAR writes to it. Use [`PciFunction::bars`] to get the sizes.
+fn dump_bars(function: &mut PciFunction, w: &mut impl Write) -> fmt::Result {
+    let max_bars = function.max_bars().unwrap_or_default();
+    let mut bar_index = 0;
+    while bar_index < max_bars {
+        let raw_addr = function.read_config_u32(0x10 + size_of::<u32>() as u16 * bar_index as u16);
+        let mut slots_len = 1;
+        if raw_addr & 0b1 != 0 {
+            writeln!(w, "  BAR{bar_index}: I/O ports at 0x{:X}", raw_addr & !0b11)?;
+        } else if raw_addr != 0 {
+            let kind = MemoryBarKind::from_type_bits(MemorySpaceBar(raw_addr)._type());
+            let mut addr = (raw_addr & !0b1111) as u64;
+            if kind == MemoryBarKind::Bit64 && bar_index + 1 < max_bars {
+                let next_raw_addr = function
+                    .read_config_u32(0x10 + size_of::<u32>() as u16 * (bar_index + 1) as u16);
+                addr |= (next_raw_addr as u64) << 32;
+                slots_len = 2;
+            }
+            writeln!(
+                w,
+                "  BAR{bar_index}: Memory at 0x{addr:X} ({}, {}prefetchable)",
+                match kind {
+                    MemoryBarKind::Bit32 => "32-bit",
+                    MemoryBarKind::Bit64 => "64-bit",
+                    MemoryBarKind::Below1MiB => "below 1 MiB",
+                    MemoryBarKind::Reserved(_) => "reserved type",
+                },
+                if MemorySpaceBar(raw_addr).prefetchable() {
+                    ""
+                } else {
+                    "non-"
+                },
+            )?;
+        }
+        bar_index += slots_len;
+    }
+    Ok(())
+}
+
+/// Writes the info of a single function.
+/// This only reads config space. BARs are written without their sizes, because sizing a BAR writes to it.
+pub fn dump_function(function: &mut PciFunction, w: &mut impl Write) -> fmt::Result {
+    writeln!(
+        w,
+        "{} {:04x}:{:04x} class {:02x}:{:02x}.{:02x}",
+        function.address(),
+        function.vendor_id(),
+        function.device_id(),
+        function.class_code(),
+        function.sub_class(),
+        function.prog_if(),
+    )?;
+    writeln!(w, "  Class: {}", function.class())?;
+    let header_type = function.header_type();
+    writeln!(w, "  Header type: {header_type:?}")?;
+    if let HeaderType::Unknown(_) = header_type {
+        return Ok(());
+    }
+
+    dump_bars(function, w)?;
+
+    if let Some(interrupt_info) = function.interrupt_info() {
+        writeln!(
+            w,
+            "  Interrupt: pin {} line {}",
+            interrupt_info.interrupt_pin, interrupt_info.interrupt_line
+        )?;
+    }
+
+    if let Some(capabilities) = function.capabilities() {
+        for capability in capabilities {
+            writeln!(
+                w,
+                "  Capability 0x{:02X} ({}) at 0x{:02X}",
+                capability.id,
+                capability_name(capability.id),
+                capability.ptr_to_self
+            )?;
+        }
+    }
+
+    if let Some(Some(msi)) = function.msi() {
+        let message_control = msi.get_message_control();
+        writeln!(
+            w,
+            "  MSI: enabled {}, 64-bit {}, per-vector masking {}, vectors capable {} enabled {}",
+            message_control.enable(),
+            message_control.supports_64_bit_addresses(),
+            message_control.per_message_masking(),
+            1 << message_control.multiple_message_capable(),
+            1 << message_control.multiple_message_enable(),
+        )?;
+    }
+
+    if let Some(Some(msi_x)) = function.msi_x() {
+        let message_control = msi_x.message_control();
+        let table_location = msi_x.table_location();
+        let pba_location = msi_x.pba_location();
+        writeln!(
+            w,
+            "  MSI-X: enabled {}, function masked {}, table size {}, table at BAR{} + 0x{:X}, PBA at BAR{} + 0x{:X}",
+            message_control.enable(),
+            message_control.function_mask(),
+            message_control.table_size(),
+            table_location.bar_index(),
+            table_location.offset_in_bar(),
+            pba_location.bar_index(),
+            pba_location.offset_in_bar(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes the info of every function that can be found. Like [`dump_function`], this only reads config space.
+pub fn dump_all(pci: &mut PciAccess, w: &mut impl Write) -> fmt::Result {
+    pci.try_for_each_function(|function, _| dump_function(function, w))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+
+    #[test]
+    fn dump_all_only_reads() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(PciAddress::new(0, 0, 2, 0))
+            .header(0x8086, 0x1234, [0x02, 0x00, 0x00], 0x0)
+            .memory_bar_32(0, 0xFEB0_0000, 0x2_0000, false)
+            .memory_bar_64(2, 0x80_0000_0000, 0x10_0000, true)
+            .io_bar(4, 0xC000, 0x20);
+        let mut pci = mock.into_pci_access();
+        let mut output = String::new();
+        dump_all(&mut pci, &mut output).unwrap();
+        assert_eq!(pci.mock().writes().count(), 0);
+        assert!(output.contains("0000:00:02.0 8086:1234 class 02:00.00"));
+        assert!(output.contains("BAR0: Memory at 0xFEB00000 (32-bit, non-prefetchable)"));
+        assert!(output.contains("BAR2: Memory at 0x8000000000 (64-bit, prefetchable)"));
+        assert!(!output.contains("BAR3"));
+        assert!(output.contains("BAR4: I/O ports at 0xC000"));
+        assert!(!output.contains("size"));
+    }
+}
@@ -0,0 +1,78 @@
+use core::ops::{Range, RangeInclusive};
+
+use super::*;
+
+/// The location of a PCIe ECAM region, and which segment group and buses it covers.
+/// On ACPI systems, this comes from an MCFG entry. On other systems, it can come from a devicetree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EcamWindow {
+    /// The physical address of bus 0, even if the window doesn't start at bus 0
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub bus_number_start: u8,
+    pub bus_number_end: u8,
+}
+
+impl EcamWindow {
+    pub fn buses(&self) -> RangeInclusive<u8> {
+        self.bus_number_start..=self.bus_number_end
+    }
+
+    /// The physical memory that you need to map before creating a [`PciAccess`]
+    pub fn phys_range(&self) -> Range<PhysAddr> {
+        self.phys_range_for_buses(self.buses())
+    }
+
+    /// A window with the same base address that only covers 1 bus
+    ///
+    /// # Panics
+    /// Panics if the bus is not within the window's bus range
+    pub fn single_bus(&self, bus: u8) -> Self {
+        let (bus_number_start, bus_number_end) = (self.bus_number_start, self.bus_number_end);
+        assert!(
+            self.buses().contains(&bus),
+            "Bus {bus} is not within the ECAM window's bus range {bus_number_start}..={bus_number_end}"
+        );
+        Self {
+            bus_number_start: bus,
+            bus_number_end: bus,
+            ..*self
+        }
+    }
+
+    /// The length of [`Self::phys_range`] in bytes. Each bus takes up 1 MiB.
+    pub fn byte_len(&self) -> usize {
+        self.buses().len() << 20
+    }
+
+    /// Like [`Self::phys_range`], but only for some of the buses in the window.
+    /// Each bus takes up 1 MiB, so this lets you avoid mapping buses that you don't need.
+    ///
+    /// # Panics
+    /// Panics if the buses are not within the window's bus range
+    pub fn phys_range_for_buses(&self, buses: RangeInclusive<u8>) -> Range<PhysAddr> {
+        let (bus_number_start, bus_number_end) = (self.bus_number_start, self.bus_number_end);
+        assert!(
+            !buses.is_empty()
+                && bus_number_start <= *buses.start()
+                && *buses.end() <= bus_number_end,
+            "Buses {buses:?} are not within the ECAM window's bus range {bus_number_start}..={bus_number_end}"
+        );
+        let n_buses = (buses.end() - buses.start()) as u64 + 1;
+        let start_addr = PhysAddr::new(self.base_address + ((*buses.start() as u64) << 20));
+        let len = n_buses * (1 << 20);
+        start_addr..start_addr + len
+    }
+}
+
+#[cfg(feature = "acpi")]
+impl From<McfgEntry> for EcamWindow {
+    fn from(mcfg_entry: McfgEntry) -> Self {
+        Self {
+            base_address: mcfg_entry.base_address,
+            segment_group: mcfg_entry.pci_segment_group,
+            bus_number_start: mcfg_entry.bus_number_start,
+            bus_number_end: mcfg_entry.bus_number_end,
+        }
+    }
+}
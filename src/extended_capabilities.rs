@@ -0,0 +1,252 @@
+use super::*;
+
+/// A broken extended capability list could loop forever. There can't be more than this many extended capabilities in the extended config space.
+const MAX_EXTENDED_CAPABILITIES: u16 = (0x1000 - 0x100) / 4;
+
+/// Iterates through the PCIe extended capabilities, which are in the extended config space (offset `0x100` and above).
+/// The iteration stops early if the list is malformed, for example if a pointer points below `0x100`.
+pub struct ExtendedCapabilities<'a> {
+    pub(super) pci: &'a mut PciAccess,
+    pub(super) address: PciAddress,
+    pub(super) ptr: u16,
+    pub(super) remaining: u16,
+}
+
+impl Iterator for ExtendedCapabilities<'_> {
+    type Item = ExtendedCapability;
+    fn next(&mut self) -> Option<Self::Item> {
+        // Extended capabilities can't be in the legacy config space
+        if self.ptr < 0x100 || self.remaining == 0 {
+            self.ptr = 0;
+            return None;
+        }
+        self.remaining -= 1;
+        let reg = self.pci.read_u32(self.address, self.ptr);
+        if reg == 0 || reg == u32::MAX {
+            // There are no extended capabilities, or the device was removed
+            self.ptr = 0;
+            return None;
+        }
+        let capability = ExtendedCapability {
+            ptr_to_self: self.ptr,
+            id: reg as u16,
+            version: ((reg >> 16) & 0xF) as u8,
+            // The bottom 2 bits are reserved
+            next_ptr: (reg >> 20) as u16 & !0b11,
+        };
+        self.ptr = capability.next_ptr;
+        Some(capability)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExtendedCapability {
+    pub ptr_to_self: u16,
+    pub id: u16,
+    pub version: u8,
+    /// The offset in the function's memory where the next extended capability is
+    pub next_ptr: u16,
+}
+
+/// Whether to access a function's extended config space. See [`PciAccess::with_extended_config_override`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ExtendedConfigPolicy {
+    /// Read the dword at `0x100` to check if the function has extended config space
+    #[default]
+    Probe,
+    /// Never access config space at `0x100` and above, for functions that misbehave when it is accessed
+    LegacyOnly,
+}
+
+impl PciFunction<'_> {
+    /// Whether the function has extended config space (offset `0x100` and above).
+    /// This is `false` with legacy PCI, if the policy from [`PciAccess::with_extended_config_override`] is [`ExtendedConfigPolicy::LegacyOnly`],
+    /// or if the dword at `0x100` is `0x0000_0000` or `0xFFFF_FFFF`, which is what some functions that only implement 256 bytes return.
+    #[must_use]
+    pub fn has_extended_config(&mut self) -> bool {
+        if self.pci.access_kind() == AccessKind::LegacyPortIo {
+            return false;
+        }
+        let (vendor_id, device_id) = (self.vendor_id(), self.device_id());
+        match self.pci.extended_config_policy(vendor_id, device_id) {
+            ExtendedConfigPolicy::Probe => {
+                let reg = self.pci.read_u32(self.address, 0x100);
+                reg != 0 && reg != u32::MAX
+            }
+            ExtendedConfigPolicy::LegacyOnly => false,
+        }
+    }
+
+    /// Returns `None` if extended config space is not accessible, which is the case for legacy PCI.
+    /// If [`Self::has_extended_config`] is `false`, this iterates through nothing, without accessing extended config space.
+    pub fn extended_capabilities(&mut self) -> Option<ExtendedCapabilities> {
+        match self.pci.access_kind() {
+            AccessKind::LegacyPortIo => None,
+            AccessKind::Ecam => {
+                let ptr = if self.has_extended_config() { 0x100 } else { 0 };
+                Some(ExtendedCapabilities {
+                    pci: self.pci,
+                    address: self.address,
+                    ptr,
+                    remaining: MAX_EXTENDED_CAPABILITIES,
+                })
+            }
+        }
+    }
+
+    /// The 64-bit serial number (EUI-64) from the Device Serial Number extended capability (ID `0x3`).
+    /// This is useful for telling apart devices that are otherwise identical.
+    ///
+    /// PCI Express Base Specification -> 7.9.3 Device Serial Number Extended Capability
+    ///
+    /// Returns `None` if the function doesn't have the capability, or if extended config space is not accessible
+    #[must_use]
+    pub fn device_serial_number(&mut self) -> Option<u64> {
+        let ptr = self
+            .extended_capabilities()?
+            .find(|capability| capability.id == 0x3)?
+            .ptr_to_self;
+        let low = self.pci.read_u32(self.address, ptr + 0x4);
+        let high = self.pci.read_u32(self.address, ptr + 0x8);
+        Some(u64::from(high) << 32 | u64::from(low))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 4,
+        function: 0,
+    };
+
+    const CAPTURE_CARD: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 5,
+        function: 0,
+    };
+
+    /// A function with a Device Serial Number extended capability, and a capture card that returns garbage at `0x100`
+    fn mock(kind: AccessKind) -> MockConfig {
+        let mut mock = MockConfig::new(kind);
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .extended_capability(0x100, 0x3, 1, &0x0011_2233_4455_6677u64.to_le_bytes());
+        mock.function(CAPTURE_CARD)
+            .header(0x1CD7, 0x0010, [0x04, 0x00, 0x00], 0x0)
+            .set_u32(0x100, 0x1234_0003);
+        mock
+    }
+
+    fn deny_capture_card(vendor_id: u16, device_id: u16) -> ExtendedConfigPolicy {
+        if (vendor_id, device_id) == (0x1CD7, 0x0010) {
+            ExtendedConfigPolicy::LegacyOnly
+        } else {
+            ExtendedConfigPolicy::Probe
+        }
+    }
+
+    fn extended_config_reads(pci: &mut PciAccess, address: PciAddress) -> usize {
+        pci.mock()
+            .log()
+            .iter()
+            .filter(|access| access.address == address && access.register_offset >= 0x100)
+            .count()
+    }
+
+    #[test]
+    fn probe_policy() {
+        let mut pci = mock(AccessKind::Ecam).into_pci_access();
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert!(function.has_extended_config());
+        let ids = function
+            .extended_capabilities()
+            .unwrap()
+            .map(|capability| capability.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [0x3]);
+        assert_eq!(function.device_serial_number(), Some(0x0011_2233_4455_6677));
+        // Without an override, the capture card's garbage is read as a capability
+        let mut capture_card = pci.function_at(0, 5, 0).unwrap();
+        assert!(capture_card.has_extended_config());
+    }
+
+    #[test]
+    fn legacy_only_policy() {
+        let mut pci = mock(AccessKind::Ecam)
+            .into_pci_access()
+            .with_extended_config_override(deny_capture_card);
+        let mut capture_card = pci.function_at(0, 5, 0).unwrap();
+        assert!(!capture_card.has_extended_config());
+        assert_eq!(capture_card.extended_capabilities().unwrap().count(), 0);
+        assert_eq!(capture_card.device_serial_number(), None);
+        assert_eq!(extended_config_reads(&mut pci, CAPTURE_CARD), 0);
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(function.device_serial_number(), Some(0x0011_2233_4455_6677));
+    }
+
+    #[test]
+    fn empty_extended_config() {
+        let mut mock = mock(AccessKind::Ecam);
+        mock.function(ADDRESS).set_u32(0x100, 0);
+        mock.function(CAPTURE_CARD).set_u32(0x100, u32::MAX);
+        let mut pci = mock.into_pci_access();
+        for device in [4, 5] {
+            let mut function = pci.function_at(0, device, 0).unwrap();
+            assert!(!function.has_extended_config());
+            assert_eq!(function.extended_capabilities().unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn legacy_pci_has_no_extended_config() {
+        let mut pci = mock(AccessKind::LegacyPortIo).into_pci_access();
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert!(!function.has_extended_config());
+        assert!(function.extended_capabilities().is_none());
+        assert_eq!(extended_config_reads(&mut pci, ADDRESS), 0);
+    }
+
+    #[test]
+    fn malformed_lists_stop() {
+        let mut mock = mock(AccessKind::Ecam);
+        // The serial number capability points back to itself
+        mock.function(ADDRESS).set_u32(0x100, 0x1001_0003);
+        // The next pointer points into the legacy config space
+        mock.function(CAPTURE_CARD).set_u32(0x100, 0x0401_0003);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 4, 0).unwrap();
+        assert_eq!(
+            function.extended_capabilities().unwrap().count(),
+            usize::from(MAX_EXTENDED_CAPABILITIES)
+        );
+        assert_eq!(function.device_serial_number(), Some(0x0011_2233_4455_6677));
+        assert_eq!(
+            function
+                .extended_capabilities()
+                .unwrap()
+                .find(|capability| capability.id == 0x10),
+            None
+        );
+        let mut capture_card = pci.function_at(0, 5, 0).unwrap();
+        let capabilities = capture_card
+            .extended_capabilities()
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            capabilities,
+            [ExtendedCapability {
+                ptr_to_self: 0x100,
+                id: 0x3,
+                version: 1,
+                next_ptr: 0x40,
+            }]
+        );
+    }
+}
@@ -1,203 +1,588 @@
+use core::fmt::{Debug, Display};
+
 use super::*;
 
-#[derive(Debug)]
 pub struct PciFunction<'a> {
     pub(super) pci: &'a mut PciAccess,
-    pub(super) bus_number: u8,
-    pub(super) device_number: u8,
-    pub(super) function_number: u8,
+    pub(super) address: PciAddress,
+    pub(super) identity: FunctionIdentity,
+}
+
+/// The IDs and class of a function, which are read when the function is found so that [`Display`] doesn't need to access config space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct FunctionIdentity {
+    vendor_id: u16,
+    device_id: u16,
+    class: ClassInfo,
+}
+
+impl FunctionIdentity {
+    /// Returns `None` if the function is not present
+    pub(super) fn read(pci: &mut PciAccess, address: PciAddress) -> Option<Self> {
+        let ids = pci.read_u32(address, 0x0);
+        if ids as u16 == u16::MAX {
+            return None;
+        }
+        let class = pci.read_u32(address, 0x8);
+        Some(Self {
+            vendor_id: ids as u16,
+            device_id: (ids >> 16) as u16,
+            class: ClassInfo {
+                class_code: (class >> 24) as u8,
+                sub_class: (class >> 16) as u8,
+                prog_if: (class >> 8) as u8,
+            },
+        })
+    }
+}
+
+impl Debug for PciFunction<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PciFunction")
+            .field("address", &format_args!("{}", self.address))
+            .finish()
+    }
+}
+
+/// Prints a one-line summary like `lspci -Dnn`, for example `0000:00:1f.2 Mass Storage Controller: SATA (Prog IF 0x01) [8086:2922]`.
+/// The IDs and class are the ones that were read when the function was found.
+impl Display for PciFunction<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {} [{:04x}:{:04x}]",
+            self.address, self.identity.class, self.identity.vendor_id, self.identity.device_id
+        )
+    }
 }
 
 impl PciFunction<'_> {
+    pub fn address(&self) -> PciAddress {
+        self.address
+    }
+
+    /// Checks if the function is still there. A function can disappear after it was found, for example if it was hot-unplugged.
+    /// Config reads of a function that isn't present return all 1s.
+    #[must_use]
+    pub fn is_present(&mut self) -> bool {
+        self.vendor_id() != u16::MAX
+    }
+
+    /// Reads a register and checks that the device is still present if the value is all 1s.
+    /// Use this for registers where all 1s is not a valid value for a present device.
+    pub fn read_verified_u32(&mut self, register_offset: u16) -> Result<u32, DeviceGone> {
+        let value = self.pci.read_u32(self.address, register_offset);
+        if value == u32::MAX && !self.is_present() {
+            Err(DeviceGone {
+                address: self.address,
+            })
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Low-level escape hatch for registers that this crate doesn't have a method for, such as vendor-specific registers.
+    /// Prefer the higher-level methods when they exist.
+    /// Offsets of `0x100` and above are only accessible with PCIe.
+    #[must_use]
+    pub fn read_config_u32(&mut self, register_offset: u16) -> u32 {
+        self.pci.read_u32(self.address, register_offset)
+    }
+
+    /// Reads 2 consecutive `u32` registers as a 64-bit register, with the lower half at `register_offset`.
+    /// With ECAM this is a single access, and with the legacy I/O ports it is 2 accesses.
+    ///
+    /// # Panics
+    /// Panics if the offset is not a multiple of 8
+    #[must_use]
+    pub fn read_config_u64(&mut self, register_offset: u16) -> u64 {
+        self.pci.read_u64(self.address, register_offset)
+    }
+
+    /// See [`Self::read_config_u32`]
+    #[must_use]
+    pub fn read_config_u16(&mut self, register_offset: u16) -> u16 {
+        self.pci.read_u16(self.address, register_offset)
+    }
+
+    /// See [`Self::read_config_u32`]
+    #[must_use]
+    pub fn read_config_u8(&mut self, register_offset: u16) -> u8 {
+        self.pci.read_u8(self.address, register_offset)
+    }
+
+    /// Low-level escape hatch. See [`Self::read_config_u32`].
+    /// Writing to registers that this crate manages can break the assumptions of other methods.
+    pub fn write_config_u32(&mut self, register_offset: u16, value: u32) {
+        self.pci.write_u32(self.address, register_offset, value);
+    }
+
+    /// See [`Self::write_config_u32`] and [`Self::read_config_u64`]
+    pub fn write_config_u64(&mut self, register_offset: u16, value: u64) {
+        self.pci.write_u64(self.address, register_offset, value);
+    }
+
+    /// See [`Self::write_config_u32`]
+    pub fn write_config_u16(&mut self, register_offset: u16, value: u16) {
+        self.pci.write_u16(self.address, register_offset, value);
+    }
+
+    /// See [`Self::write_config_u32`]
+    pub fn write_config_u8(&mut self, register_offset: u16, value: u8) {
+        self.pci.write_u8(self.address, register_offset, value);
+    }
+
+    /// Like [`Self::write_config_u32`], but reads the register back afterwards to check that the write wasn't dropped.
+    /// Only the bits in `mask` are compared, so that read-only, RW1C, and reserved bits can be ignored.
+    ///
+    /// This is meant for debugging hardware bring-up. Don't use it on registers where reading has side effects.
+    pub fn write_config_u32_verified(
+        &mut self,
+        register_offset: u16,
+        value: u32,
+        mask: u32,
+    ) -> Result<(), WriteVerifyError> {
+        self.pci
+            .write_u32_verified(self.address, register_offset, value, mask)
+    }
+
+    /// See [`Self::write_config_u32_verified`]
+    pub fn write_config_u16_verified(
+        &mut self,
+        register_offset: u16,
+        value: u16,
+        mask: u16,
+    ) -> Result<(), WriteVerifyError> {
+        self.pci
+            .write_u16_verified(self.address, register_offset, value, mask)
+    }
+
     pub fn vendor_id(&mut self) -> u16 {
-        self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0x0,
-        )
+        self.pci.read_u16(self.address, 0x0)
     }
 
+    #[must_use]
     pub fn device_id(&mut self) -> u16 {
-        self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0x2,
-        )
+        self.pci.read_u16(self.address, 0x2)
     }
 
+    #[must_use]
     pub fn class_code(&mut self) -> u8 {
-        (self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0xA,
-        ) >> 8) as u8
+        (self.pci.read_u16(self.address, 0xA) >> 8) as u8
     }
 
+    #[must_use]
     pub fn sub_class(&mut self) -> u8 {
-        self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0xA,
-        ) as u8
+        self.pci.read_u16(self.address, 0xA) as u8
     }
 
+    #[must_use]
     pub fn prog_if(&mut self) -> u8 {
-        (self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0x8,
-        ) >> 8) as u8
+        (self.pci.read_u16(self.address, 0x8) >> 8) as u8
     }
 
+    #[must_use]
     pub fn header_type_byte(&mut self) -> HeaderTypeByte {
-        HeaderTypeByte(self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0xE,
-        ) as u8)
+        HeaderTypeByte(self.pci.read_u16(self.address, 0xE) as u8)
     }
 
-    /// Returns `None` if the header type is not known
-    pub fn header_type(&mut self) -> Option<HeaderType> {
-        self.header_type_byte().header_type().try_into().ok()
+    #[must_use]
+    pub fn header_type(&mut self) -> HeaderType {
+        self.header_type_byte().header_type().into()
     }
 
     /// Returns `None` if the header type is not known
+    #[must_use]
     pub fn max_bars(&mut self) -> Option<u8> {
-        Some(match self.header_type()? {
-            HeaderType::GeneralDevice => 6,
-            HeaderType::PciToPciBridge => 2,
-            HeaderType::PciToCardBusBridge => 0,
-        })
+        self.header_type().bar_count()
+    }
+
+    /// Returns `None` if the header type doesn't have a subsystem vendor ID register
+    #[must_use]
+    pub fn subsystem_vendor_id(&mut self) -> Option<u16> {
+        let register_offset = self.header_type().subsystem_vendor_id_offset()?;
+        Some(self.pci.read_u16(self.address, register_offset.into()))
+    }
+
+    /// Returns `None` if the header type doesn't have a subsystem ID register
+    #[must_use]
+    pub fn subsystem_id(&mut self) -> Option<u16> {
+        let register_offset = self.header_type().subsystem_id_offset()?;
+        Some(self.pci.read_u16(self.address, register_offset.into()))
     }
 
     /// Returns `None` if header type is not known.
-    /// Returns `Some(None)` if the bar is not present
+    /// Returns `Some(None)` if the bar is not present, which is when none of its address bits are writable.
+    /// I/O and memory decoding are turned off while the BAR is sized, and then restored.
+    /// If the size that the BAR reports is 0 or not a power of 2, or it is a 64-bit BAR in the last slot, it is returned as [`BarWithSize::Invalid`].
+    /// Memory BARs with reserved type bits aren't sized, because their layout is unknown. They are returned as [`BarWithSize::Unknown`].
+    #[must_use]
     pub fn read_bar_with_size(&mut self, bar_index: u8) -> Option<Option<BarWithSize>> {
-        assert!((0..self.max_bars()?).contains(&bar_index));
-        let register_offset = 0x10 + size_of::<u32>() as u8 * bar_index;
-        let raw_addr = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-        );
-        if raw_addr == 0 {
+        let max_bars = self.max_bars()?;
+        assert!((0..max_bars).contains(&bar_index));
+        let register_offset = 0x10 + size_of::<u32>() as u16 * bar_index as u16;
+        let raw_addr = self.pci.read_u32(self.address, register_offset);
+        // The function doesn't respond, so there is nothing to size
+        if raw_addr == u32::MAX {
             return Some(None);
         }
-        self.pci.write_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-            u32::MAX,
-        );
-        let raw_size = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-        );
-        self.pci.write_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-            raw_addr,
-        );
-        Some(Some(if BarCommon(raw_addr).bar_type() == 0x0 {
+        let memory_bar_kind = (BarCommon(raw_addr).bar_type() == 0x0)
+            .then(|| MemoryBarKind::from_type_bits(MemorySpaceBar(raw_addr)._type()));
+        if let Some(MemoryBarKind::Reserved(_)) = memory_bar_kind {
+            return Some(Some(BarWithSize::Unknown { raw: raw_addr }));
+        }
+        let is_64_bit = memory_bar_kind == Some(MemoryBarKind::Bit64);
+        // The register after the last BAR is not a BAR (on bridges it is the bus number register), so it must not be written to
+        if is_64_bit && bar_index + 1 >= max_bars {
+            return Some(Some(BarWithSize::Invalid(InvalidBarInfo {
+                is_io: false,
+                addr: (raw_addr & !0b1111) as u64,
+                size: 0,
+                slots_len: 1,
+            })));
+        }
+        // While the BAR holds all ones, the function must not decode accesses, or it could claim accesses meant for something else
+        let command = CommandRegister(self.pci.read_u16(self.address, 0x4));
+        let decoding = command.io_space() || command.memory_space();
+        if decoding {
+            let mut not_decoding = command;
+            not_decoding.set_io_space(false);
+            not_decoding.set_memory_space(false);
+            self.pci.write_u16(self.address, 0x4, not_decoding.0);
+        }
+        self.pci.write_u32(self.address, register_offset, u32::MAX);
+        let raw_size = self.pci.read_u32(self.address, register_offset);
+        let next_register_offset = register_offset + size_of::<u32>() as u16;
+        let (next_raw_addr, next_raw_size) = if is_64_bit {
+            let next_raw_addr = self.pci.read_u32(self.address, next_register_offset);
+            self.pci
+                .write_u32(self.address, next_register_offset, u32::MAX);
+            let next_raw_size = self.pci.read_u32(self.address, next_register_offset);
+            // Restore the high dword before the low dword.
+            // Otherwise the address would briefly be the original low dword with an all-ones high dword.
+            self.pci
+                .write_u32(self.address, next_register_offset, next_raw_addr);
+            (next_raw_addr, next_raw_size)
+        } else {
+            (0, 0)
+        };
+        self.pci.write_u32(self.address, register_offset, raw_addr);
+        if decoding {
+            self.pci.write_u16(self.address, 0x4, command.0);
+        }
+        // A BAR that isn't implemented is hardwired to 0, so none of its address bits are writable
+        let size_mask = if BarCommon(raw_addr).bar_type() == 0x0 {
+            raw_size & !0b1111 | next_raw_size
+        } else {
+            raw_size & !0b11
+        };
+        if size_mask == 0 {
+            return Some(None);
+        }
+        let bar = if BarCommon(raw_addr).bar_type() == 0x0 {
+            let kind = MemoryBarKind::from_type_bits(MemorySpaceBar(raw_addr)._type());
             BarWithSize::Memory(MemoryBarInfo {
-                addr_and_size: match MemorySpaceBar(raw_addr)._type() {
-                    0x0 => MemoryBarAddrAndSize::U32(MemoryBarAddrAndSizeU32 {
-                        addr: raw_addr & !0b1111,
-                        size: (!(raw_size & !0b1111)).wrapping_add(1),
-                    }),
-                    0x2 => {
-                        let register_offset = 0x10 + size_of::<u32>() as u8 * (bar_index + 1);
-                        let next_raw_addr = self.pci.read_u32(
-                            self.bus_number,
-                            self.device_number,
-                            self.function_number,
-                            register_offset,
-                        );
-                        self.pci.write_u32(
-                            self.bus_number,
-                            self.device_number,
-                            self.function_number,
-                            register_offset,
-                            u32::MAX,
-                        );
-                        let next_raw_size = self.pci.read_u32(
-                            self.bus_number,
-                            self.device_number,
-                            self.function_number,
-                            register_offset,
-                        );
-                        self.pci.write_u32(
-                            self.bus_number,
-                            self.device_number,
-                            self.function_number,
-                            register_offset,
-                            next_raw_addr,
-                        );
+                addr_and_size: match kind {
+                    MemoryBarKind::Bit64 => {
+                        // Upper bits that the device can't decode are hardwired to 0, so they are not part of the size
+                        let addressable_bits = (u64::BITS - next_raw_size.leading_zeros()) as u8;
+                        let mut size_mask =
+                            (raw_size & !0b1111) as u64 | (next_raw_size as u64) << 32;
+                        if addressable_bits < 64 {
+                            size_mask |= u64::MAX << addressable_bits;
+                        }
                         MemoryBarAddrAndSize::U64(MemoryBarAddrAndSizeU64 {
                             addr: (raw_addr & !0b1111) as u64 | (next_raw_addr as u64) << 32,
-                            size: (!((raw_size & !0b1111) as u64 | (next_raw_size as u64) << 32))
-                                .wrapping_add(1),
+                            size: (!size_mask).wrapping_add(1),
+                            addressable_bits,
                         })
                     }
-                    _ => unreachable!(),
+                    // Below 1 MiB BARs only take up 1 slot, so decode them like 32-bit BARs
+                    _ => MemoryBarAddrAndSize::U32(MemoryBarAddrAndSizeU32 {
+                        addr: raw_addr & !0b1111,
+                        size: (!(raw_size & !0b1111)).wrapping_add(1),
+                    }),
                 },
+                kind,
                 prefetchable: MemorySpaceBar(raw_addr).prefetchable(),
             })
         } else {
+            // Bit 0 is the space indicator and bit 1 is reserved
+            let mut size_mask = raw_size & !0b11;
+            // Devices that only decode 16 bits of I/O address can read the upper 16 bits back as 0
+            if size_mask >> 16 == 0 {
+                size_mask |= 0xFFFF_0000;
+            }
             BarWithSize::Io(IoBarInfo {
                 addr: raw_addr & !0b11,
-                size: (!(raw_size & !0b11)).wrapping_add(1),
+                size: (!size_mask).wrapping_add(1),
             })
-        }))
+        };
+        Some(Some(bar.validated()))
+    }
+
+    /// Reads the address of a memory BAR without sizing it
+    fn memory_bar_addr(&mut self, bar_index: u8) -> Option<u64> {
+        let max_bars = self.max_bars()?;
+        if bar_index >= max_bars {
+            return None;
+        }
+        let register_offset = 0x10 + size_of::<u32>() as u16 * bar_index as u16;
+        let raw_addr = self.pci.read_u32(self.address, register_offset);
+        if BarCommon(raw_addr).bar_type() != 0x0 {
+            return None;
+        }
+        match MemoryBarKind::from_type_bits(MemorySpaceBar(raw_addr)._type()) {
+            MemoryBarKind::Bit64 if bar_index + 1 < max_bars => {
+                let next_raw_addr = self
+                    .pci
+                    .read_u32(self.address, register_offset + size_of::<u32>() as u16);
+                Some((raw_addr & !0b1111) as u64 | (next_raw_addr as u64) << 32)
+            }
+            MemoryBarKind::Bit64 | MemoryBarKind::Reserved(_) => None,
+            MemoryBarKind::Bit32 | MemoryBarKind::Below1MiB => Some((raw_addr & !0b1111) as u64),
+        }
+    }
+
+    /// Writes the address of a BAR, for example after assigning it. The address must be aligned to the BAR's size.
+    /// The upper dword of a 64-bit BAR is written first.
+    /// Returns `None` if the header type is not known, or if the BAR is a 64-bit BAR in the last slot.
+    ///
+    /// # Panics
+    /// Panics if the BAR index is out of range, or if the address doesn't fit in a BAR that isn't 64-bit
+    pub fn set_bar_addr(&mut self, bar_index: u8, addr: u64) -> Option<()> {
+        self.write_bar_addr(bar_index, addr, |function, register_offset, value, _| {
+            function.write_config_u32(register_offset, value);
+            Ok(())
+        })?
+        .ok()
+    }
+
+    /// Like [`Self::set_bar_addr`], but reads back each register after writing it.
+    /// Only the address bits are compared. See [`Self::write_config_u32_verified`].
+    pub fn set_bar_addr_verified(
+        &mut self,
+        bar_index: u8,
+        addr: u64,
+    ) -> Option<Result<(), WriteVerifyError>> {
+        self.write_bar_addr(bar_index, addr, Self::write_config_u32_verified)
+    }
+
+    fn write_bar_addr(
+        &mut self,
+        bar_index: u8,
+        addr: u64,
+        mut write: impl FnMut(&mut Self, u16, u32, u32) -> Result<(), WriteVerifyError>,
+    ) -> Option<Result<(), WriteVerifyError>> {
+        let max_bars = self.max_bars()?;
+        assert!((0..max_bars).contains(&bar_index));
+        let register_offset = 0x10 + size_of::<u32>() as u16 * bar_index as u16;
+        let raw_addr = self.pci.read_u32(self.address, register_offset);
+        let addr_mask = if BarCommon(raw_addr).bar_type() == 0x0 {
+            !0b1111
+        } else {
+            !0b11
+        };
+        let is_64_bit = BarCommon(raw_addr).bar_type() == 0x0
+            && MemoryBarKind::from_type_bits(MemorySpaceBar(raw_addr)._type())
+                == MemoryBarKind::Bit64;
+        if is_64_bit {
+            if bar_index + 1 >= max_bars {
+                return None;
+            }
+            let next_register_offset = register_offset + size_of::<u32>() as u16;
+            if let Err(e) = write(self, next_register_offset, (addr >> 32) as u32, u32::MAX) {
+                return Some(Err(e));
+            }
+        } else {
+            assert!(
+                addr <= u32::MAX as u64,
+                "The BAR can only hold a 32-bit address"
+            );
+        }
+        Some(write(
+            self,
+            register_offset,
+            (addr as u32 & addr_mask) | (raw_addr & !addr_mask),
+            addr_mask,
+        ))
+    }
+
+    /// Finds the physical address of the MSI-X table by reading the BAR that it is in.
+    /// Returns `None` if there is no MSI-X capability or the BAR is not a valid memory BAR.
+    #[must_use]
+    pub fn msi_x_table_phys_addr(&mut self) -> Option<MsiXPhysRegion> {
+        let msi_x = self.msi_x()??;
+        let location = msi_x.table_location();
+        let byte_len = msi_x.table_byte_len();
+        Some(MsiXPhysRegion {
+            phys_addr: self.memory_bar_addr(location.bar_index())?
+                + location.offset_in_bar() as u64,
+            byte_len,
+        })
+    }
+
+    /// Finds the physical address of the MSI-X Pending Bit Array by reading the BAR that it is in.
+    /// Returns `None` if there is no MSI-X capability or the BAR is not a valid memory BAR.
+    #[must_use]
+    pub fn msi_x_pba_phys_addr(&mut self) -> Option<MsiXPhysRegion> {
+        let msi_x = self.msi_x()??;
+        let location = msi_x.pba_location();
+        let byte_len = msi_x.pba_byte_len();
+        Some(MsiXPhysRegion {
+            phys_addr: self.memory_bar_addr(location.bar_index())?
+                + location.offset_in_bar() as u64,
+            byte_len,
+        })
+    }
+
+    /// Returns `None` if the header type is not [`HeaderType::PciToPciBridge`]
+    #[must_use]
+    pub fn bridge_control(&mut self) -> Option<BridgeControl> {
+        match self.header_type() {
+            HeaderType::PciToPciBridge => {
+                Some(BridgeControl(self.pci.read_u16(self.address, 0x3E)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns `None` if the header type is not [`HeaderType::PciToPciBridge`]
+    pub fn set_bridge_control(&mut self, bridge_control: BridgeControl) -> Option<()> {
+        match self.header_type() {
+            HeaderType::PciToPciBridge => {
+                self.pci.write_u16(self.address, 0x3E, bridge_control.0);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the function is a PCI-to-PCI bridge that uses subtractive decode (prog IF `0x01`).
+    /// A subtractive decode bridge forwards any transaction that nothing else claims, including legacy ranges.
+    #[must_use]
+    pub fn is_subtractive_decode_bridge(&mut self) -> bool {
+        self.class_code() == 0x06 && self.sub_class() == 0x04 && self.prog_if() == 0x01
+    }
+
+    /// Whether the bridge forwards legacy VGA memory and I/O to the devices behind it.
+    /// To check that a VGA device is reachable, check this for every bridge between it and the host bridge.
+    ///
+    /// Returns `None` if the header type is not [`HeaderType::PciToPciBridge`]
+    #[must_use]
+    pub fn routes_legacy_vga(&mut self) -> Option<bool> {
+        let vga_enable = self.bridge_control()?.vga_enable();
+        Some(vga_enable || self.is_subtractive_decode_bridge())
     }
 
     /// Returns `None` if header type is unknown
+    #[must_use]
     pub fn interrupt_info(&mut self) -> Option<InterruptInfo> {
-        let register_offset = self.header_type()?.interrupt_reg_addr();
-        let reg = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-        );
+        let header_type = self.header_type();
+        let register_offset = header_type.interrupt_reg_addr()?.into();
+        let reg = self.pci.read_u32(self.address, register_offset);
+        let general_device = header_type == HeaderType::GeneralDevice;
         Some(InterruptInfo {
             interrupt_pin: (reg >> 8) as u8,
             interrupt_line: reg as u8,
+            min_grant: general_device.then_some((reg >> 16) as u8),
+            max_latency: general_device.then_some((reg >> 24) as u8),
+            bridge_control: (header_type == HeaderType::PciToPciBridge)
+                .then_some(BridgeControl((reg >> 16) as u16)),
         })
     }
 
+    /// Returns `None` if header type is unknown
+    #[must_use]
+    pub fn interrupt_line(&mut self) -> Option<u8> {
+        let register_offset = self.header_type().interrupt_reg_addr()?.into();
+        Some(self.pci.read_u8(self.address, register_offset))
+    }
+
+    /// Which INTx pin the function uses. `0` means none, `1` means INTA, and so on.
+    /// This is wired in hardware, so it is read-only.
+    ///
+    /// Returns `None` if header type is unknown
+    #[must_use]
+    pub fn interrupt_pin(&mut self) -> Option<u8> {
+        let register_offset: u16 = self.header_type().interrupt_reg_addr()?.into();
+        Some(self.pci.read_u8(self.address, register_offset + 1))
+    }
+
+    /// The minimum burst period the device needs, in units of 0.25 µs.
+    ///
+    /// Returns `None` if the header type is not [`HeaderType::GeneralDevice`]
+    #[must_use]
+    pub fn min_gnt(&mut self) -> Option<u8> {
+        match self.header_type() {
+            HeaderType::GeneralDevice => Some(self.pci.read_u8(self.address, 0x3E)),
+            _ => None,
+        }
+    }
+
+    /// How often the device needs to access the bus, in units of 0.25 µs.
+    ///
+    /// Returns `None` if the header type is not [`HeaderType::GeneralDevice`]
+    #[must_use]
+    pub fn max_lat(&mut self) -> Option<u8> {
+        match self.header_type() {
+            HeaderType::GeneralDevice => Some(self.pci.read_u8(self.address, 0x3F)),
+            _ => None,
+        }
+    }
+
+    /// Whether the status register says that the function has a capabilities list
+    #[must_use]
+    pub fn has_capabilities_list(&mut self) -> bool {
+        self.status().capabilities_list()
+    }
+
     /// Returns `None` if the header type is unknown
+    #[must_use]
     pub fn capabilities(&mut self) -> Option<Capabilities> {
-        let register_offset = match self.header_type()? {
-            HeaderType::GeneralDevice => 0x34,
-            HeaderType::PciToPciBridge => 0x34,
-            HeaderType::PciToCardBusBridge => 0x14,
+        let register_offset = self.header_type().capabilities_ptr_offset()?;
+        // If the function doesn't have a capabilities list, the pointer is meaningless
+        let ptr = if self.has_capabilities_list() {
+            let reg = self.pci.read_u32(self.address, register_offset.into());
+            // If the device was removed, the register reads as all 1s and there are no capabilities
+            if reg == u32::MAX { 0 } else { reg as u8 }
+        } else {
+            0
         };
-        Some(Capabilities {
-            bus_number: self.bus_number,
-            device_number: self.device_number,
-            function_number: self.function_number,
-            ptr: self.pci.read_u32(
-                self.bus_number,
-                self.device_number,
-                self.function_number,
-                register_offset,
-            ) as u8,
+        Some(Capabilities::new(self.pci, self.address, ptr))
+    }
+
+    /// Finds the first capability with the ID.
+    /// To support a capability that this crate doesn't know about, find it with this and then read it with [`Self::capability_reader`].
+    ///
+    /// Returns `None` if the header type is unknown, and `Some(None)` if the function doesn't have the capability.
+    #[must_use]
+    pub fn find_capability(&mut self, id: u8) -> Option<Option<Capability>> {
+        Some(self.capabilities()?.find(|capability| capability.id == id))
+    }
+
+    /// Copies the capabilities into `out`, so that you can use the function while going through them.
+    /// Stops once `out` is full, and returns the number of capabilities that were written.
+    /// Returns 0 if the header type is unknown.
+    pub fn capability_offsets(&mut self, out: &mut [Capability]) -> usize {
+        let Some(capabilities) = self.capabilities() else {
+            return 0;
+        };
+        let mut count = 0;
+        for (slot, capability) in out.iter_mut().zip(capabilities) {
+            *slot = capability;
+            count += 1;
+        }
+        count
+    }
+
+    /// Read the contents of a capability, for example one that was found with [`Self::find_capability`]
+    pub fn capability_reader(&mut self, capability: &Capability) -> CapabilityReader {
+        CapabilityReader {
             pci: self.pci,
-        })
+            address: self.address,
+            ptr: capability.ptr_to_self.into(),
+        }
     }
 
     /// # Important
@@ -207,54 +592,369 @@ impl PciFunction<'_> {
     ///
     /// Returns `None` if the header type is unknown
     pub fn set_interrupt_line(&mut self, interrupt_line: u8) -> Option<()> {
-        let register_offset = self.header_type()?.interrupt_reg_addr();
-        let current_reg = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-        );
-        let new_reg = current_reg & !0xFF | interrupt_line as u32;
-        self.pci.write_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            register_offset,
-            new_reg,
-        );
+        let register_offset = self.header_type().interrupt_reg_addr()?.into();
+        self.pci
+            .write_u8(self.address, register_offset, interrupt_line);
         Some(())
     }
 
+    #[must_use]
     pub fn msi(&mut self) -> Option<Option<Msi>> {
         Msi::find(self)
     }
 
+    #[must_use]
     pub fn msi_x(&mut self) -> Option<Option<MsiX>> {
         MsiX::find(self)
     }
 
+    /// Picks the best interrupt mechanism that the function supports, in the order MSI-X, MSI, and then legacy INTx
+    #[must_use]
+    pub fn preferred_interrupt(&mut self) -> InterruptMechanism {
+        let (mut msi, mut msi_x) = (false, false);
+        if let Some(capabilities) = self.capabilities() {
+            for capability in capabilities {
+                match capability.id {
+                    0x5 => msi = true,
+                    0x11 => msi_x = true,
+                    _ => {}
+                }
+            }
+        }
+        if msi_x {
+            InterruptMechanism::MsiX
+        } else if msi {
+            InterruptMechanism::Msi
+        } else if self.interrupt_pin().is_some_and(|pin| pin != 0) {
+            InterruptMechanism::LegacyIntx
+        } else {
+            InterruptMechanism::None
+        }
+    }
+
+    /// Returns `None` if extended config space is not accessible
+    #[must_use]
+    pub fn sr_iov(&mut self) -> Option<Option<SrIov>> {
+        SrIov::find(self)
+    }
+
+    #[must_use]
     pub fn command(&mut self) -> CommandRegister {
-        CommandRegister(self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            0x4,
-        ))
+        CommandRegister(self.pci.read_u16(self.address, 0x4))
     }
 
+    /// Writes the defined bits of `command`.
+    /// The reserved bits (7 and 11-15) are read from the register and written back unchanged, so you can safely pass a `CommandRegister` that you built from scratch.
     pub fn set_command(&mut self, command: CommandRegister) {
+        let reserved = self.pci.read_u16(self.address, 0x4) & CommandRegister::RESERVED_BITS;
         self.pci.write_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
+            self.address,
             0x4,
-            command.0,
+            (command.0 & !CommandRegister::RESERVED_BITS) | reserved,
         );
     }
+
+    /// Like [`Self::set_command`], but reads the register back afterwards. See [`Self::write_config_u16_verified`].
+    /// Only the bits in `mask` are compared. Bits that the function doesn't implement are hardwired to 0, so only include bits that you know are implemented.
+    pub fn set_command_verified(
+        &mut self,
+        command: CommandRegister,
+        mask: u16,
+    ) -> Result<(), WriteVerifyError> {
+        let reserved = self.pci.read_u16(self.address, 0x4) & CommandRegister::RESERVED_BITS;
+        self.write_config_u16_verified(
+            0x4,
+            (command.0 & !CommandRegister::RESERVED_BITS) | reserved,
+            mask & !CommandRegister::RESERVED_BITS,
+        )
+    }
+
+    #[must_use]
+    pub fn status(&mut self) -> StatusRegister {
+        StatusRegister(self.pci.read_u16(self.address, 0x6))
+    }
+
+    /// Whether the function is currently asserting its INTx interrupt. In a shared IRQ handler, use this to find out which function is interrupting.
+    #[must_use]
+    pub fn intx_asserted(&mut self) -> bool {
+        self.status().interrupt_status()
+    }
+
+    /// Enables or disables INTx interrupts using [`CommandRegister::interrupt_disable`]
+    pub fn set_intx_enabled(&mut self, enabled: bool) {
+        self.update_command(|command| command.set_interrupt_disable(!enabled));
+    }
+
+    /// For shared IRQ handlers. If the function is asserting INTx, this disables INTx and returns `true`.
+    /// After you handle the interrupt, re-enable INTx with [`Self::set_intx_enabled`].
+    #[must_use]
+    pub fn claim_intx(&mut self) -> bool {
+        let asserted = self.intx_asserted();
+        if asserted {
+            self.set_intx_enabled(false);
+        }
+        asserted
+    }
+
+    /// Reads the command register, lets you modify it, and writes it back.
+    /// Like [`Self::set_command`], the reserved bits are preserved.
+    pub fn update_command(&mut self, f: impl FnOnce(&mut CommandRegister)) {
+        let mut command = self.command();
+        f(&mut command);
+        self.set_command(command);
+    }
 }
 
-#[derive(Debug)]
+/// The register that has the interrupt line and pin also has other info that depends on the header type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct InterruptInfo {
     pub interrupt_pin: u8,
     pub interrupt_line: u8,
+    /// Only for [`HeaderType::GeneralDevice`]. See [`PciFunction::min_gnt`].
+    pub min_grant: Option<u8>,
+    /// Only for [`HeaderType::GeneralDevice`]. See [`PciFunction::max_lat`].
+    pub max_latency: Option<u8>,
+    /// Only for [`HeaderType::PciToPciBridge`]
+    pub bridge_control: Option<BridgeControl>,
+}
+
+/// See [`PciFunction::preferred_interrupt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterruptMechanism {
+    MsiX,
+    Msi,
+    LegacyIntx,
+    /// The function doesn't use interrupts
+    None,
+}
+
+/// The function is no longer present, for example because it was hot-unplugged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceGone {
+    pub address: PciAddress,
+}
+
+/// A verified config write read back a different value than what was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WriteVerifyError {
+    pub address: PciAddress,
+    pub register_offset: u16,
+    pub wrote: u32,
+    pub read_back: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 1,
+        function: 0,
+    };
+
+    fn mock_with(header_type: u8, f: impl FnOnce(&mut MockFunction)) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        f(mock
+            .function(ADDRESS)
+            .header(0x1234, 0x5678, [0xFF, 0x00, 0x00], header_type));
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn general_device_offsets() {
+        let mut pci = mock_with(0x0, |function| {
+            function
+                .set_u16(0x2C, 0xAAAA)
+                .set_u16(0x2E, 0xBBBB)
+                .set_u16(0x3C, 0x0210)
+                .capability(0x40, 0x5, &[0; 10]);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert_eq!(function.header_type(), HeaderType::GeneralDevice);
+        assert_eq!(function.max_bars(), Some(6));
+        assert_eq!(function.subsystem_vendor_id(), Some(0xAAAA));
+        assert_eq!(function.subsystem_id(), Some(0xBBBB));
+        assert_eq!(function.interrupt_line(), Some(0x10));
+        assert_eq!(function.interrupt_pin(), Some(0x2));
+        assert_eq!(
+            function
+                .capabilities()
+                .unwrap()
+                .map(|c| c.ptr_to_self)
+                .next(),
+            Some(0x40)
+        );
+    }
+
+    #[test]
+    fn pci_to_pci_bridge_offsets() {
+        let mut pci = mock_with(0x1, |function| {
+            function
+                .set_u16(0x2C, 0xAAAA)
+                .set_u16(0x3C, 0x0110)
+                .capability(0x50, 0x10, &[0; 10]);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert_eq!(function.header_type(), HeaderType::PciToPciBridge);
+        assert_eq!(function.max_bars(), Some(2));
+        // 0x2C is the upper 32 bits of the prefetchable base in a bridge
+        assert_eq!(function.subsystem_vendor_id(), None);
+        assert_eq!(function.subsystem_id(), None);
+        assert_eq!(function.min_gnt(), None);
+        assert_eq!(function.interrupt_line(), Some(0x10));
+        assert_eq!(
+            function
+                .capabilities()
+                .unwrap()
+                .map(|c| c.ptr_to_self)
+                .next(),
+            Some(0x50)
+        );
+    }
+
+    #[test]
+    fn cardbus_bridge_offsets() {
+        let mut pci = mock_with(0x2, |function| {
+            function
+                .set_u16(0x40, 0xAAAA)
+                .set_u16(0x42, 0xBBBB)
+                // In a general device, this would be the capabilities pointer
+                .set_u8(0x34, 0x60)
+                .capability(0x80, 0x1, &[0; 6]);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert_eq!(function.header_type(), HeaderType::PciToCardBusBridge);
+        assert_eq!(function.max_bars(), Some(0));
+        assert_eq!(function.subsystem_vendor_id(), Some(0xAAAA));
+        assert_eq!(function.subsystem_id(), Some(0xBBBB));
+        assert_eq!(
+            function
+                .capabilities()
+                .unwrap()
+                .map(|c| c.ptr_to_self)
+                .next(),
+            Some(0x80)
+        );
+    }
+
+    #[test]
+    fn unknown_header_type() {
+        let mut pci = mock_with(0x7F, |function| {
+            function.capability(0x40, 0x5, &[0; 10]);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert_eq!(function.header_type(), HeaderType::Unknown(0x7F));
+        assert_eq!(function.max_bars(), None);
+        assert_eq!(function.subsystem_vendor_id(), None);
+        assert_eq!(function.interrupt_line(), None);
+        assert!(function.capabilities().is_none());
+        assert_eq!(function.set_interrupt_line(0x10), None);
+    }
+
+    #[test]
+    fn multi_function_bit_is_not_part_of_the_header_type() {
+        let mut pci = mock_with(0x81, |_| {});
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert!(function.header_type_byte().multi_function());
+        assert_eq!(function.header_type(), HeaderType::PciToPciBridge);
+    }
+
+    #[test]
+    fn set_command_verified() {
+        // Only memory space and bus master are implemented
+        let mut pci = mock_with(0x0, |function| {
+            function.writable_u8s(0x4, 1, 0b110).writable_u8s(0x5, 1, 0);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        let mut command = CommandRegister(0);
+        command.set_memory_space(true);
+        command.set_bus_master(true);
+        assert_eq!(function.set_command_verified(command, 0b110), Ok(()));
+        command.set_io_space(true);
+        assert_eq!(
+            function.set_command_verified(command, 0b111),
+            Err(WriteVerifyError {
+                address: ADDRESS,
+                register_offset: 0x4,
+                wrote: 0b111,
+                read_back: 0b110,
+            })
+        );
+    }
+
+    #[test]
+    fn set_bar_addr_verified() {
+        let mut pci = mock_with(0x0, |function| {
+            function
+                .memory_bar_64(0, 0x0, 0x1000, false)
+                .io_bar(2, 0x0, 0x20)
+                // This BAR drops writes
+                .memory_bar_32(3, 0x0, 0x1000, false)
+                .read_only(0x1C, 4);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert_eq!(
+            function.set_bar_addr_verified(0, 0x1_2345_6000),
+            Some(Ok(()))
+        );
+        assert_eq!(function.read_config_u32(0x10), 0x2345_6004);
+        assert_eq!(function.read_config_u32(0x14), 0x1);
+        assert_eq!(function.set_bar_addr_verified(2, 0xC000), Some(Ok(())));
+        assert_eq!(function.read_config_u32(0x18), 0xC001);
+        assert_eq!(
+            function.set_bar_addr_verified(3, 0xFE00_0000),
+            Some(Err(WriteVerifyError {
+                address: ADDRESS,
+                register_offset: 0x1C,
+                wrote: 0xFE00_0000,
+                read_back: 0x0,
+            }))
+        );
+        // The upper dword of a 64-bit BAR is written first
+        let writes = pci
+            .mock()
+            .writes()
+            .map(|access| access.register_offset)
+            .take(2)
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(writes, [0x14, 0x10]);
+    }
+
+    #[test]
+    fn capabilities_without_the_capabilities_list_bit() {
+        let mut pci = mock_with(0x0, |function| {
+            function.set_u8(0x34, 0x40).set_bytes(0x40, &[0x5, 0x0]);
+        });
+        let mut function = pci.function_at(0, 1, 0).unwrap();
+        assert!(!function.has_capabilities_list());
+        assert_eq!(function.capabilities().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn formatting_includes_the_location() {
+        let mut mock = MockConfig::new(AccessKind::Ecam).with_ecam_buses(0..=3);
+        mock.function(PciAddress::new(0, 3, 0, 0))
+            .header(0x8086, 0x2922, [0x01, 0x06, 0x01], 0x0);
+        let mut pci = mock.into_pci_access();
+        assert_eq!(format!("{:?}", pci.bus(3)), "PciBus { location: 0000:03 }");
+        let mut bus = pci.bus(3);
+        let mut device = bus.device(0).unwrap();
+        assert_eq!(
+            format!("{device:?}"),
+            "PciDevice { location: 0000:03:00, multi_function: false }"
+        );
+        let function = device.function(0).unwrap();
+        assert_eq!(
+            format!("{function:?}"),
+            "PciFunction { address: 0000:03:00.0 }"
+        );
+        assert_eq!(
+            format!("{function}"),
+            "0000:03:00.0 Mass Storage Controller: SATA (Prog IF 0x01) [8086:2922]"
+        );
+    }
 }
@@ -1,12 +1,21 @@
-use core::ops::Range;
+use core::ops::{Range, RangeInclusive};
 
 pub use acpi::mcfg::McfgEntry;
-pub use x86_64::PhysAddr;
+
+use super::*;
 
 pub fn get_phys_range_to_map(mcfg_entry: &McfgEntry) -> Range<PhysAddr> {
-    let n_buses = (mcfg_entry.bus_number_end - mcfg_entry.bus_number_start) as u64 + 1;
-    let start_addr =
-        PhysAddr::new(mcfg_entry.base_address + ((mcfg_entry.bus_number_start as u64) << 20));
-    let len = n_buses * (1 << 20);
-    start_addr..start_addr + len
+    EcamWindow::from(*mcfg_entry).phys_range()
+}
+
+/// Like [`get_phys_range_to_map`], but only for some of the buses in the MCFG entry.
+/// Each bus takes up 1 MiB, so this lets you avoid mapping buses that you don't need.
+///
+/// # Panics
+/// Panics if the buses are not within the MCFG entry's bus range
+pub fn get_phys_range_to_map_for_buses(
+    mcfg_entry: &McfgEntry,
+    buses: RangeInclusive<u8>,
+) -> Range<PhysAddr> {
+    EcamWindow::from(*mcfg_entry).phys_range_for_buses(buses)
 }
@@ -1,5 +1,5 @@
 use bitfield::bitfield;
-use num_enum::TryFromPrimitive;
+use num_enum::FromPrimitive;
 
 bitfield! {
     pub struct HeaderTypeByte(u8);
@@ -9,16 +9,77 @@ bitfield! {
     u8; pub header_type, _: 6, 0;
 }
 
-#[derive(Debug, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
 #[repr(u8)]
 pub enum HeaderType {
     GeneralDevice = 0x0,
     PciToPciBridge = 0x1,
     PciToCardBusBridge = 0x2,
+    /// A reserved header type. The layout of the config space after the common header is not known.
+    #[num_enum(catch_all)]
+    Unknown(u8),
 }
 
 impl HeaderType {
-    pub fn interrupt_reg_addr(&self) -> u8 {
-        0x3C
+    /// The raw value of the header type field (without the multi-function bit)
+    pub fn raw(&self) -> u8 {
+        match self {
+            Self::GeneralDevice => 0x0,
+            Self::PciToPciBridge => 0x1,
+            Self::PciToCardBusBridge => 0x2,
+            Self::Unknown(raw) => *raw,
+        }
+    }
+}
+
+/// The offsets of registers that depend on the header type.
+/// They return `None` if the header type doesn't have the register, or if the header type is unknown.
+impl HeaderType {
+    pub fn interrupt_reg_addr(&self) -> Option<u8> {
+        match self {
+            Self::GeneralDevice | Self::PciToPciBridge | Self::PciToCardBusBridge => Some(0x3C),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn bar_count(&self) -> Option<u8> {
+        match self {
+            Self::GeneralDevice => Some(6),
+            Self::PciToPciBridge => Some(2),
+            Self::PciToCardBusBridge => Some(0),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn capabilities_ptr_offset(&self) -> Option<u8> {
+        match self {
+            Self::GeneralDevice | Self::PciToPciBridge => Some(0x34),
+            Self::PciToCardBusBridge => Some(0x14),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn expansion_rom_offset(&self) -> Option<u8> {
+        match self {
+            Self::GeneralDevice => Some(0x30),
+            Self::PciToPciBridge => Some(0x38),
+            Self::PciToCardBusBridge | Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn subsystem_vendor_id_offset(&self) -> Option<u8> {
+        match self {
+            Self::GeneralDevice => Some(0x2C),
+            Self::PciToCardBusBridge => Some(0x40),
+            Self::PciToPciBridge | Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn subsystem_id_offset(&self) -> Option<u8> {
+        match self {
+            Self::GeneralDevice => Some(0x2E),
+            Self::PciToCardBusBridge => Some(0x42),
+            Self::PciToPciBridge | Self::Unknown(_) => None,
+        }
     }
 }
@@ -0,0 +1,179 @@
+use super::*;
+
+/// A summary of everything that can go wrong with a function, from [`PciFunction::health`].
+/// The error bits are latched, so use [`PciFunction::clear_errors`] after reading them to only see new errors next time.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionHealth {
+    /// If this is `false`, the function is gone (its vendor ID reads as all ones), and the other fields are empty
+    pub present: bool,
+    /// Check the master/target abort, parity, and SERR bits, or use [`StatusRegister::has_errors`]
+    pub status: StatusRegister,
+    /// `None` if the function is not PCIe
+    pub device_status: Option<DeviceStatus>,
+    /// `None` if the function is not PCIe or has no link (root complex integrated endpoints and event collectors)
+    pub link: Option<LinkHealth>,
+}
+
+impl FunctionHealth {
+    /// Whether the function is present, has no latched errors, and its link is up (if that is known)
+    pub fn is_healthy(&self) -> bool {
+        self.present
+            && !self.status.has_errors()
+            && !self
+                .device_status
+                .is_some_and(|device_status| device_status.has_errors())
+            && self.link.is_none_or(|link| link.link_up != Some(false))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LinkHealth {
+    /// `None` if the port doesn't support Data Link Layer Link Active reporting
+    pub link_up: Option<bool>,
+    pub speed: LinkSpeed,
+    pub max_speed: LinkSpeed,
+    pub width: u8,
+    pub max_width: u8,
+}
+
+impl LinkHealth {
+    /// Whether the link is running slower or with fewer lanes than it supports.
+    /// This can also be because the other end of the link doesn't support more.
+    pub fn is_degraded(&self) -> bool {
+        self.speed != self.max_speed || self.width != self.max_width
+    }
+}
+
+impl PciFunction<'_> {
+    /// Reads presence, the error bits of the Status register, and (for PCIe functions) the error bits of the Device Status register and the link status.
+    /// This doesn't change anything, so it is safe to call on a timer.
+    #[must_use]
+    pub fn health(&mut self) -> FunctionHealth {
+        if !self.is_present() {
+            return FunctionHealth {
+                present: false,
+                status: StatusRegister(0),
+                device_status: None,
+                link: None,
+            };
+        }
+        let status = self.status();
+        let (device_status, link) = match self.pci_express().flatten() {
+            Some(mut pci_express) => {
+                let has_link = !matches!(
+                    pci_express.port_type(),
+                    PciePortType::RootComplexIntegratedEndpoint
+                        | PciePortType::RootComplexEventCollector
+                );
+                let link = has_link.then(|| {
+                    let link_capabilities = pci_express.link_capabilities();
+                    let link_status = pci_express.link_status();
+                    LinkHealth {
+                        link_up: link_capabilities
+                            .dll_link_active_reporting_capable()
+                            .then_some(link_status.dll_link_active()),
+                        speed: link_status.current_link_speed().into(),
+                        max_speed: link_capabilities.max_link_speed().into(),
+                        width: link_status.negotiated_link_width(),
+                        max_width: link_capabilities.max_link_width(),
+                    }
+                });
+                (Some(pci_express.device_status()), link)
+            }
+            None => (None, None),
+        };
+        FunctionHealth {
+            present: true,
+            status,
+            device_status,
+            link,
+        }
+    }
+
+    /// Clears the latched error bits that [`Self::health`] reports, by writing 1 to them
+    pub fn clear_errors(&mut self) {
+        self.pci
+            .write_u16(self.address, 0x6, StatusRegister::ERROR_BITS);
+        if let Some(mut pci_express) = self.pci_express().flatten() {
+            pci_express.clear_device_status_errors();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 8,
+        function: 0,
+    };
+
+    /// A PCIe endpoint with a Gen 3 x4 link that is up, and Data Link Layer Link Active reporting
+    fn mock_endpoint(status: u16, device_status: u16) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x8086, 0x1234, [0x02, 0x00, 0x00], 0x0)
+            .pci_express(
+                0x0,
+                0,
+                device_status,
+                1 << 20 | 4 << 4 | 3,
+                1 << 13 | 4 << 4 | 3,
+            )
+            .set_u16(0x6, status | 1 << 4)
+            .read_only(0x4A, 2)
+            .write_1_to_clear_u16(0x4A, DeviceStatus::ERROR_BITS);
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn healthy() {
+        let mut pci = mock_endpoint(0, 0);
+        let health = pci.function_at(0, 8, 0).unwrap().health();
+        assert!(health.present);
+        assert_eq!(
+            health.link,
+            Some(LinkHealth {
+                link_up: Some(true),
+                speed: LinkSpeed::Gt8,
+                max_speed: LinkSpeed::Gt8,
+                width: 4,
+                max_width: 4,
+            })
+        );
+        assert!(health.is_healthy());
+        assert_eq!(pci.mock().writes().count(), 0);
+    }
+
+    #[test]
+    fn latched_parity_errors() {
+        let mut pci = mock_endpoint(1 << 15 | 1 << 8, 1 << 0);
+        let mut function = pci.function_at(0, 8, 0).unwrap();
+        let health = function.health();
+        assert!(health.status.detected_parity_error());
+        assert!(health.status.master_data_parity_error());
+        assert!(health.device_status.unwrap().correctable_error_detected());
+        assert!(!health.is_healthy());
+        // Reading the health again still reports the errors, because they are latched
+        assert!(!function.health().is_healthy());
+        function.clear_errors();
+        assert!(function.health().is_healthy());
+        assert_eq!(pci.mock().function(ADDRESS).u16(0x6), 1 << 4);
+        assert_eq!(pci.mock().function(ADDRESS).u16(0x4A), 0);
+    }
+
+    #[test]
+    fn vanished_device() {
+        let pci = PciAccessShared::new(mock_endpoint(0, 0));
+        let function = pci.split_function(ADDRESS).unwrap();
+        pci.with(|pci| pci.mock().remove_function(ADDRESS));
+        let health = function.with(|function| function.health());
+        assert!(!health.present);
+        assert!(health.device_status.is_none());
+        assert!(health.link.is_none());
+        assert!(!health.is_healthy());
+    }
+}
@@ -0,0 +1,103 @@
+use x86_64::instructions::port::{Port, PortRead, PortWrite};
+
+use super::*;
+
+/// The I/O ports of an I/O space BAR. Accesses are checked to be inside the BAR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IoPortRange {
+    base: u16,
+    size: u32,
+}
+
+impl IoPortRange {
+    /// # Panics
+    /// Panics if the BAR is not within the 16-bit x86 I/O port space
+    ///
+    /// # Safety
+    /// The ports must be the ports of the BAR, and must not be used by other code
+    pub unsafe fn new(io_bar_info: IoBarInfo) -> Self {
+        let IoBarInfo { addr, size } = io_bar_info;
+        assert!(
+            addr as u64 + size as u64 <= 0x1_0000,
+            "I/O BAR at 0x{addr:X} with size 0x{size:X} is outside of the x86 I/O port space"
+        );
+        Self {
+            base: addr as u16,
+            size,
+        }
+    }
+
+    /// The first port
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// The number of ports
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn port(&self, offset: u16, access_len: usize) -> u16 {
+        let size = self.size;
+        assert!(
+            offset as usize + access_len <= size as usize,
+            "I/O port offset 0x{offset:X} with access size {access_len} is outside of the BAR with size 0x{size:X}"
+        );
+        self.base + offset
+    }
+
+    /// An escape hatch for accessing the ports directly
+    ///
+    /// # Panics
+    /// Panics if the access would be outside of the BAR
+    ///
+    /// # Safety
+    /// Accessing the port must not break the assumptions of other code, such as the methods of this type
+    pub unsafe fn port_at<T: PortRead + PortWrite>(&self, offset: u16) -> Port<T> {
+        Port::new(self.port(offset, size_of::<T>()))
+    }
+
+    pub fn read_u8(&mut self, offset: u16) -> u8 {
+        // Safety: the port is inside the BAR, which this type owns
+        unsafe { self.port_at::<u8>(offset).read() }
+    }
+
+    pub fn read_u16(&mut self, offset: u16) -> u16 {
+        // Safety: the port is inside the BAR, which this type owns
+        unsafe { self.port_at::<u16>(offset).read() }
+    }
+
+    pub fn read_u32(&mut self, offset: u16) -> u32 {
+        // Safety: the port is inside the BAR, which this type owns
+        unsafe { self.port_at::<u32>(offset).read() }
+    }
+
+    pub fn write_u8(&mut self, offset: u16, value: u8) {
+        // Safety: the port is inside the BAR, which this type owns
+        unsafe { self.port_at::<u8>(offset).write(value) }
+    }
+
+    pub fn write_u16(&mut self, offset: u16, value: u16) {
+        // Safety: the port is inside the BAR, which this type owns
+        unsafe { self.port_at::<u16>(offset).write(value) }
+    }
+
+    pub fn write_u32(&mut self, offset: u16, value: u32) {
+        // Safety: the port is inside the BAR, which this type owns
+        unsafe { self.port_at::<u32>(offset).write(value) }
+    }
+}
+
+impl PciFunction<'_> {
+    /// Reads and sizes the BAR, and returns its ports if it is an I/O space BAR.
+    /// Returns `None` if the header type is unknown, or if the BAR is not present or not an I/O space BAR.
+    ///
+    /// # Safety
+    /// See [`IoPortRange::new`]
+    pub unsafe fn io_bar(&mut self, bar_index: u8) -> Option<IoPortRange> {
+        match self.read_bar_with_size(bar_index)?? {
+            BarWithSize::Io(io_bar_info) => Some(unsafe { IoPortRange::new(io_bar_info) }),
+            _ => None,
+        }
+    }
+}
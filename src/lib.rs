@@ -1,35 +1,112 @@
-//! To use PCI, use [`PciAccess::new_pci`].
-//! To use PCIe, use [`PciAccess::new_pcie`].
+#![cfg_attr(
+    feature = "legacy-port-io",
+    doc = "To use PCI, use [`PciAccess::new_pci`]."
+)]
+#![cfg_attr(feature = "ecam", doc = "To use PCIe, use [`PcieBuilder`].")]
 //!
-//! Then you can scan buses.
+//! Then you can scan buses. Use [`PciAccess::segment`] if there are multiple PCI segment groups.
 //! For each bus, you can scan devices.
 //! For each device, you can scan functions.
 //! For each function, you can scan BARs, capabilities, and general info.
 //!
 //! You can also find and configure MSI (Message Signaled Interrupts)
+//!
+//! To print everything about the functions, use the [`dump`] module.
+//! To collect every function into a buffer before using them, use [`PciAccess::scan_into`].
 #![no_std]
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+mod address;
 mod bar;
+mod bridge;
+pub mod bridge_window;
 mod bus;
 mod capabilities;
+mod class;
 mod command;
+mod config_image;
+mod config_snapshot;
 mod device;
+mod dma;
+pub mod dump;
+#[cfg(feature = "ecam")]
+mod ecam_window;
+mod extended_capabilities;
 mod function;
+#[cfg(feature = "acpi")]
 mod get_phys_range_to_map;
 mod header_type;
+mod health;
+#[cfg(feature = "x86")]
+mod io_port_range;
+#[cfg(test)]
+mod mock;
 mod msi;
 mod msi_x;
 mod pci_access;
+#[cfg(feature = "legacy-port-io")]
 mod pci_config;
+mod pci_express;
+#[cfg(feature = "ecam")]
+mod pcie_builder;
+mod phys_addr;
+mod power_management;
+mod probe;
+mod probe_table;
+mod reset;
+mod scan;
+mod segment;
+mod shared;
+mod sr_iov;
+mod status;
+#[cfg(feature = "alloc")]
+mod tree;
+mod vpd;
 
+pub use address::*;
 pub use bar::*;
+pub use bridge::*;
 pub use bus::*;
 pub use capabilities::*;
+pub use class::*;
 pub use command::*;
+pub use config_image::*;
+pub use config_snapshot::*;
 pub use device::*;
+pub use dma::*;
+#[cfg(feature = "ecam")]
+pub use ecam_window::*;
+pub use extended_capabilities::*;
 pub use function::*;
+#[cfg(feature = "acpi")]
 pub use get_phys_range_to_map::*;
 pub use header_type::*;
+pub use health::*;
+#[cfg(feature = "x86")]
+pub use io_port_range::*;
+#[cfg(test)]
+use mock::*;
 pub use msi::*;
 pub use msi_x::*;
 pub use pci_access::*;
+#[cfg(feature = "legacy-port-io")]
 use pci_config::*;
+pub use pci_express::*;
+#[cfg(feature = "ecam")]
+pub use pcie_builder::*;
+pub use phys_addr::*;
+pub use power_management::*;
+pub use probe::*;
+pub use probe_table::*;
+pub use reset::*;
+pub use scan::*;
+pub use segment::*;
+pub use shared::*;
+pub use sr_iov::*;
+pub use status::*;
+#[cfg(feature = "alloc")]
+pub use tree::*;
+pub use vpd::*;
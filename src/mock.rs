@@ -0,0 +1,470 @@
+//! A fake config space for tests, used through [`PciAccess::Mock`].
+//! Every access is logged, so that tests can check the order of writes and count reads.
+// Not every test uses every helper
+#![allow(dead_code)]
+use core::{fmt::Debug, ops::RangeInclusive};
+use std::{boxed::Box, vec::Vec};
+
+use super::*;
+
+/// 1 config access that went through the mock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockAccess {
+    pub address: PciAddress,
+    pub register_offset: u16,
+    /// In bytes
+    pub len: u8,
+    pub value: u64,
+    pub is_write: bool,
+}
+
+/// The config space of 1 function. Registers are little-endian.
+pub struct MockFunction {
+    address: PciAddress,
+    bytes: Box<[u8; 0x1000]>,
+    /// The bits that a write changes
+    writable: Box<[u8; 0x1000]>,
+    /// The bits that are cleared by writing 1 (RW1C)
+    write_1_to_clear: Box<[u8; 0x1000]>,
+    completion_flag: Option<CompletionFlag>,
+}
+
+/// Bits that the function sets some time after the register is written, like the VPD F flag
+#[derive(Debug, Clone, Copy)]
+struct CompletionFlag {
+    register_offset: u16,
+    mask: u16,
+    polls: usize,
+    polls_left: usize,
+}
+
+impl MockFunction {
+    fn new(address: PciAddress) -> Self {
+        let mut function = Self {
+            address,
+            bytes: Box::new([0; 0x1000]),
+            writable: Box::new([0xFF; 0x1000]),
+            write_1_to_clear: Box::new([0; 0x1000]),
+            completion_flag: None,
+        };
+        // IDs, revision, class code, and header type
+        function
+            .read_only(0x0, 4)
+            .read_only(0x8, 4)
+            .read_only(0xE, 1);
+        // Status is read-only, except for the error bits, which are RW1C
+        function
+            .read_only(0x6, 2)
+            .write_1_to_clear_u16(0x6, StatusRegister::ERROR_BITS);
+        // BARs that aren't implemented are hardwired to 0
+        function.read_only(0x10, 0x18);
+        function
+    }
+
+    /// Sets the register without going through the write masks
+    pub fn set_u8(&mut self, register_offset: u16, value: u8) -> &mut Self {
+        self.bytes[register_offset as usize] = value;
+        self
+    }
+
+    /// See [`Self::set_u8`]
+    pub fn set_u16(&mut self, register_offset: u16, value: u16) -> &mut Self {
+        self.set_bytes(register_offset, &value.to_le_bytes())
+    }
+
+    /// See [`Self::set_u8`]
+    pub fn set_u32(&mut self, register_offset: u16, value: u32) -> &mut Self {
+        self.set_bytes(register_offset, &value.to_le_bytes())
+    }
+
+    /// See [`Self::set_u8`]
+    pub fn set_bytes(&mut self, register_offset: u16, bytes: &[u8]) -> &mut Self {
+        let start = register_offset as usize;
+        self.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub fn u8(&self, register_offset: u16) -> u8 {
+        self.bytes[register_offset as usize]
+    }
+
+    pub fn u16(&self, register_offset: u16) -> u16 {
+        u16::from_le_bytes(self.array(register_offset))
+    }
+
+    pub fn u32(&self, register_offset: u16) -> u32 {
+        u32::from_le_bytes(self.array(register_offset))
+    }
+
+    pub fn bytes(&self) -> &[u8; 0x1000] {
+        &self.bytes
+    }
+
+    fn array<const N: usize>(&self, register_offset: u16) -> [u8; N] {
+        let start = register_offset as usize;
+        self.bytes[start..start + N].try_into().unwrap()
+    }
+
+    /// Makes writes to the bytes do nothing
+    pub fn read_only(&mut self, register_offset: u16, len: u16) -> &mut Self {
+        self.writable_u8s(register_offset, len, 0)
+    }
+
+    /// Sets which bits of each byte writes change
+    pub fn writable_u8s(&mut self, register_offset: u16, len: u16, mask: u8) -> &mut Self {
+        let start = register_offset as usize;
+        self.writable[start..start + len as usize].fill(mask);
+        self
+    }
+
+    /// Sets which bits of the register writes change
+    pub fn writable_u32(&mut self, register_offset: u16, mask: u32) -> &mut Self {
+        let start = register_offset as usize;
+        self.writable[start..start + 4].copy_from_slice(&mask.to_le_bytes());
+        self
+    }
+
+    /// Makes the bits RW1C. They can only be cleared, by writing 1 to them.
+    pub fn write_1_to_clear_u16(&mut self, register_offset: u16, mask: u16) -> &mut Self {
+        let start = register_offset as usize;
+        for (i, byte) in mask.to_le_bytes().into_iter().enumerate() {
+            self.write_1_to_clear[start + i] = byte;
+            self.writable[start + i] &= !byte;
+        }
+        self
+    }
+
+    /// After a write to the register, the bits read as 0 until the register has been read `polls` times, and then they read as 1.
+    /// Use `usize::MAX` for a flag that never gets set.
+    pub fn completion_flag_u16(
+        &mut self,
+        register_offset: u16,
+        mask: u16,
+        polls: usize,
+    ) -> &mut Self {
+        self.completion_flag = Some(CompletionFlag {
+            register_offset,
+            mask,
+            polls,
+            polls_left: 0,
+        });
+        self
+    }
+
+    /// Sets the vendor ID, device ID, class, and header type. Bridges get their BAR registers and bus numbers set up too.
+    pub fn header(
+        &mut self,
+        vendor_id: u16,
+        device_id: u16,
+        class: [u8; 3],
+        header_type: u8,
+    ) -> &mut Self {
+        let [class_code, sub_class, prog_if] = class;
+        self.set_u16(0x0, vendor_id)
+            .set_u16(0x2, device_id)
+            .set_bytes(0x9, &[prog_if, sub_class, class_code])
+            .set_u8(0xE, header_type);
+        match header_type & 0x7F {
+            0x0 => self.read_only(0x10, 0x18),
+            0x1 => self.read_only(0x10, 0x8).writable_u8s(0x18, 0x10, 0xFF),
+            _ => self.writable_u8s(0x10, 0x18, 0xFF),
+        }
+    }
+
+    /// A 32-bit memory BAR. `size` must be a power of 2 of at least 16 bytes.
+    pub fn memory_bar_32(
+        &mut self,
+        bar_index: u8,
+        addr: u32,
+        size: u32,
+        prefetchable: bool,
+    ) -> &mut Self {
+        let register_offset = 0x10 + 4 * bar_index as u16;
+        self.set_u32(register_offset, addr | (prefetchable as u32) << 3)
+            .writable_u32(register_offset, !(size - 1))
+    }
+
+    /// A 64-bit memory BAR in 2 slots. `size` must be a power of 2 of at least 16 bytes.
+    pub fn memory_bar_64(
+        &mut self,
+        bar_index: u8,
+        addr: u64,
+        size: u64,
+        prefetchable: bool,
+    ) -> &mut Self {
+        let register_offset = 0x10 + 4 * bar_index as u16;
+        let size_mask = !(size - 1);
+        self.set_u32(
+            register_offset,
+            addr as u32 | 0b100 | (prefetchable as u32) << 3,
+        )
+        .writable_u32(register_offset, size_mask as u32 & !0b1111)
+        .set_u32(register_offset + 4, (addr >> 32) as u32)
+        .writable_u32(register_offset + 4, (size_mask >> 32) as u32)
+    }
+
+    /// An I/O BAR. `size` must be a power of 2 of at least 4 bytes.
+    pub fn io_bar(&mut self, bar_index: u8, addr: u32, size: u32) -> &mut Self {
+        let register_offset = 0x10 + 4 * bar_index as u16;
+        self.set_u32(register_offset, addr | 0b1)
+            .writable_u32(register_offset, !(size - 1) & !0b11)
+    }
+
+    /// Adds a capability with the body (the bytes after the ID and next pointer) to the end of the capabilities list.
+    /// The ID and next pointer are read-only.
+    pub fn capability(&mut self, ptr: u8, id: u8, body: &[u8]) -> &mut Self {
+        let capabilities_ptr_offset = if self.u8(0xE) & 0x7F == 0x2 {
+            0x14
+        } else {
+            0x34
+        };
+        let status = self.u16(0x6) | 1 << 4;
+        self.set_u16(0x6, status);
+        let mut next_ptr_offset = capabilities_ptr_offset;
+        while self.u8(next_ptr_offset) != 0 {
+            next_ptr_offset = self.u8(next_ptr_offset) as u16 + 1;
+        }
+        self.set_u8(next_ptr_offset, ptr)
+            .set_bytes(ptr.into(), &[id, 0])
+            .set_bytes(ptr as u16 + 2, body)
+            .read_only(ptr.into(), 2)
+    }
+
+    /// Adds a PCI Express capability (version 2) at `0x40`, with the raw device/port type.
+    /// The control registers and the registers after Link Status are 0.
+    pub fn pci_express(
+        &mut self,
+        port_type: u8,
+        device_capabilities: u32,
+        device_status: u16,
+        link_capabilities: u32,
+        link_status: u16,
+    ) -> &mut Self {
+        let mut body = [0; 0x3A];
+        body[0x0..0x2].copy_from_slice(&(u16::from(port_type) << 4 | 0x2).to_le_bytes());
+        body[0x2..0x6].copy_from_slice(&device_capabilities.to_le_bytes());
+        body[0x8..0xA].copy_from_slice(&device_status.to_le_bytes());
+        body[0xA..0xE].copy_from_slice(&link_capabilities.to_le_bytes());
+        body[0x10..0x12].copy_from_slice(&link_status.to_le_bytes());
+        self.capability(0x40, 0x10, &body)
+    }
+
+    /// Adds an extended capability with the body (the bytes after the header) to the end of the extended capabilities list
+    pub fn extended_capability(
+        &mut self,
+        ptr: u16,
+        id: u16,
+        version: u8,
+        body: &[u8],
+    ) -> &mut Self {
+        if ptr != 0x100 {
+            let mut header_offset = 0x100;
+            while self.u32(header_offset) >> 20 != 0 {
+                header_offset = (self.u32(header_offset) >> 20) as u16;
+            }
+            let header = self.u32(header_offset) | (ptr as u32) << 20;
+            self.set_u32(header_offset, header);
+        }
+        self.set_u32(ptr, id as u32 | (version as u32) << 16)
+            .set_bytes(ptr + 4, body)
+            .read_only(ptr, 4)
+    }
+
+    fn read(&mut self, register_offset: u16, len: u8) -> u64 {
+        if let Some(flag) = self
+            .completion_flag
+            .as_mut()
+            .filter(|flag| flag.polls_left != 0 && register_offset == flag.register_offset)
+        {
+            flag.polls_left -= 1;
+            if flag.polls_left == 0 {
+                let (register_offset, mask) = (flag.register_offset, flag.mask);
+                self.set_u16(register_offset, self.u16(register_offset) | mask);
+            }
+        }
+        let start = register_offset as usize;
+        let mut bytes = [0; 8];
+        bytes[..len as usize].copy_from_slice(&self.bytes[start..start + len as usize]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn write(&mut self, register_offset: u16, len: u8, value: u64) {
+        let start = register_offset as usize;
+        for (i, value) in value.to_le_bytes()[..len as usize].iter().enumerate() {
+            let (old, writable, write_1_to_clear) = (
+                self.bytes[start + i],
+                self.writable[start + i],
+                self.write_1_to_clear[start + i],
+            );
+            self.bytes[start + i] =
+                (old & !writable & !(write_1_to_clear & value)) | (value & writable);
+        }
+        if let Some(flag) = self
+            .completion_flag
+            .as_mut()
+            .filter(|flag| register_offset == flag.register_offset)
+        {
+            flag.polls_left = flag.polls;
+            let (register_offset, mask) = (flag.register_offset, flag.mask);
+            self.set_u16(register_offset, self.u16(register_offset) & !mask);
+        }
+    }
+}
+
+impl Debug for MockFunction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MockFunction")
+            .field("address", &format_args!("{}", self.address))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct MockConfig {
+    kind: AccessKind,
+    functions: Vec<MockFunction>,
+    log: Vec<MockAccess>,
+    /// The buses of segment group 0 that the mock says it covers, like an ECAM window
+    ecam_buses: RangeInclusive<u8>,
+    pub(super) discovered_buses: BusBitmap,
+    pub(super) extended_config_override: Option<fn(u16, u16) -> ExtendedConfigPolicy>,
+}
+
+impl MockConfig {
+    /// With [`AccessKind::LegacyPortIo`], offsets of `0x100` and above read all ones, and only bus 0 is known until [`PciAccess::discover`] is called.
+    /// With [`AccessKind::Ecam`], buses `0..=0` are known, which can be changed with [`Self::with_ecam_buses`].
+    pub fn new(kind: AccessKind) -> Self {
+        Self {
+            kind,
+            functions: Vec::new(),
+            log: Vec::new(),
+            ecam_buses: 0..=0,
+            discovered_buses: BusBitmap::BUS_0,
+            extended_config_override: None,
+        }
+    }
+
+    pub fn with_ecam_buses(mut self, ecam_buses: RangeInclusive<u8>) -> Self {
+        self.ecam_buses = ecam_buses;
+        self
+    }
+
+    /// The function, which is added if it isn't present yet
+    pub fn function(&mut self, address: PciAddress) -> &mut MockFunction {
+        match self
+            .functions
+            .iter()
+            .position(|function| function.address == address)
+        {
+            Some(index) => &mut self.functions[index],
+            None => {
+                self.functions.push(MockFunction::new(address));
+                self.functions.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Makes the function not present, like it was hot-unplugged
+    pub fn remove_function(&mut self, address: PciAddress) {
+        self.functions
+            .retain(|function| function.address != address);
+    }
+
+    pub fn into_pci_access(self) -> PciAccess {
+        PciAccess::Mock(self)
+    }
+
+    pub fn kind(&self) -> AccessKind {
+        self.kind
+    }
+
+    pub fn ecam_buses(&self) -> RangeInclusive<u8> {
+        self.ecam_buses.clone()
+    }
+
+    pub fn log(&self) -> &[MockAccess] {
+        &self.log
+    }
+
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+    }
+
+    pub fn writes(&self) -> impl Iterator<Item = &MockAccess> {
+        self.log.iter().filter(|access| access.is_write)
+    }
+
+    pub fn read_count(&self) -> usize {
+        self.log.iter().filter(|access| !access.is_write).count()
+    }
+
+    /// Whether any write touched the byte
+    pub fn wrote_to(&self, address: PciAddress, register_offset: u16) -> bool {
+        self.writes().any(|access| {
+            access.address == address
+                && (access.register_offset..access.register_offset + access.len as u16)
+                    .contains(&register_offset)
+        })
+    }
+
+    fn is_accessible(&self, address: PciAddress, register_offset: u16) -> bool {
+        match self.kind {
+            AccessKind::LegacyPortIo => address.segment == 0 && register_offset < 0x100,
+            AccessKind::Ecam => address.segment == 0 && self.ecam_buses.contains(&address.bus),
+        }
+    }
+
+    pub(super) fn read(&mut self, address: PciAddress, register_offset: u16, len: u8) -> u64 {
+        let is_accessible = self.is_accessible(address, register_offset);
+        let value = self
+            .functions
+            .iter_mut()
+            .find(|function| function.address == address)
+            .filter(|_| is_accessible)
+            .map_or(u64::MAX >> (64 - 8 * len as u32), |function| {
+                function.read(register_offset, len)
+            });
+        self.log.push(MockAccess {
+            address,
+            register_offset,
+            len,
+            value,
+            is_write: false,
+        });
+        value
+    }
+
+    pub(super) fn write(&mut self, address: PciAddress, register_offset: u16, len: u8, value: u64) {
+        self.log.push(MockAccess {
+            address,
+            register_offset,
+            len,
+            value,
+            is_write: true,
+        });
+        if !self.is_accessible(address, register_offset) {
+            return;
+        }
+        if let Some(function) = self
+            .functions
+            .iter_mut()
+            .find(|function| function.address == address)
+        {
+            function.write(register_offset, len, value);
+        }
+    }
+}
+
+impl PciAccess {
+    /// The mock that this was created from
+    ///
+    /// # Panics
+    /// Panics if this is not [`PciAccess::Mock`]
+    pub fn mock(&mut self) -> &mut MockConfig {
+        match self {
+            Self::Mock(mock) => mock,
+            #[allow(unreachable_patterns)]
+            _ => panic!("Not a mock"),
+        }
+    }
+}
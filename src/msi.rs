@@ -1,141 +1,129 @@
 use core::fmt::Debug;
 
 use bitfield::bitfield;
+#[cfg(feature = "x86")]
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use super::*;
 
+/// The message control register is read once in [`PciFunction::msi`] and cached.
+/// Use [`Self::refresh`] if you think it changed.
 pub struct Msi<'a> {
     pci: &'a mut PciAccess,
-    bus_number: u8,
-    device_number: u8,
-    function_number: u8,
-    ptr: u8,
+    address: PciAddress,
+    ptr: u16,
+    message_control: MessageControlRegister,
 }
 
 impl<'a> Msi<'a> {
     pub(super) fn find(function: &'a mut PciFunction) -> Option<Option<Self>> {
-        if let Some(capability) = function
-            .capabilities()?
-            .find(|capability| capability.id == 0x5)
-        {
-            Some(Some(Self {
+        Some(function.find_capability(0x5)?.map(|capability| {
+            let ptr = capability.ptr_to_self.into();
+            Self {
+                message_control: MessageControlRegister(
+                    function.pci.read_u16(function.address, ptr + 0x2),
+                ),
                 pci: function.pci,
-                bus_number: function.bus_number,
-                device_number: function.device_number,
-                function_number: function.function_number,
-                ptr: capability.ptr_to_self,
-            }))
-        } else {
-            Some(None)
+                address: function.address,
+                ptr,
+            }
+        }))
+    }
+
+    /// Creates the accessor from an offset that you got from [`Self::offset`], without walking the capabilities list.
+    /// This does 1 config read, which checks that the capability at the offset is MSI and reads the message control register.
+    ///
+    /// Returns `None` if the capability at the offset is not MSI.
+    #[must_use]
+    pub fn at_offset(pci: &'a mut PciAccess, address: PciAddress, offset: u8) -> Option<Self> {
+        if offset < 0x40 || !offset.is_multiple_of(4) {
+            return None;
         }
+        let ptr = offset.into();
+        let header = pci.read_u32(address, ptr);
+        (header as u8 == 0x5).then_some(Self {
+            pci,
+            address,
+            ptr,
+            message_control: MessageControlRegister((header >> 16) as u16),
+        })
+    }
+
+    /// The offset of the capability in the function's config space. You can store this and use it with [`Self::at_offset`] later.
+    #[must_use]
+    pub fn offset(&self) -> u8 {
+        self.ptr as u8
     }
 
-    pub fn get_message_control(&mut self) -> MessageControlRegister {
-        MessageControlRegister(self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x2,
-        ))
+    /// Re-reads the cached message control register from the function
+    pub fn refresh(&mut self) {
+        self.message_control =
+            MessageControlRegister(self.pci.read_u16(self.address, self.ptr + 0x2));
+    }
+
+    #[must_use]
+    pub fn get_message_control(&self) -> MessageControlRegister {
+        self.message_control
     }
 
     pub fn set_message_control(&mut self, message_control_register: MessageControlRegister) {
-        self.pci.write_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x2,
-            message_control_register.0,
-        )
+        self.pci
+            .write_u16(self.address, self.ptr + 0x2, message_control_register.0);
+        self.message_control = message_control_register;
     }
 
     #[deprecated = "You might misinterpret the address if 64-bit message address is supported"]
+    #[must_use]
     pub fn get_message_addr_u32(&mut self) -> u32 {
-        self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x4,
-        )
+        self.pci.read_u32(self.address, self.ptr + 0x4)
     }
 
     #[deprecated = "If 64-bit message address is supported and upper bits are not 0, then the effective address will be one that you didn't expect"]
     pub fn set_message_addr_u32(&mut self, addr: u32) {
-        self.pci.write_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x4,
-            addr,
-        );
+        self.pci.write_u32(self.address, self.ptr + 0x4, addr);
     }
 
     /// Remember to check the message control register to see if a 64-bit message address is supported.
     #[deprecated = "You might accidentally read the wrong register if 64-bit message address is not supported. Open an issue if you need to get an address that's >u32::MAX."]
+    #[must_use]
     pub fn get_message_addr_u64(&mut self) -> u64 {
-        let low = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x4,
-        );
-        let high = self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x8,
-        );
+        let low = self.pci.read_u32(self.address, self.ptr + 0x4);
+        let high = self.pci.read_u32(self.address, self.ptr + 0x8);
         low as u64 | ((high as u64) << 32)
     }
 
     /// Remember to check the message control register to see if a 64-bit message address is supported.
     #[deprecated = "You might accidentally read the wrong register if 64-bit message address is not supported. Open an issue if you need to set an address to be >u32::MAX."]
     pub fn set_message_addr_u64(&mut self, addr: u64) {
-        self.pci.write_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x4,
-            addr as u32,
-        );
-        self.pci.write_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x8,
-            (addr >> 32) as u32,
-        );
+        self.pci
+            .write_u32(self.address, self.ptr + 0x4, addr as u32);
+        self.pci
+            .write_u32(self.address, self.ptr + 0x8, (addr >> 32) as u32);
     }
 
     /// Sets the address to a u32 address. This will work whether 64 bit addresses are supported or not.
     pub fn set_message_addr(&mut self, addr: u32) {
         if self.get_message_control().supports_64_bit_addresses() {
-            self.pci.write_u32(
-                self.bus_number,
-                self.device_number,
-                self.function_number,
-                self.ptr + 0x4,
-                addr,
-            );
-            self.pci.write_u32(
-                self.bus_number,
-                self.device_number,
-                self.function_number,
-                self.ptr + 0x8,
-                0,
-            );
+            self.pci.write_u32(self.address, self.ptr + 0x4, addr);
+            self.pci.write_u32(self.address, self.ptr + 0x8, 0);
         } else {
-            self.pci.write_u32(
-                self.bus_number,
-                self.device_number,
-                self.function_number,
-                self.ptr + 0x4,
-                addr,
-            );
+            self.pci.write_u32(self.address, self.ptr + 0x4, addr);
         }
     }
 
-    fn get_message_data_offset(&mut self) -> u8 {
+    /// Like [`Self::set_message_addr`], but reads back each register after writing it. See [`PciFunction::write_config_u32_verified`].
+    pub fn set_message_addr_verified(&mut self, addr: u32) -> Result<(), WriteVerifyError> {
+        // The lower 2 bits of the address are reserved
+        self.pci
+            .write_u32_verified(self.address, self.ptr + 0x4, addr, !0b11)?;
+        if self.get_message_control().supports_64_bit_addresses() {
+            self.pci
+                .write_u32_verified(self.address, self.ptr + 0x8, 0, u32::MAX)?;
+        }
+        Ok(())
+    }
+
+    fn get_message_data_offset(&self) -> u16 {
         if self.get_message_control().supports_64_bit_addresses() {
             0xC
         } else {
@@ -143,44 +131,189 @@ impl<'a> Msi<'a> {
         }
     }
 
+    #[must_use]
     pub fn get_message_data(&mut self) -> u16 {
         let message_data_offset = self.get_message_data_offset();
-        self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + message_data_offset,
-        )
+        self.pci
+            .read_u16(self.address, self.ptr + message_data_offset)
     }
 
     /// Note that if you enable multiple interrupts in the message control register, the PCI function will override the lowest N bits of the message data when writing the message data to the message address.
     /// This effectively lets you assign multiple interrupt vectors to a PCI function.
     /// This is useful for balancing interrupts between multiple CPUs.
     /// If you only want the PCI function to send interrupts to 1 interrupt vector, make sure to set the `multiple_message_enable` to `0b000`.
+    ///
+    /// This only writes the lower 16 bits, so the extended message data is not changed.
     pub fn set_message_data(&mut self, message_data: u16) {
         let message_data_offset = self.get_message_data_offset();
-        self.pci.write_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + message_data_offset,
-            message_data,
-        )
+        self.pci
+            .write_u16(self.address, self.ptr + message_data_offset, message_data)
+    }
+
+    /// The message data with the extended message data in the upper 16 bits.
+    /// The upper 16 bits are only used by the function if extended message data is enabled.
+    #[must_use]
+    pub fn get_message_data_u32(&mut self) -> u32 {
+        let message_data_offset = self.get_message_data_offset();
+        self.pci
+            .read_u32(self.address, self.ptr + message_data_offset)
+    }
+
+    /// Enables or disables 32-bit message data.
+    /// Enable this before writing the extended message data with [`Self::set_message_data_u32`].
+    pub fn set_extended_message_data_enabled(
+        &mut self,
+        enabled: bool,
+    ) -> Result<(), ExtendedMessageDataError> {
+        let mut message_control = self.get_message_control();
+        if !message_control.extended_message_data_capable() {
+            return Err(ExtendedMessageDataError::NotCapable);
+        }
+        message_control.set_extended_message_data_enable(enabled);
+        self.set_message_control(message_control);
+        Ok(())
+    }
+
+    /// Writes the message data and the extended message data (the upper 16 bits).
+    /// Extended message data must be enabled first with [`Self::set_extended_message_data_enabled`].
+    pub fn set_message_data_u32(
+        &mut self,
+        message_data: u32,
+    ) -> Result<(), ExtendedMessageDataError> {
+        let message_control = self.get_message_control();
+        if !message_control.extended_message_data_capable() {
+            return Err(ExtendedMessageDataError::NotCapable);
+        }
+        if !message_control.extended_message_data_enable() {
+            return Err(ExtendedMessageDataError::NotEnabled);
+        }
+        let message_data_offset = self.get_message_data_offset();
+        self.pci
+            .write_u32(self.address, self.ptr + message_data_offset, message_data);
+        Ok(())
+    }
+
+    /// Clears the enable bit, so that the function stops sending MSI interrupts
+    pub fn disable(&mut self) {
+        let mut message_control = self.get_message_control();
+        message_control.set_enable(false);
+        self.set_message_control(message_control);
+    }
+
+    /// Enables `count` vectors, rounded up to a power of 2.
+    /// Returns the number of vectors that were actually enabled.
+    /// Use [`MessageControlRegister::set_enabled_vectors_exact`] if you don't want rounding.
+    pub fn set_enabled_vectors(&mut self, count: u8) -> Result<u8, MsiVectorError> {
+        let mut message_control = self.get_message_control();
+        let max_vectors = message_control.max_vectors();
+        if count == 0 {
+            return Err(MsiVectorError::Zero);
+        }
+        if count > max_vectors {
+            return Err(MsiVectorError::MoreThanCapable { max_vectors });
+        }
+        let count = count.next_power_of_two();
+        message_control.set_multiple_message_enable(count.trailing_zeros() as u8);
+        self.set_message_control(message_control);
+        Ok(count)
+    }
+
+    /// Changes the message address and data without the function sending an interrupt with a mix of the old and new values.
+    ///
+    /// If [`MessageControlRegister::per_message_masking`] is supported, all vectors are masked during the update and then the previous mask is restored.
+    /// Otherwise, MSI is disabled during the update and then re-enabled if it was enabled.
+    /// While MSI is disabled, the function may use legacy INTx interrupts instead, unless you set [`CommandRegister::interrupt_disable`].
+    pub fn reconfigure(&mut self, addr: u32, message_data: u16) {
+        let message_control = self.get_message_control();
+        if message_control.per_message_masking() {
+            // The mask bits register is right after the message data register
+            let mask_offset = self.ptr + self.get_message_data_offset() + 0x4;
+            let mask = self.pci.read_u32(self.address, mask_offset);
+            self.pci.write_u32(self.address, mask_offset, u32::MAX);
+            self.set_message_addr(addr);
+            self.set_message_data(message_data);
+            self.pci.write_u32(self.address, mask_offset, mask);
+        } else {
+            let enabled = message_control.enable();
+            let mut disabled = MessageControlRegister(message_control.0);
+            disabled.set_enable(false);
+            self.set_message_control(disabled);
+            self.set_message_addr(addr);
+            self.set_message_data(message_data);
+            let mut restored = MessageControlRegister(message_control.0);
+            restored.set_enable(enabled);
+            self.set_message_control(restored);
+        }
+    }
+}
+
+impl Msi<'_> {
+    /// Reads and decodes the whole capability, so that you can print it with `{:#?}`
+    #[must_use]
+    pub fn debug_snapshot(&mut self) -> MsiSnapshot {
+        let message_control = self.get_message_control();
+        let low = self.pci.read_u32(self.address, self.ptr + 0x4);
+        let high = if message_control.supports_64_bit_addresses() {
+            self.pci.read_u32(self.address, self.ptr + 0x8)
+        } else {
+            0
+        };
+        MsiSnapshot {
+            message_control,
+            message_address: low as u64 | (high as u64) << 32,
+            message_data: self.get_message_data(),
+        }
+    }
+}
+
+/// See [`Msi::debug_snapshot`]
+#[derive(Clone, Copy)]
+pub struct MsiSnapshot {
+    pub message_control: MessageControlRegister,
+    pub message_address: u64,
+    pub message_data: u16,
+}
+
+impl Debug for MsiSnapshot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MsiSnapshot")
+            .field("enable", &self.message_control.enable())
+            .field(
+                "supports_64_bit_addresses",
+                &self.message_control.supports_64_bit_addresses(),
+            )
+            .field("max_vectors", &self.message_control.max_vectors())
+            .field("enabled_vectors", &self.message_control.enabled_vectors())
+            .field(
+                "per_message_masking",
+                &self.message_control.per_message_masking(),
+            )
+            .field(
+                "message_address",
+                &format_args!("0x{:X}", self.message_address),
+            )
+            .field("message_data", &format_args!("0x{:X}", self.message_data))
+            .finish()
     }
 }
 
 impl Debug for Msi<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("MSI")
+            .field("address", &format_args!("{}", self.address))
             .field("ptr", &format_args!("0x{:X}", self.ptr))
             .finish()
     }
 }
 
 bitfield! {
+    #[derive(Clone, Copy)]
     pub struct MessageControlRegister(u16);
     impl Debug;
 
+    /// If this is 1, you can enable extended message data, which makes the message data 32 bits instead of 16 bits.
+    pub extended_message_data_capable, _: 9;
+    pub extended_message_data_enable, set_extended_message_data_enable: 10;
     /// If this is 1, you can use the MSI mask and pending registers.
     pub per_message_masking, _: 8;
     /// If this is 1, you can set the message address to a 64 bit address.
@@ -191,8 +324,57 @@ bitfield! {
     pub enable, set_enable: 0;
 }
 
+impl MessageControlRegister {
+    /// The number of vectors that the function supports, decoded from [`Self::multiple_message_capable`]
+    pub fn max_vectors(&self) -> u8 {
+        1 << self.multiple_message_capable().min(5)
+    }
+
+    /// The number of vectors that are enabled, decoded from [`Self::multiple_message_enable`]
+    pub fn enabled_vectors(&self) -> u8 {
+        1 << self.multiple_message_enable().min(5)
+    }
+
+    /// Encodes `count` into [`Self::set_multiple_message_enable`].
+    /// Unlike [`Msi::set_enabled_vectors`], this doesn't round up, so `count` must be a power of 2.
+    pub fn set_enabled_vectors_exact(&mut self, count: u8) -> Result<(), MsiVectorError> {
+        if count == 0 {
+            return Err(MsiVectorError::Zero);
+        }
+        if !count.is_power_of_two() {
+            return Err(MsiVectorError::NotPowerOfTwo);
+        }
+        let max_vectors = self.max_vectors();
+        if count > max_vectors {
+            return Err(MsiVectorError::MoreThanCapable { max_vectors });
+        }
+        self.set_multiple_message_enable(count.trailing_zeros() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MsiVectorError {
+    /// At least 1 vector must be enabled
+    Zero,
+    /// The function doesn't support this many vectors
+    MoreThanCapable { max_vectors: u8 },
+    /// The number of vectors must be a power of 2
+    NotPowerOfTwo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExtendedMessageDataError {
+    /// [`MessageControlRegister::extended_message_data_capable`] is 0
+    NotCapable,
+    /// Extended message data must be enabled before writing it
+    NotEnabled,
+}
+
+#[cfg(feature = "x86")]
 bitfield! {
     /// See Intel SDM -> Volume 3 -> 12.11.1 Message Address Register Format
+    #[derive(Clone, Copy)]
     pub struct ApicMsiMessageAddress(u32);
     impl Debug;
 
@@ -213,6 +395,7 @@ bitfield! {
     destination_mode, set_destination_mode: 2;
 }
 
+#[cfg(feature = "x86")]
 impl Default for ApicMsiMessageAddress {
     fn default() -> Self {
         let mut address = Self(0);
@@ -221,8 +404,36 @@ impl Default for ApicMsiMessageAddress {
     }
 }
 
+#[cfg(feature = "x86")]
+impl ApicMsiMessageAddress {
+    /// An address that sends the interrupt to the local APIC with the ID
+    pub fn new(destination_id: u8) -> Self {
+        let mut address = Self::default();
+        address.set_destination_id(destination_id);
+        address
+    }
+}
+
+/// For MSI, use [`Msi::set_message_addr`]
+#[cfg(feature = "x86")]
+impl From<ApicMsiMessageAddress> for u32 {
+    fn from(address: ApicMsiMessageAddress) -> Self {
+        address.0
+    }
+}
+
+/// For MSI-X, use [`MsiXTable::reconfigure_entry`]
+#[cfg(feature = "x86")]
+impl From<ApicMsiMessageAddress> for u64 {
+    fn from(address: ApicMsiMessageAddress) -> Self {
+        address.0.into()
+    }
+}
+
+#[cfg(feature = "x86")]
 bitfield! {
     /// See Intel SDM -> Volume 3 -> 12.11.2 Message Data Register Format
+    #[derive(Clone, Copy)]
     pub struct ApicMsiMessageData(u16);
     impl Debug;
 
@@ -231,3 +442,366 @@ bitfield! {
     u8; pub delivery_mode, set_delivery_mode: 10, 8;
     u8; pub vector, set_vector: 7, 0;
 }
+
+#[cfg(feature = "x86")]
+impl ApicMsiMessageData {
+    pub fn new(vector: u8, trigger: TriggerMode, delivery: DeliveryMode) -> Self {
+        let mut data = Self(0);
+        data.set_vector(vector);
+        data.set_trigger(trigger);
+        data.set_delivery_mode_enum(delivery);
+        data
+    }
+
+    /// Like [`Self::set_vector`], but returns an error for vectors below 16.
+    /// Vectors 0-15 are used by the CPU for exceptions, so the local APIC treats an interrupt with one of them as illegal and it never arrives.
+    pub fn set_vector_checked(&mut self, vector: u8) -> Result<(), InvalidVector> {
+        if vector < 16 {
+            return Err(InvalidVector { vector });
+        }
+        self.set_vector(vector);
+        Ok(())
+    }
+
+    pub fn trigger(&self) -> TriggerMode {
+        if self.trigger_mode() {
+            TriggerMode::Level
+        } else {
+            TriggerMode::Edge
+        }
+    }
+
+    pub fn set_trigger(&mut self, trigger: TriggerMode) {
+        let level = trigger == TriggerMode::Level;
+        self.set_trigger_mode(level);
+        // For level-triggered interrupts, the message asserts the interrupt
+        self.set_trigger_mode_level(level);
+    }
+
+    /// Returns `None` if the delivery mode is reserved. Use [`Self::delivery_mode`] to get the raw value.
+    pub fn delivery_mode_enum(&self) -> Option<DeliveryMode> {
+        DeliveryMode::try_from(self.delivery_mode()).ok()
+    }
+
+    /// Unlike [`Self::set_delivery_mode`], this can't write a reserved delivery mode
+    pub fn set_delivery_mode_enum(&mut self, delivery: DeliveryMode) {
+        self.set_delivery_mode(delivery.into());
+    }
+}
+
+/// For MSI, use [`Msi::set_message_data`]
+#[cfg(feature = "x86")]
+impl From<ApicMsiMessageData> for u16 {
+    fn from(data: ApicMsiMessageData) -> Self {
+        data.0
+    }
+}
+
+/// For MSI-X, use [`MsiXTable::reconfigure_entry`]
+#[cfg(feature = "x86")]
+impl From<ApicMsiMessageData> for u32 {
+    fn from(data: ApicMsiMessageData) -> Self {
+        data.0.into()
+    }
+}
+
+/// The vector is below 16, so it can't be used for an interrupt
+#[cfg(feature = "x86")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InvalidVector {
+    pub vector: u8,
+}
+
+#[cfg(feature = "x86")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TriggerMode {
+    #[default]
+    Edge,
+    Level,
+}
+
+/// See Intel SDM -> Volume 3 -> 12.11.2 Message Data Register Format.
+/// The other values are reserved. Convert from and to the raw value with [`TryFrom<u8>`] and [`Into<u8>`].
+#[cfg(feature = "x86")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    LowestPriority = 0b001,
+    Smi = 0b010,
+    Nmi = 0b100,
+    Init = 0b101,
+    ExtInt = 0b111,
+}
+
+/// `num_enum` would convert reserved values to the `#[default]` variant, so this can't be derived
+#[cfg(feature = "x86")]
+impl Default for DeliveryMode {
+    fn default() -> Self {
+        Self::Fixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 5,
+        function: 0,
+    };
+
+    /// A function with an MSI capability at `0x60`
+    fn mock_msi(message_control: u16, f: impl FnOnce(&mut MockFunction)) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        let mut body = [0; 22];
+        body[..2].copy_from_slice(&message_control.to_le_bytes());
+        f(mock
+            .function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .capability(0x60, 0x5, &body));
+        mock.into_pci_access()
+    }
+
+    /// The offset, length, and value of each write
+    fn writes(pci: &mut PciAccess) -> Vec<(u16, u8, u64)> {
+        pci.mock()
+            .writes()
+            .map(|access| (access.register_offset, access.len, access.value))
+            .collect()
+    }
+
+    #[test]
+    fn reconfigure_with_per_vector_masking() {
+        // Enabled, 64-bit, per-vector masking
+        let mut pci = mock_msi(1 << 8 | 1 << 7 | 1, |function| {
+            function.set_u32(0x70, 0b10);
+        });
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        function
+            .msi()
+            .unwrap()
+            .unwrap()
+            .reconfigure(0xFEE0_1000, 0x41);
+        assert_eq!(
+            writes(&mut pci),
+            [
+                (0x70, 4, 0xFFFF_FFFF),
+                (0x64, 4, 0xFEE0_1000),
+                (0x68, 4, 0),
+                (0x6C, 2, 0x41),
+                (0x70, 4, 0b10),
+            ]
+        );
+        let function = pci.mock().function(ADDRESS);
+        assert_eq!(function.u16(0x62), 1 << 8 | 1 << 7 | 1);
+        assert_eq!(function.u32(0x70), 0b10);
+    }
+
+    #[test]
+    fn reconfigure_without_per_vector_masking() {
+        // Enabled, 32-bit, no per-vector masking
+        let mut pci = mock_msi(1, |_| {});
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        function
+            .msi()
+            .unwrap()
+            .unwrap()
+            .reconfigure(0xFEE0_2000, 0x42);
+        assert_eq!(
+            writes(&mut pci),
+            [
+                (0x62, 2, 0),
+                (0x64, 4, 0xFEE0_2000),
+                (0x68, 2, 0x42),
+                (0x62, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn reconfigure_while_disabled_stays_disabled() {
+        let mut pci = mock_msi(0, |_| {});
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        function
+            .msi()
+            .unwrap()
+            .unwrap()
+            .reconfigure(0xFEE0_2000, 0x42);
+        assert_eq!(pci.mock().function(ADDRESS).u16(0x62), 0);
+        assert_eq!(pci.mock().function(ADDRESS).u32(0x64), 0xFEE0_2000);
+    }
+
+    #[test]
+    fn set_message_addr_verified() {
+        // 64-bit
+        let mut pci = mock_msi(1 << 7, |function| {
+            function.set_u32(0x68, 0xFFFF_FFFF).read_only(0x64, 1);
+        });
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        let mut msi = function.msi().unwrap().unwrap();
+        // The reserved bits aren't compared
+        assert_eq!(msi.set_message_addr_verified(0xFEE0_1000), Ok(()));
+        assert_eq!(
+            msi.set_message_addr_verified(0xFEE0_1020),
+            Err(WriteVerifyError {
+                address: ADDRESS,
+                register_offset: 0x64,
+                wrote: 0xFEE0_1020,
+                read_back: 0xFEE0_1000,
+            })
+        );
+        assert_eq!(pci.mock().function(ADDRESS).u32(0x68), 0);
+    }
+
+    #[test]
+    fn set_enabled_vectors_exact() {
+        // Capable of 8 vectors
+        let mut message_control = MessageControlRegister(0b011 << 1);
+        assert_eq!(message_control.set_enabled_vectors_exact(4), Ok(()));
+        assert_eq!(message_control.multiple_message_enable(), 2);
+        assert_eq!(
+            message_control.set_enabled_vectors_exact(3),
+            Err(MsiVectorError::NotPowerOfTwo)
+        );
+        assert_eq!(
+            message_control.set_enabled_vectors_exact(0),
+            Err(MsiVectorError::Zero)
+        );
+        assert_eq!(
+            message_control.set_enabled_vectors_exact(16),
+            Err(MsiVectorError::MoreThanCapable { max_vectors: 8 })
+        );
+        // Failed calls don't change the register
+        assert_eq!(message_control.multiple_message_enable(), 2);
+    }
+
+    #[test]
+    fn set_enabled_vectors_rounds_up() {
+        let mut pci = mock_msi(0b011 << 1, |_| {});
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        let mut msi = function.msi().unwrap().unwrap();
+        assert_eq!(msi.set_enabled_vectors(3), Ok(4));
+        assert_eq!(msi.set_enabled_vectors(5), Ok(8));
+        assert_eq!(
+            msi.set_enabled_vectors(9),
+            Err(MsiVectorError::MoreThanCapable { max_vectors: 8 })
+        );
+        assert_eq!(
+            pci.mock().function(ADDRESS).u16(0x62),
+            0b011 << 4 | 0b011 << 1
+        );
+    }
+
+    #[test]
+    fn cached_message_control_is_only_read_once() {
+        // 64-bit
+        let mut pci = mock_msi(1 << 7, |_| {});
+        let mut msi = Msi::at_offset(&mut pci, ADDRESS, 0x60).unwrap();
+        assert!(msi.get_message_control().supports_64_bit_addresses());
+        msi.set_message_addr(0xFEE0_0000);
+        msi.set_message_data(0x30);
+        // The capability header, which has the message control register
+        assert_eq!(pci.mock().read_count(), 1);
+        assert_eq!(pci.mock().function(ADDRESS).u16(0x6C), 0x30);
+        Msi::at_offset(&mut pci, ADDRESS, 0x60).unwrap().refresh();
+        assert_eq!(pci.mock().read_count(), 1 + 1 + 1);
+    }
+
+    #[test]
+    fn at_offset_rejects_other_capabilities() {
+        let mut pci = mock_msi(0, |function| {
+            function.capability(0x80, 0x1, &[0; 6]);
+        });
+        assert!(Msi::at_offset(&mut pci, ADDRESS, 0x60).is_some());
+        // Power Management
+        assert!(Msi::at_offset(&mut pci, ADDRESS, 0x80).is_none());
+        // Not a capability pointer
+        assert!(Msi::at_offset(&mut pci, ADDRESS, 0x62).is_none());
+        assert!(Msi::at_offset(&mut pci, ADDRESS, 0x34).is_none());
+    }
+
+    #[test]
+    fn extended_message_data_register_image() {
+        // 64-bit and extended message data capable
+        let mut pci = mock_msi(1 << 9 | 1 << 7, |_| {});
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        let mut msi = function.msi().unwrap().unwrap();
+        assert_eq!(
+            msi.set_message_data_u32(0x1234_0041),
+            Err(ExtendedMessageDataError::NotEnabled)
+        );
+        assert_eq!(msi.set_extended_message_data_enabled(true), Ok(()));
+        msi.set_message_addr(0xFEE0_1000);
+        assert_eq!(msi.set_message_data_u32(0x1234_0041), Ok(()));
+        assert_eq!(msi.get_message_data_u32(), 0x1234_0041);
+        assert_eq!(
+            pci.mock().function(ADDRESS).bytes()[0x60..0x70],
+            [
+                0x05, 0x00, 0x80, 0x06, // Capability header and message control
+                0x00, 0x10, 0xE0, 0xFE, // Message address
+                0x00, 0x00, 0x00, 0x00, // Message upper address
+                0x41, 0x00, 0x34, 0x12, // Message data and extended message data
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_16_bit_message_data_register_image() {
+        // 32-bit, and not extended message data capable
+        let mut pci = mock_msi(0, |function| {
+            function.set_u16(0x6A, 0xBEEF);
+        });
+        let mut function = pci.function_at(0, 5, 0).unwrap();
+        let mut msi = function.msi().unwrap().unwrap();
+        assert_eq!(
+            msi.set_extended_message_data_enabled(true),
+            Err(ExtendedMessageDataError::NotCapable)
+        );
+        assert_eq!(
+            msi.set_message_data_u32(0x1234_0042),
+            Err(ExtendedMessageDataError::NotCapable)
+        );
+        msi.set_message_addr(0xFEE0_2000);
+        msi.set_message_data(0x42);
+        // The 2 bytes after the message data are not part of the capability, so they are not written
+        assert_eq!(
+            pci.mock().function(ADDRESS).bytes()[0x60..0x6C],
+            [
+                0x05, 0x00, 0x00, 0x00, // Capability header and message control
+                0x00, 0x20, 0xE0, 0xFE, // Message address
+                0x42, 0x00, 0xEF, 0xBE, // Message data
+            ]
+        );
+    }
+
+    #[cfg(feature = "x86")]
+    #[test]
+    fn delivery_mode_enum() {
+        for delivery in [
+            DeliveryMode::Fixed,
+            DeliveryMode::LowestPriority,
+            DeliveryMode::Smi,
+            DeliveryMode::Nmi,
+            DeliveryMode::Init,
+            DeliveryMode::ExtInt,
+        ] {
+            let data = ApicMsiMessageData::new(0x40, TriggerMode::Edge, delivery);
+            assert_eq!(data.delivery_mode(), u8::from(delivery));
+            assert_eq!(data.delivery_mode_enum(), Some(delivery));
+        }
+        let mut data = ApicMsiMessageData::new(0x40, TriggerMode::Level, DeliveryMode::Fixed);
+        for reserved in [0b011, 0b110] {
+            assert_eq!(DeliveryMode::try_from(reserved).ok(), None);
+            data.set_delivery_mode(reserved);
+            assert_eq!(data.delivery_mode_enum(), None);
+            assert_eq!(u16::from(data), 0xC040 | u16::from(reserved) << 8);
+        }
+        data.set_delivery_mode_enum(DeliveryMode::LowestPriority);
+        assert_eq!(u16::from(data), 0xC140);
+    }
+}
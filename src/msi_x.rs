@@ -1,6 +1,7 @@
 use core::{
     fmt::Debug,
     num::NonZero,
+    ops::Range,
     ptr::{NonNull, slice_from_raw_parts_mut},
 };
 
@@ -12,70 +13,221 @@ use volatile::{
 
 use super::*;
 
+/// The message control, table location, and PBA location registers are read once in [`PciFunction::msi_x`] and cached.
+/// Use [`Self::refresh`] if you think they changed.
 pub struct MsiX<'a> {
     pci: &'a mut PciAccess,
-    bus_number: u8,
-    device_number: u8,
-    function_number: u8,
-    ptr: u8,
+    address: PciAddress,
+    ptr: u16,
+    message_control: MsiXMessageControl,
+    table_location: MsiXLocation,
+    pba_location: MsiXLocation,
 }
 
 impl<'a> MsiX<'a> {
     pub(super) fn find(function: &'a mut PciFunction) -> Option<Option<Self>> {
-        if let Some(capability) = function
-            .capabilities()?
-            .find(|capability| capability.id == 0x11)
-        {
-            Some(Some(Self {
+        Some(function.find_capability(0x11)?.map(|capability| {
+            let mut msi_x = Self {
                 pci: function.pci,
-                bus_number: function.bus_number,
-                device_number: function.device_number,
-                function_number: function.function_number,
-                ptr: capability.ptr_to_self,
-            }))
-        } else {
-            Some(None)
+                address: function.address,
+                ptr: capability.ptr_to_self.into(),
+                message_control: MsiXMessageControl(0),
+                table_location: MsiXLocation(0),
+                pba_location: MsiXLocation(0),
+            };
+            msi_x.refresh();
+            msi_x
+        }))
+    }
+
+    /// Creates the accessor from an offset that you got from [`Self::offset`], without walking the capabilities list.
+    /// This checks that the capability at the offset is MSI-X, and then reads the registers that are cached.
+    ///
+    /// Returns `None` if the capability at the offset is not MSI-X.
+    #[must_use]
+    pub fn at_offset(pci: &'a mut PciAccess, address: PciAddress, offset: u8) -> Option<Self> {
+        if offset < 0x40 || !offset.is_multiple_of(4) {
+            return None;
+        }
+        let ptr: u16 = offset.into();
+        let header = pci.read_u32(address, ptr);
+        if header as u8 != 0x11 {
+            return None;
         }
+        Some(Self {
+            message_control: MsiXMessageControl((header >> 16) as u16),
+            table_location: MsiXLocation(pci.read_u32(address, ptr + 0x4)),
+            pba_location: MsiXLocation(pci.read_u32(address, ptr + 0x8)),
+            pci,
+            address,
+            ptr,
+        })
+    }
+}
+
+impl Debug for MsiX<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MsiX")
+            .field("address", &format_args!("{}", self.address))
+            .field("ptr", &format_args!("0x{:X}", self.ptr))
+            .finish()
     }
 }
 
 impl MsiX<'_> {
-    pub fn message_control(&mut self) -> MsiXMessageControl {
-        MsiXMessageControl(self.pci.read_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x2,
-        ))
+    /// The offset of the capability in the function's config space. You can store this and use it with [`Self::at_offset`] later.
+    #[must_use]
+    pub fn offset(&self) -> u8 {
+        self.ptr as u8
+    }
+
+    /// Re-reads the cached registers from the function
+    pub fn refresh(&mut self) {
+        self.message_control = MsiXMessageControl(self.pci.read_u16(self.address, self.ptr + 0x2));
+        self.table_location = MsiXLocation(self.pci.read_u32(self.address, self.ptr + 0x4));
+        self.pba_location = MsiXLocation(self.pci.read_u32(self.address, self.ptr + 0x8));
+    }
+
+    #[must_use]
+    pub fn message_control(&self) -> MsiXMessageControl {
+        self.message_control
     }
 
     pub fn set_message_control(&mut self, message_control: MsiXMessageControl) {
-        self.pci.write_u16(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x2,
-            message_control.0,
-        );
+        self.pci
+            .write_u16(self.address, self.ptr + 0x2, message_control.0);
+        self.message_control = message_control;
+    }
+
+    /// Like [`Self::set_message_control`], but reads the register back afterwards. See [`PciFunction::write_config_u16_verified`].
+    /// Only [`MsiXMessageControl::enable`] and [`MsiXMessageControl::function_mask`] are compared, because the other bits are read-only.
+    /// If the check fails, [`Self::message_control`] returns the value that was read back.
+    pub fn set_message_control_verified(
+        &mut self,
+        message_control: MsiXMessageControl,
+    ) -> Result<(), WriteVerifyError> {
+        let result =
+            self.pci
+                .write_u16_verified(self.address, self.ptr + 0x2, message_control.0, 0xC000);
+        self.message_control = match result {
+            Ok(()) => message_control,
+            Err(error) => MsiXMessageControl(error.read_back as u16),
+        };
+        result
+    }
+
+    /// Masks all vectors and then disables MSI-X, so that the function stops sending MSI-X interrupts
+    pub fn disable(&mut self) {
+        let mut message_control = self.message_control();
+        message_control.set_function_mask(true);
+        self.set_message_control(message_control);
+        message_control.set_enable(false);
+        self.set_message_control(message_control);
     }
 
-    pub fn table_location(&mut self) -> MsiXLocation {
-        MsiXLocation(self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x4,
-        ))
+    #[must_use]
+    pub fn table_location(&self) -> MsiXLocation {
+        self.table_location
     }
 
     /// The location of the Pending Bit Array
-    pub fn pba_location(&mut self) -> MsiXLocation {
-        MsiXLocation(self.pci.read_u32(
-            self.bus_number,
-            self.device_number,
-            self.function_number,
-            self.ptr + 0x8,
-        ))
+    #[must_use]
+    pub fn pba_location(&self) -> MsiXLocation {
+        self.pba_location
+    }
+
+    /// The size of the table in bytes. Use this to know how much of the BAR to map.
+    #[must_use]
+    pub fn table_byte_len(&self) -> usize {
+        self.message_control().table_size() as usize * size_of::<MsiXTableEntry>()
+    }
+
+    /// The size of the Pending Bit Array in bytes. Each entry takes up 1 bit, and the array is made of `u64`s.
+    #[must_use]
+    pub fn pba_byte_len(&self) -> usize {
+        self.message_control()
+            .table_size()
+            .div_ceil(u64::BITS as u16) as usize
+            * size_of::<u64>()
+    }
+
+    /// The page-aligned physical range that you need to map to access the table.
+    /// `bar_phys` is the physical address of the BAR that the table is in.
+    #[must_use]
+    pub fn table_mapping(&self, bar_phys: PhysAddr) -> Range<PhysAddr> {
+        page_aligned_range(bar_phys, self.table_bounds())
+    }
+
+    /// The page-aligned physical range that you need to map to access the Pending Bit Array.
+    /// `bar_phys` is the physical address of the BAR that the Pending Bit Array is in.
+    #[must_use]
+    pub fn pba_mapping(&self, bar_phys: PhysAddr) -> Range<PhysAddr> {
+        page_aligned_range(bar_phys, self.pba_bounds())
+    }
+
+    /// The ranges that you need to map to access both the table and the Pending Bit Array.
+    /// The table and Pending Bit Array often share a page, so if their ranges overlap or touch, only 1 merged range is returned.
+    #[must_use]
+    pub fn table_and_pba_mapping(
+        &self,
+        table_bar_phys: PhysAddr,
+        pba_bar_phys: PhysAddr,
+    ) -> (Range<PhysAddr>, Option<Range<PhysAddr>>) {
+        let table = self.table_mapping(table_bar_phys);
+        let pba = self.pba_mapping(pba_bar_phys);
+        if table.start <= pba.end && pba.start <= table.end {
+            (table.start.min(pba.start)..table.end.max(pba.end), None)
+        } else {
+            (table, Some(pba))
+        }
+    }
+
+    /// Re-reads the locations and sizes of the table and the Pending Bit Array together.
+    /// Returns an error if the function was removed while reading, or if the table and the Pending Bit Array overlap.
+    pub fn layout(&mut self) -> Result<MsiXLayout, InvalidMsiXLayout> {
+        self.refresh();
+        let (message_control, table_location, pba_location) = (
+            self.message_control(),
+            self.table_location(),
+            self.pba_location(),
+        );
+        if message_control.0 == u16::MAX
+            || table_location.0 == u32::MAX
+            || pba_location.0 == u32::MAX
+        {
+            return Err(InvalidMsiXLayout::DeviceGone(DeviceGone {
+                address: self.address,
+            }));
+        }
+        let layout = MsiXLayout {
+            table_bar_index: table_location.bar_index(),
+            table_offset_in_bar: table_location.offset_in_bar(),
+            table_byte_len: self.table_byte_len(),
+            pba_bar_index: pba_location.bar_index(),
+            pba_offset_in_bar: pba_location.offset_in_bar(),
+            pba_byte_len: self.pba_byte_len(),
+        };
+        if layout.overlaps() {
+            Err(InvalidMsiXLayout::Overlap(layout))
+        } else {
+            Ok(layout)
+        }
+    }
+
+    /// The offset of the table inside the BAR, and the table's length in bytes
+    fn table_bounds(&self) -> (usize, usize) {
+        (
+            self.table_location().offset_in_bar() as usize,
+            self.table_byte_len(),
+        )
+    }
+
+    /// The offset of the Pending Bit Array inside the BAR, and its length in bytes
+    fn pba_bounds(&self) -> (usize, usize) {
+        (
+            self.pba_location().offset_in_bar() as usize,
+            self.pba_byte_len(),
+        )
     }
 
     /// To use this function, you must:
@@ -85,14 +237,28 @@ impl MsiX<'_> {
     ///
     /// # Safety
     /// The virtual address must be mapped to the **start** of the BAR.
-    pub unsafe fn table<'a>(&mut self, bar_virt_addr: NonZero<usize>) -> MsiXTable<'a> {
-        let table_addr = bar_virt_addr
-            .checked_add(self.table_location().offset_in_bar() as usize)
-            .expect("Doesn't overflow");
+    #[must_use]
+    pub unsafe fn table<'a>(&self, bar_virt_addr: NonZero<usize>) -> MsiXTable<'a> {
+        let (offset, _) = self.table_bounds();
+        let table_addr = bar_virt_addr.checked_add(offset).expect("Doesn't overflow");
         let table_size = self.message_control().table_size();
         unsafe { MsiXTable::new(table_addr, table_size) }
     }
 
+    /// Like [`Self::table`], but checks that the table fits inside the BAR.
+    /// Use this if you don't trust the function to report a table size that fits in the BAR.
+    ///
+    /// # Safety
+    /// The virtual address must be mapped to the **start** of the BAR, and `bar_len` bytes must be mapped.
+    pub unsafe fn table_checked<'a>(
+        &self,
+        bar_virt_addr: NonZero<usize>,
+        bar_len: usize,
+    ) -> Result<MsiXTable<'a>, MsiXLayoutError> {
+        MsiXLayoutError::check(self.table_bounds(), bar_len)?;
+        Ok(unsafe { self.table(bar_virt_addr) })
+    }
+
     /// To use this function, you must:
     /// - Find out which BAR the table is located in using [`Self::pba_location`].
     /// - Map the BAR (it will always be MMIO) using the correct memory type
@@ -100,18 +266,103 @@ impl MsiX<'_> {
     ///
     /// # Safety
     /// The virtual address must be mapped to the **start** of the BAR.
+    #[must_use]
     pub unsafe fn pending_bit_array<'a>(
-        &mut self,
+        &self,
         bar_virt_addr: NonZero<usize>,
     ) -> MsiXPendingBitArray<'a> {
-        let table_addr = bar_virt_addr
-            .checked_add(self.pba_location().offset_in_bar() as usize)
-            .expect("Doesn't overflow");
+        let (offset, _) = self.pba_bounds();
+        let pba_addr = bar_virt_addr.checked_add(offset).expect("Doesn't overflow");
         let table_size = self.message_control().table_size();
-        unsafe { MsiXPendingBitArray::new(table_addr, table_size) }
+        unsafe { MsiXPendingBitArray::new(pba_addr, table_size) }
+    }
+
+    /// Like [`Self::pending_bit_array`], but checks that the Pending Bit Array fits inside the BAR.
+    ///
+    /// # Safety
+    /// The virtual address must be mapped to the **start** of the BAR, and `bar_len` bytes must be mapped.
+    pub unsafe fn pending_bit_array_checked<'a>(
+        &self,
+        bar_virt_addr: NonZero<usize>,
+        bar_len: usize,
+    ) -> Result<MsiXPendingBitArray<'a>, MsiXLayoutError> {
+        MsiXLayoutError::check(self.pba_bounds(), bar_len)?;
+        Ok(unsafe { self.pending_bit_array(bar_virt_addr) })
+    }
+}
+
+const PAGE_SIZE: u64 = 0x1000;
+
+fn page_aligned_range(
+    bar_phys: PhysAddr,
+    (offset_in_bar, byte_len): (usize, usize),
+) -> Range<PhysAddr> {
+    let start = bar_phys.as_u64() + offset_in_bar as u64;
+    let end = start + byte_len as u64;
+    PhysAddr::new(start / PAGE_SIZE * PAGE_SIZE)..PhysAddr::new(end.div_ceil(PAGE_SIZE) * PAGE_SIZE)
+}
+
+/// The MSI-X table or Pending Bit Array doesn't fit inside its BAR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MsiXLayoutError {
+    pub offset_in_bar: usize,
+    pub byte_len: usize,
+    pub bar_len: usize,
+}
+
+impl MsiXLayoutError {
+    fn check((offset_in_bar, byte_len): (usize, usize), bar_len: usize) -> Result<(), Self> {
+        if offset_in_bar
+            .checked_add(byte_len)
+            .is_some_and(|end| end <= bar_len)
+        {
+            Ok(())
+        } else {
+            Err(Self {
+                offset_in_bar,
+                byte_len,
+                bar_len,
+            })
+        }
     }
 }
 
+/// The locations of the MSI-X table and Pending Bit Array. They can be in the same BAR, in any order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MsiXLayout {
+    pub table_bar_index: u8,
+    pub table_offset_in_bar: u32,
+    pub table_byte_len: usize,
+    pub pba_bar_index: u8,
+    pub pba_offset_in_bar: u32,
+    pub pba_byte_len: usize,
+}
+
+impl MsiXLayout {
+    /// The spec doesn't allow the table and the Pending Bit Array to overlap
+    pub fn overlaps(&self) -> bool {
+        let table_start = self.table_offset_in_bar as usize;
+        let pba_start = self.pba_offset_in_bar as usize;
+        self.table_bar_index == self.pba_bar_index
+            && table_start < pba_start + self.pba_byte_len
+            && pba_start < table_start + self.table_byte_len
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvalidMsiXLayout {
+    DeviceGone(DeviceGone),
+    /// The table and the Pending Bit Array overlap, which is not allowed
+    Overlap(MsiXLayout),
+}
+
+/// The physical location of the MSI-X table or Pending Bit Array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MsiXPhysRegion {
+    pub phys_addr: u64,
+    pub byte_len: usize,
+}
+
 bitfield! {
     /// PCI Local Bus Specification Rev. 3.0 -> 6.8.2.3. Message Control for MSI-X
     #[derive(Clone, Copy)]
@@ -121,7 +372,8 @@ bitfield! {
     u16;
     /// The table size is encoded as N-1. So if 3 is stored, that means the table size is actually 4.
     _table_size, _: 10, 0;
-    pub function_mask, _: 14;
+    /// If this is 1, all vectors are masked, regardless of their own mask bits
+    pub function_mask, set_function_mask: 14;
     pub enable, set_enable: 15;
 }
 
@@ -153,7 +405,7 @@ impl MsiXLocation {
     }
 }
 
-#[derive(Debug, Clone, Copy, VolatileFieldAccess)]
+#[derive(Clone, Copy, VolatileFieldAccess)]
 #[repr(C)]
 pub struct MsiXTableEntry {
     /// In reality this is documented as a high and low u32, but on little-endian systems we can just treat it as a u64.
@@ -166,6 +418,42 @@ pub struct MsiXTableEntry {
     pub vector_control: MsiXVectorControl,
 }
 
+impl Debug for MsiXTableEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MsiXTableEntry")
+            .field(
+                "message_address",
+                &format_args!("0x{:X}", self.message_address),
+            )
+            .field("message_data", &format_args!("0x{:X}", self.message_data))
+            .field("masked", &self.vector_control.mask())
+            .finish()
+    }
+}
+
+/// Builds the message address and data of an [`MsiXTableEntry`], for [`MsiXTable::reconfigure_entry`]
+#[cfg(feature = "x86")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MsiXTableEntryConfig;
+
+#[cfg(feature = "x86")]
+impl MsiXTableEntryConfig {
+    /// The message address and data that send `vector` to the local APIC with the ID.
+    /// This uses the same [`ApicMsiMessageAddress`] and [`ApicMsiMessageData`] as MSI.
+    #[must_use]
+    pub fn for_apic(
+        destination_id: u8,
+        vector: u8,
+        trigger: TriggerMode,
+        delivery: DeliveryMode,
+    ) -> (u64, u32) {
+        (
+            ApicMsiMessageAddress::new(destination_id).into(),
+            ApicMsiMessageData::new(vector, trigger, delivery).into(),
+        )
+    }
+}
+
 bitfield! {
     /// PCI Local Bus Specification Rev. 3.0 -> 6.8.2.9. Vector Control for MSI-X Table Entries
     #[derive(Clone, Copy)]
@@ -193,18 +481,140 @@ impl MsiXTable<'_> {
         }
     }
 
-    pub fn entry_mut(&mut self, index: u16) -> VolatilePtr<MsiXTableEntry> {
-        self.ptr.as_mut_ptr().index(index as usize)
+    pub fn len(&self) -> u16 {
+        self.ptr.as_ptr().len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check_index(&self, index: u16) {
+        assert!(
+            index < self.len(),
+            "MSI-X table index {index} is out of bounds for table size {}",
+            self.len()
+        );
+    }
+
+    /// # Panics
+    /// Panics if the index is not less than the table size. Use [`Self::get_entry_mut`] if you don't want to panic.
+    #[must_use]
+    pub fn entry_mut(&mut self, index: u16) -> MsiXEntryMut {
+        self.check_index(index);
+        MsiXEntryMut(self.ptr.as_mut_ptr().index(index as usize))
+    }
+
+    /// Like [`Self::entry_mut`], but returns `None` if the index is out of bounds
+    #[must_use]
+    pub fn get_entry_mut(&mut self, index: u16) -> Option<MsiXEntryMut> {
+        if index < self.len() {
+            Some(MsiXEntryMut(self.ptr.as_mut_ptr().index(index as usize)))
+        } else {
+            None
+        }
+    }
+
+    /// Changes the message address and data of an entry without the function sending an interrupt with a mix of the old and new values.
+    /// The entry is masked during the update, and then its previous mask bit is restored.
+    pub fn reconfigure_entry(&mut self, index: u16, message_address: u64, message_data: u32) {
+        let mut entry = self.entry_mut(index);
+        let was_masked = entry.is_masked();
+        entry.mask();
+        entry.set_address(message_address);
+        entry.set_data(message_data);
+        if !was_masked {
+            entry.unmask();
+        }
+    }
+
+    /// Masks every entry
+    pub fn mask_all(&mut self) {
+        self.set_masked_bulk(|_| true);
+    }
+
+    /// Unmasks every entry
+    pub fn unmask_all(&mut self) {
+        self.set_masked_bulk(|_| false);
+    }
+
+    /// Sets the mask bit of every entry to `masked(index)`
+    pub fn set_masked_bulk(&mut self, masked: impl Fn(u16) -> bool) {
+        for index in 0..self.len() {
+            let mut entry = self.entry_mut(index);
+            if masked(index) {
+                entry.mask();
+            } else {
+                entry.unmask();
+            }
+        }
+    }
+
+    /// Reads a copy of an entry
+    pub fn entry(&self, index: u16) -> MsiXTableEntry {
+        self.check_index(index);
+        self.ptr.as_ptr().index(index as usize).read()
+    }
+
+    /// Reads a copy of each entry
+    pub fn iter(&self) -> impl Iterator<Item = MsiXTableEntry> + '_ {
+        (0..self.len()).map(|index| self.entry(index))
     }
 }
 
 pub use volatile::VolatilePtr;
 
+/// A handle to 1 entry of the MSI-X table. Every method does a volatile access.
+pub struct MsiXEntryMut<'a>(VolatilePtr<'a, MsiXTableEntry>);
+
+impl<'a> MsiXEntryMut<'a> {
+    /// The underlying pointer, for accessing the fields with [`VolatileFieldAccess`]
+    pub fn as_volatile(&self) -> VolatilePtr<'a, MsiXTableEntry> {
+        self.0
+    }
+
+    /// Reads a copy of the entry
+    pub fn read(&self) -> MsiXTableEntry {
+        self.0.read()
+    }
+
+    pub fn set_address(&mut self, message_address: u64) {
+        self.0.message_address().write(message_address);
+    }
+
+    pub fn set_data(&mut self, message_data: u32) {
+        self.0.message_data().write(message_data);
+    }
+
+    pub fn is_masked(&self) -> bool {
+        self.0.vector_control().read().mask()
+    }
+
+    pub fn mask(&mut self) {
+        self.set_masked(true);
+    }
+
+    pub fn unmask(&mut self) {
+        self.set_masked(false);
+    }
+
+    fn set_masked(&mut self, masked: bool) {
+        self.0.vector_control().update(|mut vector_control| {
+            vector_control.set_mask(masked);
+            vector_control
+        });
+    }
+}
+
+impl Debug for MsiXEntryMut<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.read().fmt(f)
+    }
+}
+
 impl Debug for MsiXTable<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_list()
-            .entries((0..self.ptr.as_ptr().len()).map(|i| self.ptr.as_ptr().index(i).read()))
-            .finish()
+        f.debug_map().entries(self.iter().enumerate()).finish()
     }
 }
 
@@ -212,6 +622,7 @@ impl Debug for MsiXTable<'_> {
 #[derive(Debug)]
 pub struct MsiXPendingBitArray<'a> {
     array: VolatileRef<'a, [u64], ReadOnly>,
+    table_size: u16,
 }
 
 impl<'a> MsiXPendingBitArray<'a> {
@@ -222,11 +633,12 @@ impl<'a> MsiXPendingBitArray<'a> {
             array: {
                 let ptr = NonNull::new(slice_from_raw_parts_mut(
                     pba_addr.get() as *mut u64,
-                    table_size.div_ceil(size_of::<u64>() as u16) as usize,
+                    table_size.div_ceil(u64::BITS as u16) as usize,
                 ))
                 .expect("ptr is not null");
                 unsafe { VolatileRef::new_read_only(ptr) }
             },
+            table_size,
         }
     }
 
@@ -235,4 +647,341 @@ impl<'a> MsiXPendingBitArray<'a> {
         let bit_index = entry % u64::BITS as u16;
         (self.array.as_ptr().index(u64_index as usize).read() >> bit_index) & 1 != 0
     }
+
+    /// Iterates through the entries that are pending.
+    /// This reads the array once per entry. Use [`Self::pending_snapshot`] to read each `u64` only once.
+    pub fn pending_vectors(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.table_size).filter(|&entry| self.is_pending(entry))
+    }
+
+    /// Iterates through the entries that are pending, reading each `u64` of the array exactly once
+    pub fn pending_snapshot(&self) -> impl Iterator<Item = u16> + '_ {
+        let array = self.array.as_ptr();
+        (0..array.len()).flat_map(move |u64_index| {
+            let mut bits = array.index(u64_index).read();
+            let table_size = self.table_size;
+            core::iter::from_fn(move || {
+                let bit_index = bits.trailing_zeros();
+                (bit_index < u64::BITS).then(|| {
+                    bits &= bits - 1;
+                    (u64_index as u32 * u64::BITS + bit_index) as u16
+                })
+            })
+            .take_while(move |&entry| entry < table_size)
+        })
+    }
+
+    /// Whether any entry is pending. This stops reading at the first `u64` that has a pending entry.
+    pub fn any_pending(&self) -> bool {
+        self.pending_snapshot().next().is_some()
+    }
+
+    /// Copies the whole array into `out`, which must have the same length as the array
+    ///
+    /// # Panics
+    /// Panics if `out` is not the same length as the array
+    pub fn copy_into(&self, out: &mut [u64]) {
+        self.array.as_ptr().copy_into_slice(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 3,
+        function: 0,
+    };
+
+    /// A function with an MSI-X capability at `0x50`
+    fn mock_msi_x(
+        table_size: u16,
+        table: (u8, u32),
+        pba: (u8, u32),
+        f: impl FnOnce(&mut MockFunction),
+    ) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        let mut body = [0; 10];
+        body[0..2].copy_from_slice(&(table_size - 1).to_le_bytes());
+        body[2..6].copy_from_slice(&(table.1 | table.0 as u32).to_le_bytes());
+        body[6..10].copy_from_slice(&(pba.1 | pba.0 as u32).to_le_bytes());
+        f(mock
+            .function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .capability(0x50, 0x11, &body));
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn phys_addr_in_64_bit_bar() {
+        let mut pci = mock_msi_x(8, (0, 0x2000), (0, 0x3000), |function| {
+            function.memory_bar_64(0, 0x40_0000_0000, 0x4000, false);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        assert_eq!(
+            function.msi_x_table_phys_addr(),
+            Some(MsiXPhysRegion {
+                phys_addr: 0x40_0000_2000,
+                byte_len: 8 * 16,
+            })
+        );
+        assert_eq!(
+            function.msi_x_pba_phys_addr(),
+            Some(MsiXPhysRegion {
+                phys_addr: 0x40_0000_3000,
+                byte_len: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn phys_addr_in_different_bars() {
+        let mut pci = mock_msi_x(65, (2, 0x0), (4, 0x800), |function| {
+            function
+                .memory_bar_64(0, 0x1_0000_0000, 0x1_0000, false)
+                .memory_bar_32(2, 0xFE00_0000, 0x1000, false)
+                .memory_bar_64(4, 0x2_0000_0000, 0x1000, false);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        assert_eq!(
+            function.msi_x_table_phys_addr(),
+            Some(MsiXPhysRegion {
+                phys_addr: 0xFE00_0000,
+                byte_len: 65 * 16,
+            })
+        );
+        // 65 entries need 2 `u64`s
+        assert_eq!(
+            function.msi_x_pba_phys_addr(),
+            Some(MsiXPhysRegion {
+                phys_addr: 0x2_0000_0800,
+                byte_len: 16,
+            })
+        );
+    }
+
+    #[test]
+    fn reserved_bir() {
+        let mut pci = mock_msi_x(1, (6, 0x0), (7, 0x0), |function| {
+            function.memory_bar_32(0, 0xFE00_0000, 0x1000, false);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        assert_eq!(function.msi_x_table_phys_addr(), None);
+        assert_eq!(function.msi_x_pba_phys_addr(), None);
+    }
+
+    #[test]
+    fn bir_of_io_bar() {
+        let mut pci = mock_msi_x(1, (1, 0x0), (0, 0x800), |function| {
+            function
+                .memory_bar_32(0, 0xFE00_0000, 0x1000, false)
+                .io_bar(1, 0xC000, 0x100);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        assert_eq!(function.msi_x_table_phys_addr(), None);
+        assert!(function.msi_x_pba_phys_addr().is_some());
+    }
+
+    #[test]
+    fn bir_of_64_bit_bar_in_last_slot() {
+        let mut pci = mock_msi_x(1, (5, 0x0), (5, 0x800), |function| {
+            function.set_u32(0x10 + 4 * 5, 0xFE00_0000 | 0b100);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        assert_eq!(function.msi_x_table_phys_addr(), None);
+        assert_eq!(function.msi_x_pba_phys_addr(), None);
+    }
+
+    #[test]
+    fn no_msi_x() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .memory_bar_32(0, 0xFE00_0000, 0x1000, false);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        assert_eq!(function.msi_x_table_phys_addr(), None);
+    }
+
+    fn table(entries: &mut [MsiXTableEntry]) -> MsiXTable<'_> {
+        let addr = NonZero::new(entries.as_mut_ptr() as usize).unwrap();
+        unsafe { MsiXTable::new(addr, entries.len() as u16) }
+    }
+
+    const ENTRY: MsiXTableEntry = MsiXTableEntry {
+        message_address: 0xFEE0_0000,
+        message_data: 0x30,
+        vector_control: MsiXVectorControl(0),
+    };
+
+    #[test]
+    fn reconfigure_entry_restores_the_mask() {
+        let mut entries = [ENTRY; 2];
+        entries[1].vector_control.set_mask(true);
+        let mut table = table(&mut entries);
+        // Memory accesses can't be observed in order, so this only checks the result
+        table.reconfigure_entry(0, 0xFEE0_1000, 0x41);
+        table.reconfigure_entry(1, 0xFEE0_2000, 0x42);
+        let (first, second) = (table.entry(0), table.entry(1));
+        assert_eq!(
+            (
+                first.message_address,
+                first.message_data,
+                first.vector_control.mask()
+            ),
+            (0xFEE0_1000, 0x41, false)
+        );
+        assert_eq!(
+            (
+                second.message_address,
+                second.message_data,
+                second.vector_control.mask()
+            ),
+            (0xFEE0_2000, 0x42, true)
+        );
+    }
+
+    #[test]
+    fn checked_at_the_exact_bar_end() {
+        // The table ends at 0x80 and the PBA ends at 0x88
+        let mut pci = mock_msi_x(4, (0, 0x40), (0, 0x80), |function| {
+            function.memory_bar_32(0, 0xFE00_0000, 0x1000, false);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        let msi_x = function.msi_x().unwrap().unwrap();
+        let mut bar = [0u64; 0x88 / 8];
+        let bar_virt_addr = NonZero::new(bar.as_mut_ptr() as usize).unwrap();
+        assert!(unsafe { msi_x.table_checked(bar_virt_addr, 0x80) }.is_ok());
+        assert!(unsafe { msi_x.pending_bit_array_checked(bar_virt_addr, 0x88) }.is_ok());
+    }
+
+    #[test]
+    fn checked_one_byte_past_the_bar_end() {
+        let mut pci = mock_msi_x(4, (0, 0x40), (0, 0x80), |function| {
+            function.memory_bar_32(0, 0xFE00_0000, 0x1000, false);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        let msi_x = function.msi_x().unwrap().unwrap();
+        let mut bar = [0u64; 0x88 / 8];
+        let bar_virt_addr = NonZero::new(bar.as_mut_ptr() as usize).unwrap();
+        assert_eq!(
+            unsafe { msi_x.table_checked(bar_virt_addr, 0x7F) }.err(),
+            Some(MsiXLayoutError {
+                offset_in_bar: 0x40,
+                byte_len: 0x40,
+                bar_len: 0x7F,
+            })
+        );
+        assert_eq!(
+            unsafe { msi_x.pending_bit_array_checked(bar_virt_addr, 0x87) }.err(),
+            Some(MsiXLayoutError {
+                offset_in_bar: 0x80,
+                byte_len: 0x8,
+                bar_len: 0x87,
+            })
+        );
+    }
+
+    #[test]
+    fn set_message_control_verified() {
+        let mut pci = mock_msi_x(4, (0, 0x0), (0, 0x800), |function| {
+            function
+                .memory_bar_32(0, 0xFE00_0000, 0x1000, false)
+                // Enable is stuck at 0, and the table size is read-only
+                .writable_u8s(0x52, 1, 0)
+                .writable_u8s(0x53, 1, 0x40);
+        });
+        let mut function = pci.function_at(0, 3, 0).unwrap();
+        let mut msi_x = function.msi_x().unwrap().unwrap();
+        let mut message_control = msi_x.message_control();
+        message_control.set_function_mask(true);
+        assert_eq!(msi_x.set_message_control_verified(message_control), Ok(()));
+        message_control.set_enable(true);
+        assert_eq!(
+            msi_x.set_message_control_verified(message_control),
+            Err(WriteVerifyError {
+                address: ADDRESS,
+                register_offset: 0x52,
+                wrote: 0xC003,
+                read_back: 0x4003,
+            })
+        );
+        // The cache matches the hardware, so disabling doesn't think MSI-X is enabled
+        assert!(!msi_x.message_control().enable());
+        assert!(msi_x.message_control().function_mask());
+    }
+
+    #[test]
+    fn cached_registers_are_only_read_once() {
+        let mut pci = mock_msi_x(4, (0, 0x0), (0, 0x40), |function| {
+            function.memory_bar_32(0, 0xFE00_0000, 0x1000, false);
+        });
+        let mut bar = [ENTRY; 5];
+        let bar_virt_addr = NonZero::new(bar.as_mut_ptr() as usize).unwrap();
+        let mut msi_x = MsiX::at_offset(&mut pci, ADDRESS, 0x50).unwrap();
+        assert_eq!(msi_x.message_control().table_size(), 4);
+        assert_eq!(msi_x.table_location().offset_in_bar(), 0x0);
+        assert_eq!(msi_x.pba_location().offset_in_bar(), 0x40);
+        let mut message_control = msi_x.message_control();
+        message_control.set_function_mask(true);
+        msi_x.set_message_control(message_control);
+        assert!(msi_x.message_control().function_mask());
+        let _ = unsafe { msi_x.table_checked(bar_virt_addr, size_of_val(&bar)) }.unwrap();
+        let _ =
+            unsafe { msi_x.pending_bit_array_checked(bar_virt_addr, size_of_val(&bar)) }.unwrap();
+        // The capability header, the table location, and the PBA location
+        assert_eq!(pci.mock().read_count(), 3);
+        MsiX::at_offset(&mut pci, ADDRESS, 0x50).unwrap().refresh();
+        assert_eq!(pci.mock().read_count(), 3 + 3 + 3);
+    }
+
+    #[test]
+    fn at_offset_rejects_other_capabilities() {
+        let mut pci = mock_msi_x(1, (0, 0x0), (0, 0x800), |function| {
+            function.capability(0x60, 0x5, &[0; 22]);
+        });
+        assert!(MsiX::at_offset(&mut pci, ADDRESS, 0x50).is_some());
+        // MSI
+        assert!(MsiX::at_offset(&mut pci, ADDRESS, 0x60).is_none());
+        // Not a capability pointer
+        assert!(MsiX::at_offset(&mut pci, ADDRESS, 0x52).is_none());
+    }
+
+    /// The register formats are in Intel SDM -> Volume 3 -> 12.11.1 and 12.11.2
+    #[cfg(feature = "x86")]
+    #[test]
+    fn apic_entry_config() {
+        assert_eq!(
+            MsiXTableEntryConfig::for_apic(0, 0x30, TriggerMode::Edge, DeliveryMode::Fixed),
+            (0xFEE0_0000, 0x0030)
+        );
+        assert_eq!(
+            MsiXTableEntryConfig::for_apic(
+                0x1,
+                0x41,
+                TriggerMode::Level,
+                DeliveryMode::LowestPriority
+            ),
+            (0xFEE0_1000, 0xC141)
+        );
+        assert_eq!(
+            MsiXTableEntryConfig::for_apic(0xFF, 0x20, TriggerMode::Edge, DeliveryMode::Nmi),
+            (0xFEEF_F000, 0x0420)
+        );
+        assert_eq!(
+            MsiXTableEntryConfig::for_apic(0x3, 0x0, TriggerMode::Edge, DeliveryMode::Init),
+            (0xFEE0_3000, 0x0500)
+        );
+        assert_eq!(
+            MsiXTableEntryConfig::for_apic(0x0, 0x21, TriggerMode::Edge, DeliveryMode::ExtInt),
+            (0xFEE0_0000, 0x0721)
+        );
+        assert_eq!(
+            MsiXTableEntryConfig::for_apic(0x10, 0x0, TriggerMode::Edge, DeliveryMode::Smi),
+            (0xFEE1_0000, 0x0200)
+        );
+    }
 }
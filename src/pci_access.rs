@@ -1,236 +1,1161 @@
-use core::{fmt::Debug, ops::RangeInclusive, ptr::NonNull};
+#[cfg(feature = "ecam")]
+use core::ptr::NonNull;
+use core::{fmt::Debug, ops::RangeInclusive};
 
-use acpi::mcfg::McfgEntry;
+#[cfg(feature = "ecam")]
 use volatile::VolatilePtr;
+#[cfg(feature = "legacy-port-io")]
 use x86_64::instructions::port::Port;
 
 use super::*;
 
+#[cfg(not(any(feature = "ecam", feature = "legacy-port-io")))]
+compile_error!("At least one of the `ecam` and `legacy-port-io` features must be enabled");
+
+#[cfg(feature = "legacy-port-io")]
+const CONFIG_ADDRESS_PORT: u16 = 0xCF8;
+#[cfg(feature = "legacy-port-io")]
+const CONFIG_DATA_PORT: u16 = 0xCFC;
+
+#[cfg(feature = "legacy-port-io")]
 #[derive(Debug)]
 pub struct Pci {
     config_address: Port<u32>,
     config_data: Port<u32>,
+    critical_section: Option<fn(&mut dyn FnMut())>,
+    /// The buses that were found by [`PciAccess::discover`]
+    discovered_buses: BusBitmap,
+}
+
+#[cfg(feature = "legacy-port-io")]
+impl Pci {
+    /// Writes the config address and then accesses the config data port with `f`.
+    /// If there is a critical section, both happen inside it, so that another CPU can't change the config address in between.
+    fn access<R>(&mut self, config: PciConfig, f: impl FnOnce(&mut Port<u32>) -> R) -> R {
+        self.critical(|pci| f(pci.select(config)))
+    }
+
+    /// Calls `f` inside the critical section, if there is one.
+    /// Use this with [`Self::select`] for accesses that need more than 1 config address.
+    fn critical<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let mut f = Some(f);
+        let mut result = None;
+        let critical_section = self.critical_section;
+        let mut access = || result = f.take().map(|f| f(self));
+        match critical_section {
+            Some(critical_section) => critical_section(&mut access),
+            None => access(),
+        }
+        result.expect("The critical section must call the closure that it is given")
+    }
+
+    /// Writes the config address and returns the config data port. Only call this inside [`Self::critical`].
+    fn select(&mut self, config: PciConfig) -> &mut Port<u32> {
+        unsafe { self.config_address.write(config.0) };
+        &mut self.config_data
+    }
+}
+
+/// A set of bus numbers, where bit N is bus N
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub(super) struct BusBitmap([u64; 4]);
+
+impl BusBitmap {
+    pub(super) const BUS_0: Self = Self([1, 0, 0, 0]);
+
+    pub(super) fn contains(&self, bus: u8) -> bool {
+        self.0[bus as usize / 64] & (1 << (bus % 64)) != 0
+    }
+
+    pub(super) fn insert(&mut self, bus: u8) {
+        self.0[bus as usize / 64] |= 1 << (bus % 64);
+    }
+
+    pub(super) fn iter(self) -> impl Iterator<Item = u8> {
+        (0..=u8::MAX).filter(move |bus| self.contains(*bus))
+    }
 }
 
+/// The maximum number of MCFG entries (ECAM windows) that a [`PciAccess`] can hold.
+/// Most systems only have 1 entry. Open an issue if you need more.
+pub const MAX_MCFG_ENTRIES: usize = 16;
+
+#[cfg(feature = "ecam")]
 #[derive(Debug)]
-pub struct Pcie {
-    mcfg_entry: McfgEntry,
+struct PcieRegion {
+    window: EcamWindow,
     ptr: VolatilePtr<'static, [u8]>,
 }
 
+#[cfg(feature = "ecam")]
+impl PcieRegion {
+    fn contains(&self, segment: u16, bus_number: u8) -> bool {
+        self.window.segment_group == segment && self.window.buses().contains(&bus_number)
+    }
+
+    /// The index of the register in the mapped memory, in units of `access_len` bytes.
+    /// This is the only place that computes ECAM offsets.
+    fn try_index(
+        &self,
+        address: PciAddress,
+        register_offset: u16,
+        access_len: usize,
+    ) -> Result<usize, EcamAccessError> {
+        if !(register_offset as usize).is_multiple_of(access_len) {
+            return Err(EcamAccessError::Unaligned {
+                address,
+                register_offset,
+                access_len,
+            });
+        }
+        let bus_offset = address.bus - self.window.bus_number_start;
+        let byte_offset = (bus_offset as usize) << 20
+            | (address.device as usize) << 15
+            | (address.function as usize) << 12
+            | register_offset as usize;
+        let mapped_len = self.ptr.len();
+        if register_offset < 0x1000 && byte_offset + access_len <= mapped_len {
+            Ok(byte_offset / access_len)
+        } else {
+            Err(EcamAccessError::OutOfRange {
+                address,
+                register_offset,
+                byte_offset,
+                mapped_len,
+            })
+        }
+    }
+
+    /// # Panics
+    /// Panics with a descriptive message if [`Self::try_index`] fails, for example because the mapping is too small.
+    fn index(&self, address: PciAddress, register_offset: u16, access_len: usize) -> usize {
+        match self.try_index(address, register_offset, access_len) {
+            Ok(index) => index,
+            Err(EcamAccessError::Unaligned { .. }) => panic!(
+                "ECAM access to {address} register 0x{register_offset:X} is not aligned to the access size of {access_len} bytes"
+            ),
+            Err(EcamAccessError::OutOfRange {
+                byte_offset,
+                mapped_len,
+                ..
+            }) => panic!(
+                "ECAM access to {address} register 0x{register_offset:X} is at byte offset 0x{byte_offset:X}, which is outside of the mapped memory of length 0x{mapped_len:X}"
+            ),
+        }
+    }
+}
+
+/// Why an ECAM config access can't be done
+#[cfg(feature = "ecam")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum EcamAccessError {
+    /// The register offset is not a multiple of the access size
+    Unaligned {
+        address: PciAddress,
+        register_offset: u16,
+        access_len: usize,
+    },
+    /// The register is outside of the mapped memory, for example because only some of the buses were mapped
+    OutOfRange {
+        address: PciAddress,
+        register_offset: u16,
+        byte_offset: usize,
+        mapped_len: usize,
+    },
+}
+
+#[cfg(feature = "ecam")]
+#[derive(Debug)]
+pub struct Pcie {
+    regions: [Option<PcieRegion>; MAX_MCFG_ENTRIES],
+    endianness: EcamEndianness,
+    extended_config_override: Option<fn(u16, u16) -> ExtendedConfigPolicy>,
+}
+
+/// The byte order of registers in the ECAM region. This is little-endian on x86 and most other systems.
+#[cfg(feature = "ecam")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum EcamEndianness {
+    #[default]
+    Little,
+    /// Some embedded PCIe host bridges present ECAM registers byte-swapped.
+    /// Each access is byte-swapped within its own width.
+    Big,
+}
+
+#[cfg(feature = "ecam")]
+impl EcamEndianness {
+    /// 64-bit registers are 2 `u32` registers with the lower half first, so each half is converted on its own
+    fn u64_from_bytes(self, bytes: [u8; 8]) -> u64 {
+        let (low, high) = bytes.split_at(4);
+        let low = self.u32_from_bytes(low.try_into().unwrap());
+        let high = self.u32_from_bytes(high.try_into().unwrap());
+        low as u64 | (high as u64) << 32
+    }
+
+    fn u32_from_bytes(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u16_from_bytes(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Self::Little => u16::from_le_bytes(bytes),
+            Self::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    /// See [`Self::u64_from_bytes`]
+    fn u64_to_bytes(self, value: u64) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&self.u32_to_bytes(value as u32));
+        bytes[4..].copy_from_slice(&self.u32_to_bytes((value >> 32) as u32));
+        bytes
+    }
+
+    fn u32_to_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Self::Little => value.to_le_bytes(),
+            Self::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn u16_to_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            Self::Little => value.to_le_bytes(),
+            Self::Big => value.to_be_bytes(),
+        }
+    }
+}
+
+#[cfg(feature = "ecam")]
+impl Pcie {
+    fn region(&self, segment: u16, bus_number: u8) -> Option<&PcieRegion> {
+        self.regions
+            .iter()
+            .flatten()
+            .find(|region| region.contains(segment, bus_number))
+    }
+}
+
+/// The mapped memory is not the same length as the ECAM window
+#[cfg(feature = "ecam")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EcamMappingLenError {
+    pub window: EcamWindow,
+    pub expected_len: usize,
+    pub mapped_len: usize,
+}
+
+/// Use [`PciAccess::access_kind`] instead of matching on the variants.
+///
+/// Config accesses take `&mut self`, and a config access is not atomic with the legacy I/O ports (it is a write to the address port and then an access to the data port).
+/// It is [`Send`], but to access config space from multiple CPUs, put it in a [`SyncPciAccess`] so that accesses are serialized.
+/// If other code also uses the legacy I/O ports, use [`PciAccess::with_critical_section`] to share your kernel's lock with it.
 #[derive(Debug)]
+#[non_exhaustive]
+// There is no allocator to box the ECAM regions in, and there is usually only 1 `PciAccess`
+#[allow(clippy::large_enum_variant)]
 pub enum PciAccess {
+    #[cfg(feature = "legacy-port-io")]
     Pci(Pci),
+    #[cfg(feature = "ecam")]
     Pcie(Pcie),
+    #[cfg(test)]
+    Mock(MockConfig),
+}
+
+// Safety: the ECAM memory is only accessed through `&mut self`, and the constructors require it to be mapped for as long as the `PciAccess` is used, on any CPU.
+#[cfg(feature = "ecam")]
+unsafe impl Send for PciAccess {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    /// The legacy I/O port mechanism. Only the first 256 bytes of each function's config space are accessible.
+    LegacyPortIo,
+    /// PCIe ECAM (MMIO). The extended config space (4096 bytes per function) is accessible.
+    Ecam,
+}
+
+impl AccessKind {
+    /// The number of bytes of each function's config space that are accessible
+    pub fn config_space_len(&self) -> usize {
+        match self {
+            Self::LegacyPortIo => 0x100,
+            Self::Ecam => 0x1000,
+        }
+    }
 }
 
 impl PciAccess {
     /// # Safety
     /// The ports must be PCI and not used by other code.
+    #[cfg(feature = "legacy-port-io")]
     pub unsafe fn new_pci() -> Self {
         Self::Pci(Pci {
-            config_address: Port::<u32>::new(0xCF8),
-            config_data: Port::<u32>::new(0xCFC),
+            config_address: Port::<u32>::new(CONFIG_ADDRESS_PORT),
+            config_data: Port::<u32>::new(CONFIG_DATA_PORT),
+            critical_section: None,
+            discovered_buses: BusBitmap::BUS_0,
         })
     }
 
+    /// Accepts an [`EcamWindow`], or an `McfgEntry` if the `acpi` feature is enabled.
+    /// This doesn't check the window or the mapping. Use [`PcieBuilder`] to get an error instead of reads that return all ones.
+    ///
+    /// # Safety
+    /// The mapped mem must point to physical memory for the ECAM window, which you can calculate using [`EcamWindow::phys_range`].
+    /// It must stay mapped on every CPU that the [`PciAccess`] is sent to.
+    #[cfg(feature = "ecam")]
+    pub unsafe fn new_pcie(window: impl Into<EcamWindow>, mapped_mem: NonNull<[u8]>) -> Self {
+        unsafe { Self::new_pcie_multiple([(window, mapped_mem)]) }
+    }
+
+    /// Like [`Self::new_pcie`], but only for 1 bus, so that you only need to map 1 MiB.
+    /// [`Self::known_buses`] will only have that bus.
+    ///
+    /// # Panics
+    /// Panics if the bus is not within the window's bus range
+    ///
+    /// # Safety
+    /// The mapped mem must point to physical memory for the bus, which you can calculate using [`EcamWindow::phys_range_for_buses`].
+    #[cfg(feature = "ecam")]
+    pub unsafe fn new_pcie_single_bus(
+        window: impl Into<EcamWindow>,
+        bus: u8,
+        mapped_mem: NonNull<[u8]>,
+    ) -> Self {
+        let window = window.into();
+        unsafe { Self::new_pcie(window.single_bus(bus), mapped_mem) }
+    }
+
+    /// Like [`Self::new_pcie`], but returns an error if the length of the mapped mem is not [`EcamWindow::byte_len`].
+    /// This catches mapping only some of the buses and then scanning all of them.
+    ///
     /// # Safety
-    /// The mapped mem must point to physical memory for the MCFG entry, which you can calculate using [`get_phys_range_to_map`].
-    pub unsafe fn new_pcie(mcfg_entry: McfgEntry, mapped_mem: NonNull<[u8]>) -> Self {
+    /// The mapped mem must point to physical memory for the ECAM window, which you can calculate using [`EcamWindow::phys_range`].
+    #[cfg(feature = "ecam")]
+    pub unsafe fn try_new_pcie(
+        window: impl Into<EcamWindow>,
+        mapped_mem: NonNull<[u8]>,
+    ) -> Result<Self, EcamMappingLenError> {
+        let window = window.into();
+        let expected_len = window.byte_len();
+        let mapped_len = mapped_mem.len();
+        if mapped_len == expected_len {
+            Ok(unsafe { Self::new_pcie(window, mapped_mem) })
+        } else {
+            Err(EcamMappingLenError {
+                window,
+                expected_len,
+                mapped_len,
+            })
+        }
+    }
+
+    /// Use this if the MCFG table has multiple entries, for example if there are multiple PCI segment groups.
+    /// Config accesses will be routed to the entry whose segment group and bus range contain the target bus.
+    ///
+    /// # Panics
+    /// Panics if there are more than [`MAX_MCFG_ENTRIES`] entries.
+    ///
+    /// # Safety
+    /// Each mapped mem must point to physical memory for its ECAM window, which you can calculate using [`EcamWindow::phys_range`].
+    #[cfg(feature = "ecam")]
+    pub unsafe fn new_pcie_multiple<W: Into<EcamWindow>>(
+        entries: impl IntoIterator<Item = (W, NonNull<[u8]>)>,
+    ) -> Self {
+        unsafe { Self::new_pcie_with_endianness(entries, EcamEndianness::Little) }
+    }
+
+    /// Like [`Self::new_pcie_multiple`], but for host bridges that present ECAM registers in a different byte order
+    ///
+    /// # Safety
+    /// Each mapped mem must point to physical memory for its ECAM window, which you can calculate using [`EcamWindow::phys_range`].
+    #[cfg(feature = "ecam")]
+    pub unsafe fn new_pcie_with_endianness<W: Into<EcamWindow>>(
+        entries: impl IntoIterator<Item = (W, NonNull<[u8]>)>,
+        endianness: EcamEndianness,
+    ) -> Self {
+        let mut regions = [const { None }; MAX_MCFG_ENTRIES];
+        for (i, (window, mapped_mem)) in entries.into_iter().enumerate() {
+            assert!(
+                i < MAX_MCFG_ENTRIES,
+                "At most {MAX_MCFG_ENTRIES} MCFG entries are supported"
+            );
+            regions[i] = Some(PcieRegion {
+                window: window.into(),
+                ptr: unsafe { VolatilePtr::new(mapped_mem) },
+            });
+        }
         Self::Pcie(Pcie {
-            mcfg_entry,
-            ptr: unsafe { VolatilePtr::new(mapped_mem) },
+            regions,
+            endianness,
+            extended_config_override: None,
         })
     }
 
+    /// With the legacy I/O ports, a config access is a write to the address port and then an access to the data port.
+    /// If another CPU writes to the address port in between, the access goes to the wrong register.
+    /// `critical_section` is called with each access, and must call the closure that it is given exactly once while holding your kernel's lock (and with interrupts disabled if interrupt handlers access config space).
+    ///
+    /// ECAM accesses are a single memory access, so this does nothing for ECAM.
+    #[cfg_attr(not(feature = "legacy-port-io"), allow(unused_variables))]
+    pub fn with_critical_section(mut self, critical_section: fn(&mut dyn FnMut())) -> Self {
+        match &mut self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => pci.critical_section = Some(critical_section),
+            #[cfg(feature = "ecam")]
+            Self::Pcie(_) => {}
+            #[cfg(test)]
+            Self::Mock(_) => {}
+        }
+        self
+    }
+
+    /// Some non-compliant functions hang or return garbage when config space at `0x100` and above is accessed, even with ECAM.
+    /// `extended_config_override` is called with the vendor ID and device ID of a function before its extended config space is accessed, so you can deny it for functions that are known to misbehave.
+    /// See [`PciFunction::has_extended_config`].
+    ///
+    /// Legacy PCI has no extended config space, so this does nothing for legacy PCI.
+    #[cfg_attr(not(any(feature = "ecam", test)), allow(unused_variables))]
+    pub fn with_extended_config_override(
+        mut self,
+        extended_config_override: fn(u16, u16) -> ExtendedConfigPolicy,
+    ) -> Self {
+        match &mut self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => {}
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => pcie.extended_config_override = Some(extended_config_override),
+            #[cfg(test)]
+            Self::Mock(mock) => mock.extended_config_override = Some(extended_config_override),
+        }
+        self
+    }
+
+    #[cfg_attr(not(any(feature = "ecam", test)), allow(unused_variables))]
+    pub(super) fn extended_config_policy(
+        &self,
+        vendor_id: u16,
+        device_id: u16,
+    ) -> ExtendedConfigPolicy {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => ExtendedConfigPolicy::LegacyOnly,
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => pcie
+                .extended_config_override
+                .map_or(ExtendedConfigPolicy::Probe, |extended_config_override| {
+                    extended_config_override(vendor_id, device_id)
+                }),
+            #[cfg(test)]
+            Self::Mock(mock) => match mock.kind() {
+                AccessKind::LegacyPortIo => ExtendedConfigPolicy::LegacyOnly,
+                AccessKind::Ecam => mock
+                    .extended_config_override
+                    .map_or(ExtendedConfigPolicy::Probe, |extended_config_override| {
+                        extended_config_override(vendor_id, device_id)
+                    }),
+            },
+        }
+    }
+
+    pub fn access_kind(&self) -> AccessKind {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => AccessKind::LegacyPortIo,
+            #[cfg(feature = "ecam")]
+            Self::Pcie(_) => AccessKind::Ecam,
+            #[cfg(test)]
+            Self::Mock(mock) => mock.kind(),
+        }
+    }
+
+    /// The buses in segment group 0 that are directly addressable by this access method. See [`PciSegment::known_buses`].
+    /// Buses behind PCI-to-PCI bridges may be outside of this range.
+    ///
+    /// With legacy PCI, this is only bus 0 until [`Self::discover`] is called, and then it is from bus 0 to the highest bus that was found.
+    /// That range can include bus numbers that no bridge leads to. Scanning them finds nothing, at the cost of 1 read per device number.
+    /// Use [`Self::discovered_buses`] to only get the buses that were found.
     pub fn known_buses(&self) -> RangeInclusive<u8> {
+        self.known_buses_in_segment(0)
+    }
+
+    /// Legacy PCI has no table of buses, so this finds them by starting at bus 0 and following the secondary bus number of every PCI-to-PCI and CardBus bridge.
+    /// The buses are remembered, so [`Self::known_buses`] and [`Self::discovered_buses`] include them and the normal scan reaches them.
+    /// Call this again after changing bus numbers.
+    ///
+    /// ECAM windows already say which buses they cover, so this does nothing for ECAM.
+    #[cfg_attr(not(any(feature = "legacy-port-io", test)), allow(unused_variables))]
+    pub fn discover(&mut self) {
+        if self.access_kind() != AccessKind::LegacyPortIo {
+            return;
+        }
+        let buses = self.find_buses_behind_bridges();
         match self {
-            Self::Pci(_) => 0..=0,
-            Self::Pcie(pcie) => pcie.mcfg_entry.bus_number_start..=pcie.mcfg_entry.bus_number_end,
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => pci.discovered_buses = buses,
+            #[cfg(feature = "ecam")]
+            Self::Pcie(_) => {}
+            #[cfg(test)]
+            Self::Mock(mock) => mock.discovered_buses = buses,
         }
     }
 
-    pub fn bus(&mut self, bus_number: u8) -> PciBus {
-        PciBus {
-            pci: self,
-            bus_number,
+    /// Starting from bus 0, finds every bus that is the secondary bus of a bridge on a bus that was already found
+    fn find_buses_behind_bridges(&mut self) -> BusBitmap {
+        let mut found = BusBitmap::BUS_0;
+        let mut visited = BusBitmap::default();
+        while let Some(bus_number) =
+            (0..=u8::MAX).find(|bus| found.contains(*bus) && !visited.contains(*bus))
+        {
+            visited.insert(bus_number);
+            let mut bus = self.bus(bus_number);
+            let mut devices = bus.devices();
+            while let Some(mut device) = devices.next() {
+                let mut functions = device.functions();
+                while let Some(mut function) = functions.next() {
+                    if matches!(
+                        function.header_type(),
+                        HeaderType::PciToPciBridge | HeaderType::PciToCardBusBridge
+                    ) {
+                        // This is the CardBus bus number for CardBus bridges
+                        let secondary_bus = function.read_config_u8(0x19);
+                        // A bridge that hasn't been configured has a secondary bus number of 0
+                        if secondary_bus != 0 {
+                            found.insert(secondary_bus);
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// The buses in segment group 0 that are known to exist.
+    /// With legacy PCI, this is bus 0 and the buses found by [`Self::discover`]. With ECAM, this is [`Self::known_buses`].
+    pub fn discovered_buses(&self) -> impl Iterator<Item = u8> + use<> {
+        let buses = match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => pci.discovered_buses,
+            #[cfg(feature = "ecam")]
+            Self::Pcie(_) => self.known_bus_bitmap(),
+            #[cfg(test)]
+            Self::Mock(mock) => match mock.kind() {
+                AccessKind::LegacyPortIo => mock.discovered_buses,
+                AccessKind::Ecam => self.known_bus_bitmap(),
+            },
+        };
+        buses.iter()
+    }
+
+    #[cfg(any(feature = "ecam", test))]
+    fn known_bus_bitmap(&self) -> BusBitmap {
+        let mut buses = BusBitmap::default();
+        for bus in self.known_buses() {
+            buses.insert(bus);
         }
+        buses
     }
 
-    pub(super) fn read_u32(
+    /// The physical address of the ECAM region for the segment group, from the MCFG entry or ECAM window. This is the address of bus 0, even if the segment group doesn't start at bus 0.
+    ///
+    /// Returns `None` for legacy PCI, or if the segment group is not accessible.
+    #[cfg(feature = "ecam")]
+    pub fn ecam_base(&self, segment: u16) -> Option<PhysAddr> {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => None,
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => pcie
+                .regions
+                .iter()
+                .flatten()
+                .map(|region| region.window)
+                .find(|window| window.segment_group == segment)
+                .map(|window| PhysAddr::new(window.base_address)),
+            #[cfg(test)]
+            Self::Mock(_) => None,
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "ecam", test)), allow(unused_variables))]
+    pub(super) fn known_buses_in_segment(&self, segment: u16) -> RangeInclusive<u8> {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => 0..=pci.discovered_buses.iter().last().unwrap_or_default(),
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => {
+                let mut windows = pcie
+                    .regions
+                    .iter()
+                    .flatten()
+                    .map(|region| region.window)
+                    .filter(|window| window.segment_group == segment);
+                match windows.next() {
+                    Some(first) => windows.fold(first.buses(), |buses, window| {
+                        (*buses.start()).min(window.bus_number_start)
+                            ..=(*buses.end()).max(window.bus_number_end)
+                    }),
+                    #[allow(clippy::reversed_empty_ranges)]
+                    None => 1..=0,
+                }
+            }
+            #[cfg(test)]
+            Self::Mock(mock) => match mock.kind() {
+                AccessKind::LegacyPortIo => {
+                    0..=mock.discovered_buses.iter().last().unwrap_or_default()
+                }
+                #[allow(clippy::reversed_empty_ranges)]
+                AccessKind::Ecam if segment != 0 => 1..=0,
+                AccessKind::Ecam => mock.ecam_buses(),
+            },
+        }
+    }
+
+    /// Iterates through the PCI segment groups that are accessible. Legacy PCI only has segment group 0.
+    /// Each segment group is only yielded once, even if it is split across multiple MCFG entries.
+    pub fn segment_groups(&self) -> impl Iterator<Item = u16> + use<> {
+        let mut segments = [None; MAX_MCFG_ENTRIES];
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => segments[0] = Some(0),
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => {
+                for (i, region) in pcie.regions.iter().enumerate() {
+                    segments[i] = region
+                        .as_ref()
+                        .map(|region| region.window.segment_group)
+                        .filter(|segment| !segments[..i].contains(&Some(*segment)));
+                }
+            }
+            #[cfg(test)]
+            Self::Mock(_) => segments[0] = Some(0),
+        }
+        segments.into_iter().flatten()
+    }
+
+    /// The physical address of a function's 4 KiB of config space. This is useful if you want to map just one function's config space.
+    ///
+    /// Returns `None` for legacy PCI, which doesn't have MMIO config space, or if the bus is not accessible.
+    #[cfg(feature = "ecam")]
+    pub fn function_config_phys_addr(&self, address: PciAddress) -> Option<PhysAddr> {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => None,
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => {
+                let region = pcie.region(address.segment, address.bus)?;
+                Some(PhysAddr::new(
+                    region.window.base_address
+                        + ((address.bus as u64) << 20
+                            | (address.device as u64) << 15
+                            | (address.function as u64) << 12),
+                ))
+            }
+            #[cfg(test)]
+            Self::Mock(_) => None,
+        }
+    }
+
+    /// Re-reads the vendor ID up to `max_tries` times, until it is valid.
+    /// A function returns all 1s while it is not ready, for example after a reset.
+    /// There is no timer in `no_std`, so you control the time between tries by choosing `max_tries`.
+    ///
+    /// Returns `true` once the vendor ID is valid, or `false` if it never became valid.
+    pub fn wait_for_vendor(&mut self, address: PciAddress, max_tries: usize) -> bool {
+        (0..max_tries).any(|_| self.read_u16(address, 0x0) != u16::MAX)
+    }
+
+    pub fn segment(&mut self, segment: u16) -> PciSegment {
+        PciSegment { pci: self, segment }
+    }
+
+    /// Iterates through the buses in segment group 0. See [`PciSegment::buses`].
+    pub fn buses(&mut self) -> PciBuses {
+        self.segment(0).into_buses()
+    }
+
+    /// A bus in segment group 0
+    pub fn bus(&mut self, bus_number: u8) -> PciBus {
+        self.segment(0).into_bus(bus_number)
+    }
+
+    /// A device in segment group 0, for when you already know where it is (for example, from ACPI `_ADR`).
+    /// This is the same as `pci.bus(bus_number).device(device_number)`.
+    ///
+    /// Returns `None` if the device is not present
+    pub fn device_at(&mut self, bus_number: u8, device_number: u8) -> Option<PciDevice> {
+        self.bus(bus_number).into_device(device_number)
+    }
+
+    /// A function in segment group 0, for when you already know where it is (for example, from ACPI `_ADR`).
+    /// Like [`PciDevice::function`], this doesn't check the multi-function bit of function 0.
+    ///
+    /// Returns `None` if the function is not present
+    pub fn function_at(
         &mut self,
         bus_number: u8,
         device_number: u8,
         function_number: u8,
-        register_offset: u8,
-    ) -> u32 {
+    ) -> Option<PciFunction> {
+        let address = PciAddress::new(0, bus_number, device_number, function_number);
+        let identity = FunctionIdentity::read(self, address)?;
+        Some(PciFunction {
+            pci: self,
+            address,
+            identity,
+        })
+    }
+
+    /// With ECAM, this is a single 64-bit access, so the 2 halves can't tear.
+    /// With the legacy I/O ports, this is 2 32-bit accesses, starting with the lower half.
+    /// Both of them happen inside the same critical section, so another CPU can't access config space in between.
+    pub(super) fn read_u64(&mut self, address: PciAddress, register_offset: u16) -> u64 {
         assert!(
-            register_offset.is_multiple_of(size_of::<u32>().try_into().unwrap()),
-            "Register offset represents bytes and should be aligned to u32"
+            register_offset.is_multiple_of(size_of::<u64>().try_into().unwrap()),
+            "Register offset represents bytes and should be aligned to u64"
         );
         match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => u64::MAX,
+            #[cfg(feature = "legacy-port-io")]
             Self::Pci(pci) => {
-                let mut address = PciConfig(0);
-                address.set_enable(true);
-                address.set_bus_number(bus_number);
-                address.set_device_number(device_number);
-                address.set_function_number(function_number);
-                address.set_register_offset(register_offset);
+                let config = PciConfig::new(address, register_offset);
+                let high_config = PciConfig::new(address, register_offset + 4);
 
-                unsafe { pci.config_address.write(address.0) };
-                unsafe { pci.config_data.read() }
+                let (low, high) = pci.critical(|pci| {
+                    let low = unsafe { pci.select(config).read() };
+                    let high = unsafe { pci.select(high_config).read() };
+                    (low, high)
+                });
+                low as u64 | (high as u64) << 32
             }
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => match pcie.region(address.segment, address.bus) {
+                Some(region) => {
+                    let bytes = region
+                        .ptr
+                        .as_chunks()
+                        .0
+                        .index(region.index(address, register_offset, size_of::<u64>()))
+                        .read();
+                    pcie.endianness.u64_from_bytes(bytes)
+                }
+                // Non-existent buses behave like non-existent devices
+                None => u64::MAX,
+            },
+            #[cfg(test)]
+            Self::Mock(mock) => mock.read(address, register_offset, 8),
+        }
+    }
+
+    /// See [`Self::read_u64`]
+    pub(super) fn write_u64(&mut self, address: PciAddress, register_offset: u16, value: u64) {
+        assert!(
+            register_offset.is_multiple_of(size_of::<u64>().try_into().unwrap()),
+            "Register offset represents bytes and should be aligned to u64"
+        );
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => {}
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => {
+                let config = PciConfig::new(address, register_offset);
+                let high_config = PciConfig::new(address, register_offset + 4);
+
+                pci.critical(|pci| {
+                    unsafe { pci.select(config).write(value as u32) };
+                    unsafe { pci.select(high_config).write((value >> 32) as u32) };
+                })
+            }
+            #[cfg(feature = "ecam")]
             Self::Pcie(pcie) => {
-                let bus_offset = bus_number - pcie.mcfg_entry.bus_number_start;
-                let bytes = pcie
-                    .ptr
-                    .as_chunks()
-                    .0
-                    .index(
-                        ((bus_offset as usize) << 20
-                            | (device_number as usize) << 15
-                            | (function_number as usize) << 12
-                            | register_offset as usize)
-                            / size_of::<u32>(),
-                    )
-                    .read();
-                u32::from_le_bytes(bytes)
+                // Writes to non-existent buses are ignored, like writes to non-existent devices
+                if let Some(region) = pcie.region(address.segment, address.bus) {
+                    region
+                        .ptr
+                        .as_chunks()
+                        .0
+                        .index(region.index(address, register_offset, size_of::<u64>()))
+                        .write(pcie.endianness.u64_to_bytes(value));
+                }
             }
+            #[cfg(test)]
+            Self::Mock(mock) => mock.write(address, register_offset, 8, value),
         }
     }
 
-    pub(super) fn read_u16(
-        &mut self,
-        bus_number: u8,
-        device_number: u8,
-        function_number: u8,
-        register_offset: u8,
-    ) -> u16 {
+    pub(super) fn read_u32(&mut self, address: PciAddress, register_offset: u16) -> u32 {
+        assert!(
+            register_offset.is_multiple_of(size_of::<u32>().try_into().unwrap()),
+            "Register offset represents bytes and should be aligned to u32"
+        );
+        match self {
+            // Legacy PCI only has segment group 0 and no extended config space
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => u32::MAX,
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => {
+                let config = PciConfig::new(address, register_offset);
+
+                pci.access(config, |config_data| unsafe { config_data.read() })
+            }
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => match pcie.region(address.segment, address.bus) {
+                Some(region) => {
+                    let bytes = region
+                        .ptr
+                        .as_chunks()
+                        .0
+                        .index(region.index(address, register_offset, size_of::<u32>()))
+                        .read();
+                    pcie.endianness.u32_from_bytes(bytes)
+                }
+                // Non-existent buses behave like non-existent devices
+                None => u32::MAX,
+            },
+            #[cfg(test)]
+            Self::Mock(mock) => mock.read(address, register_offset, 4) as u32,
+        }
+    }
+
+    pub(super) fn read_u16(&mut self, address: PciAddress, register_offset: u16) -> u16 {
         assert!(
             register_offset.is_multiple_of(size_of::<u16>().try_into().unwrap()),
             "Register offset represents bytes and should be aligned to u16"
         );
         match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => u16::MAX,
+            #[cfg(feature = "legacy-port-io")]
             Self::Pci(pci) => {
-                let mut address = PciConfig(0);
-                address.set_enable(true);
-                address.set_bus_number(bus_number);
-                address.set_device_number(device_number);
-                address.set_function_number(function_number);
-                address.set_register_offset(register_offset / 4 * 4);
+                let config = PciConfig::new(address, register_offset);
 
-                unsafe { pci.config_address.write(address.0) };
-                let bit_index = (register_offset % 4) * u8::BITS as u8;
-                (unsafe { pci.config_data.read() } >> bit_index) as u16
+                let bit_index = (register_offset % 4) * u8::BITS as u16;
+                (pci.access(config, |config_data| unsafe { config_data.read() }) >> bit_index)
+                    as u16
             }
-            Self::Pcie(pcie) => {
-                let bus_offset = bus_number - pcie.mcfg_entry.bus_number_start;
-                let bytes = pcie
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => match pcie.region(address.segment, address.bus) {
+                Some(region) => {
+                    let bytes = region
+                        .ptr
+                        .as_chunks()
+                        .0
+                        .index(region.index(address, register_offset, size_of::<u16>()))
+                        .read();
+                    pcie.endianness.u16_from_bytes(bytes)
+                }
+                // Non-existent buses behave like non-existent devices
+                None => u16::MAX,
+            },
+            #[cfg(test)]
+            Self::Mock(mock) => mock.read(address, register_offset, 2) as u16,
+        }
+    }
+
+    pub(super) fn read_u8(&mut self, address: PciAddress, register_offset: u16) -> u8 {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) => {
+                let reg = self.read_u32(address, register_offset / 4 * 4);
+                (reg >> ((register_offset % 4) * u8::BITS as u16)) as u8
+            }
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => match pcie.region(address.segment, address.bus) {
+                Some(region) => region
                     .ptr
-                    .as_chunks()
-                    .0
-                    .index(
-                        ((bus_offset as usize) << 20
-                            | (device_number as usize) << 15
-                            | (function_number as usize) << 12
-                            | register_offset as usize)
-                            / size_of::<u16>(),
-                    )
-                    .read();
-                u16::from_le_bytes(bytes)
+                    .index(region.index(address, register_offset, 1))
+                    .read(),
+                None => u8::MAX,
+            },
+            #[cfg(test)]
+            Self::Mock(mock) => mock.read(address, register_offset, 1) as u8,
+        }
+    }
+
+    /// Only writes the 1 byte, so that the other bytes in the same `u32` are not written to
+    pub(super) fn write_u8(&mut self, address: PciAddress, register_offset: u16, value: u8) {
+        match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => {}
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => {
+                let config = PciConfig::new(address, register_offset);
+
+                pci.access(config, |_| {
+                    // The data port can be accessed 1 byte at a time
+                    let mut config_data = Port::<u8>::new(CONFIG_DATA_PORT + register_offset % 4);
+                    unsafe { config_data.write(value) }
+                })
             }
+            #[cfg(feature = "ecam")]
+            Self::Pcie(pcie) => {
+                if let Some(region) = pcie.region(address.segment, address.bus) {
+                    region
+                        .ptr
+                        .index(region.index(address, register_offset, 1))
+                        .write(value);
+                }
+            }
+            #[cfg(test)]
+            Self::Mock(mock) => mock.write(address, register_offset, 1, value.into()),
         }
     }
 
-    pub(super) fn write_u32(
-        &mut self,
-        bus_number: u8,
-        device_number: u8,
-        function_number: u8,
-        register_offset: u8,
-        value: u32,
-    ) {
+    pub(super) fn write_u32(&mut self, address: PciAddress, register_offset: u16, value: u32) {
         assert!(
             register_offset.is_multiple_of(size_of::<u32>().try_into().unwrap()),
             "Register offset represents bytes and should be aligned to u32"
         );
         match self {
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => {}
+            #[cfg(feature = "legacy-port-io")]
             Self::Pci(pci) => {
-                let mut address = PciConfig(0);
-                address.set_enable(true);
-                address.set_bus_number(bus_number);
-                address.set_device_number(device_number);
-                address.set_function_number(function_number);
-                address.set_register_offset(register_offset);
+                let config = PciConfig::new(address, register_offset);
 
-                unsafe { pci.config_address.write(address.0) };
-                unsafe { pci.config_data.write(value) }
+                pci.access(config, |config_data| unsafe { config_data.write(value) })
             }
+            #[cfg(feature = "ecam")]
             Self::Pcie(pcie) => {
-                // assert!(self.known_buses().contains(&bus_number));
-                let bus_offset = bus_number - pcie.mcfg_entry.bus_number_start;
-                pcie.ptr
-                    .as_chunks()
-                    .0
-                    .index(
-                        ((bus_offset as usize) << 20
-                            | (device_number as usize) << 15
-                            | (function_number as usize) << 12
-                            | register_offset as usize)
-                            / size_of::<u32>(),
-                    )
-                    .write(value.to_le_bytes());
+                // Writes to non-existent buses are ignored, like writes to non-existent devices
+                if let Some(region) = pcie.region(address.segment, address.bus) {
+                    region
+                        .ptr
+                        .as_chunks()
+                        .0
+                        .index(region.index(address, register_offset, size_of::<u32>()))
+                        .write(pcie.endianness.u32_to_bytes(value));
+                }
             }
+            #[cfg(test)]
+            Self::Mock(mock) => mock.write(address, register_offset, 4, value.into()),
         }
     }
 
-    pub(super) fn write_u16(
-        &mut self,
-        bus_number: u8,
-        device_number: u8,
-        function_number: u8,
-        register_offset: u8,
-        value: u16,
-    ) {
+    /// Only writes the 2 bytes, so that the other half of the same `u32` is not written to
+    pub(super) fn write_u16(&mut self, address: PciAddress, register_offset: u16, value: u16) {
         assert!(
             register_offset.is_multiple_of(size_of::<u16>().try_into().unwrap()),
             "Register offset represents bytes and should be aligned to u16"
         );
         match self {
-            Self::Pci(_) => {
-                let reg_offset_bytes_within_u32 = register_offset % size_of::<u32>() as u8;
-                let register_offset_u32 = register_offset - reg_offset_bytes_within_u32;
-                let reg = self.read_u32(
-                    bus_number,
-                    device_number,
-                    function_number,
-                    register_offset_u32,
-                );
-                let bit_index = reg_offset_bytes_within_u32 * u8::BITS as u8;
-                let change_mask = (u16::MAX as u32) << bit_index;
-                self.write_u32(
-                    bus_number,
-                    device_number,
-                    function_number,
-                    register_offset_u32,
-                    (reg & !change_mask) | ((value as u32) << bit_index),
-                );
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(_) if address.segment != 0 || register_offset >= 0x100 => {}
+            #[cfg(feature = "legacy-port-io")]
+            Self::Pci(pci) => {
+                let config = PciConfig::new(address, register_offset);
+
+                pci.access(config, |_| {
+                    // The data port can be accessed 2 bytes at a time, so the other half of the `u32` is not written to
+                    let mut config_data =
+                        Port::<u16>::new(CONFIG_DATA_PORT + (register_offset & 2));
+                    unsafe { config_data.write(value) }
+                })
             }
+            #[cfg(feature = "ecam")]
             Self::Pcie(pcie) => {
-                // assert!(self.known_buses().contains(&bus_number));
-                let bus_offset = bus_number - pcie.mcfg_entry.bus_number_start;
-                pcie.ptr
-                    .as_chunks()
-                    .0
-                    .index(
-                        ((bus_offset as usize) << 20
-                            | (device_number as usize) << 15
-                            | (function_number as usize) << 12
-                            | register_offset as usize)
-                            / size_of::<u16>(),
-                    )
-                    .write(value.to_le_bytes());
+                // Writes to non-existent buses are ignored, like writes to non-existent devices
+                if let Some(region) = pcie.region(address.segment, address.bus) {
+                    region
+                        .ptr
+                        .as_chunks()
+                        .0
+                        .index(region.index(address, register_offset, size_of::<u16>()))
+                        .write(pcie.endianness.u16_to_bytes(value));
+                }
             }
+            #[cfg(test)]
+            Self::Mock(mock) => mock.write(address, register_offset, 2, value.into()),
+        }
+    }
+
+    /// Writes the register and reads it back. Only the bits in `mask` are compared.
+    pub(super) fn write_u32_verified(
+        &mut self,
+        address: PciAddress,
+        register_offset: u16,
+        value: u32,
+        mask: u32,
+    ) -> Result<(), WriteVerifyError> {
+        self.write_u32(address, register_offset, value);
+        let read_back = self.read_u32(address, register_offset);
+        if (read_back ^ value) & mask == 0 {
+            Ok(())
+        } else {
+            Err(WriteVerifyError {
+                address,
+                register_offset,
+                wrote: value,
+                read_back,
+            })
+        }
+    }
+
+    /// See [`Self::write_u32_verified`]
+    pub(super) fn write_u16_verified(
+        &mut self,
+        address: PciAddress,
+        register_offset: u16,
+        value: u16,
+        mask: u16,
+    ) -> Result<(), WriteVerifyError> {
+        self.write_u16(address, register_offset, value);
+        let read_back = self.read_u16(address, register_offset);
+        if (read_back ^ value) & mask == 0 {
+            Ok(())
+        } else {
+            Err(WriteVerifyError {
+                address,
+                register_offset,
+                wrote: value.into(),
+                read_back: read_back.into(),
+            })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ecam"))]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+
+    fn mapped_mem(mem: &mut [u64]) -> NonNull<[u8]> {
+        NonNull::slice_from_raw_parts(
+            NonNull::new(mem.as_mut_ptr().cast::<u8>()).unwrap(),
+            size_of_val(mem),
+        )
+    }
+
+    /// A big-endian ECAM window for bus 0, backed by `mem`
+    fn big_endian_ecam(mem: &mut [u64]) -> PciAccess {
+        let window = EcamWindow {
+            base_address: 0,
+            segment_group: 0,
+            bus_number_start: 0,
+            bus_number_end: 0,
+        };
+        unsafe {
+            PciAccess::new_pcie_with_endianness([(window, mapped_mem(mem))], EcamEndianness::Big)
+        }
+    }
+
+    fn bytes(mem: &[u64], offset: usize, len: usize) -> Vec<u8> {
+        mem.iter()
+            .flat_map(|word| word.to_ne_bytes())
+            .collect::<Vec<_>>()[offset..offset + len]
+            .to_vec()
+    }
+
+    #[test]
+    fn big_endian_round_trip() {
+        let mut mem = vec![0u64; (1 << 20) / 8];
+        let mut pci = big_endian_ecam(&mut mem);
+        pci.write_u16(ADDRESS, 0x42, 0x1234);
+        pci.write_u32(ADDRESS, 0x44, 0x1234_5678);
+        pci.write_u64(ADDRESS, 0x48, 0x0123_4567_89AB_CDEF);
+        assert_eq!(pci.read_u16(ADDRESS, 0x42), 0x1234);
+        assert_eq!(pci.read_u32(ADDRESS, 0x44), 0x1234_5678);
+        assert_eq!(pci.read_u64(ADDRESS, 0x48), 0x0123_4567_89AB_CDEF);
+        drop(pci);
+        assert_eq!(bytes(&mem, 0x42, 2), [0x12, 0x34]);
+        assert_eq!(bytes(&mem, 0x44, 4), [0x12, 0x34, 0x56, 0x78]);
+        // The lower `u32` comes first, and each `u32` is big-endian
+        assert_eq!(
+            bytes(&mem, 0x48, 8),
+            [0x89, 0xAB, 0xCD, 0xEF, 0x01, 0x23, 0x45, 0x67]
+        );
+    }
+
+    #[test]
+    fn try_index_errors() {
+        let mut mem = vec![0u64; 0x2000 / 8];
+        let region = PcieRegion {
+            window: EcamWindow {
+                base_address: 0,
+                segment_group: 0,
+                bus_number_start: 0,
+                bus_number_end: 0,
+            },
+            ptr: unsafe { VolatilePtr::new(mapped_mem(&mut mem)) },
+        };
+        assert_eq!(region.try_index(ADDRESS, 0x10, 4), Ok(4));
+        let function_1 = PciAddress::new(0, 0, 0, 1);
+        assert_eq!(region.try_index(function_1, 0x0, 2), Ok(0x800));
+        assert_eq!(
+            region.try_index(ADDRESS, 0x11, 4),
+            Err(EcamAccessError::Unaligned {
+                address: ADDRESS,
+                register_offset: 0x11,
+                access_len: 4,
+            })
+        );
+        let function_2 = PciAddress::new(0, 0, 0, 2);
+        assert_eq!(
+            region.try_index(function_2, 0x8, 4),
+            Err(EcamAccessError::OutOfRange {
+                address: function_2,
+                register_offset: 0x8,
+                byte_offset: 0x2008,
+                mapped_len: 0x2000,
+            })
+        );
+    }
+
+    #[test]
+    fn accesses_go_to_the_region_for_the_segment_and_bus() {
+        // Segment 0 buses 0-1, and segment 1 bus 4
+        let mut mem_0 = vec![0u64; (2 << 20) / 8];
+        let mut mem_1 = vec![0u64; (1 << 20) / 8];
+        let window_0 = EcamWindow {
+            base_address: 0xE000_0000,
+            segment_group: 0,
+            bus_number_start: 0,
+            bus_number_end: 1,
+        };
+        let window_1 = EcamWindow {
+            base_address: 0xF000_0000,
+            segment_group: 1,
+            bus_number_start: 4,
+            bus_number_end: 4,
+        };
+        let mut pci = unsafe {
+            PciAccess::new_pcie_multiple([
+                (window_0, mapped_mem(&mut mem_0)),
+                (window_1, mapped_mem(&mut mem_1)),
+            ])
+        };
+        let in_region_0 = PciAddress::new(0, 1, 2, 3);
+        let in_region_1 = PciAddress::new(1, 4, 5, 6);
+        pci.write_u32(in_region_0, 0x40, 0x1111_2222);
+        pci.write_u32(in_region_1, 0x40, 0x3333_4444);
+        assert_eq!(pci.read_u32(in_region_0, 0x40), 0x1111_2222);
+        assert_eq!(pci.read_u32(in_region_1, 0x40), 0x3333_4444);
+        // Bus 4 of segment 0 and bus 1 of segment 1 are not in any region
+        for address in [PciAddress::new(0, 4, 5, 6), PciAddress::new(1, 1, 2, 3)] {
+            pci.write_u32(address, 0x40, 0x5555_6666);
+            assert_eq!(pci.read_u32(address, 0x40), u32::MAX);
         }
+        drop(pci);
+        let offset_0 = 1 << 20 | 2 << 15 | 3 << 12 | 0x40;
+        assert_eq!(bytes(&mem_0, offset_0, 4), 0x1111_2222u32.to_le_bytes());
+        let offset_1 = 5 << 15 | 6 << 12 | 0x40;
+        assert_eq!(bytes(&mem_1, offset_1, 4), 0x3333_4444u32.to_le_bytes());
+        // Nothing else was written
+        let nonzero = |mem: &[u64]| mem.iter().filter(|word| **word != 0).count();
+        assert_eq!((nonzero(&mem_0), nonzero(&mem_1)), (1, 1));
     }
 }
@@ -1,12 +1,45 @@
 use bitfield::bitfield;
 
+use super::*;
+
 bitfield! {
   pub(super) struct PciConfig(u32);
   impl Debug;
   // The fields default to u16
   pub enable, set_enable: 31;
-  u8; pub bus_number, set_bus_number: 23, 15;
+  u8; pub bus_number, set_bus_number: 23, 16;
   u8; pub device_number, set_device_number: 15, 11;
   u8; pub function_number, set_function_number: 10, 8;
   u8; pub register_offset, set_register_offset: 7,0 ;
 }
+
+impl PciConfig {
+    /// The config address of the `u32` register that contains `register_offset`
+    pub(super) fn new(address: PciAddress, register_offset: u16) -> Self {
+        let mut config = Self(0);
+        config.set_enable(true);
+        config.set_bus_number(address.bus);
+        config.set_device_number(address.device);
+        config.set_function_number(address.function);
+        config.set_register_offset((register_offset / 4 * 4) as u8);
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_encoding() {
+        let config = |bus, device, function, register_offset| {
+            PciConfig::new(PciAddress::new(0, bus, device, function), register_offset).0
+        };
+        assert_eq!(config(0, 0, 0, 0x0), 0x8000_0000);
+        assert_eq!(config(0x1, 0, 0, 0x0), 0x8001_0000);
+        assert_eq!(config(0xFF, 0, 0, 0x0), 0x80FF_0000);
+        assert_eq!(config(0, 0x1F, 0, 0x0), 0x8000_F800);
+        assert_eq!(config(0, 0, 0x7, 0x0), 0x8000_0700);
+        assert_eq!(config(0x12, 0x1F, 0x7, 0xFE), 0x8012_FFFC);
+    }
+}
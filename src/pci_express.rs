@@ -0,0 +1,437 @@
+use core::{fmt::Debug, ops::RangeInclusive};
+
+use bitfield::bitfield;
+use num_enum::FromPrimitive;
+
+use super::*;
+
+/// The PCI Express capability (ID `0x10`). Every PCIe function has this.
+///
+/// PCI Express Base Specification -> 7.5.3 PCI Express Capability Structure
+pub struct PciExpressCap<'a> {
+    pci: &'a mut PciAccess,
+    address: PciAddress,
+    ptr: u16,
+}
+
+impl<'a> PciExpressCap<'a> {
+    pub(super) fn find(function: &'a mut PciFunction) -> Option<Option<Self>> {
+        Some(function.find_capability(0x10)?.map(|capability| Self {
+            pci: function.pci,
+            address: function.address,
+            ptr: capability.ptr_to_self.into(),
+        }))
+    }
+}
+
+impl PciExpressCap<'_> {
+    #[must_use]
+    pub fn capabilities_register(&mut self) -> PciExpressCapabilities {
+        PciExpressCapabilities(self.pci.read_u16(self.address, self.ptr + 0x2))
+    }
+
+    #[must_use]
+    pub fn port_type(&mut self) -> PciePortType {
+        self.capabilities_register().device_port_type().into()
+    }
+
+    #[must_use]
+    pub fn device_capabilities(&mut self) -> DeviceCapabilities {
+        DeviceCapabilities(self.pci.read_u32(self.address, self.ptr + 0x4))
+    }
+
+    #[must_use]
+    pub fn device_control(&mut self) -> DeviceControl {
+        DeviceControl(self.pci.read_u16(self.address, self.ptr + 0x8))
+    }
+
+    pub fn set_device_control(&mut self, device_control: DeviceControl) {
+        self.pci
+            .write_u16(self.address, self.ptr + 0x8, device_control.0);
+    }
+
+    /// The maximum payload size that the function currently uses, in bytes
+    #[must_use]
+    pub fn max_payload_size(&mut self) -> u16 {
+        decode_payload_size(self.device_control().max_payload_size())
+    }
+
+    /// The maximum size of a read request that the function currently makes, in bytes
+    #[must_use]
+    pub fn max_read_request_size(&mut self) -> u16 {
+        decode_payload_size(self.device_control().max_read_request_size())
+    }
+
+    #[must_use]
+    pub fn device_status(&mut self) -> DeviceStatus {
+        DeviceStatus(self.pci.read_u16(self.address, self.ptr + 0xA))
+    }
+
+    /// Clears the latched error bits of [`Self::device_status`]
+    pub fn clear_device_status_errors(&mut self) {
+        self.pci
+            .write_u16(self.address, self.ptr + 0xA, DeviceStatus::ERROR_BITS);
+    }
+
+    #[must_use]
+    pub fn link_capabilities(&mut self) -> LinkCapabilities {
+        LinkCapabilities(self.pci.read_u32(self.address, self.ptr + 0xC))
+    }
+
+    #[must_use]
+    pub fn link_status(&mut self) -> LinkStatus {
+        LinkStatus(self.pci.read_u16(self.address, self.ptr + 0x12))
+    }
+
+    /// The current link speed
+    #[must_use]
+    pub fn link_speed(&mut self) -> LinkSpeed {
+        self.link_status().current_link_speed().into()
+    }
+
+    /// The number of lanes that the link is using
+    #[must_use]
+    pub fn link_width(&mut self) -> u8 {
+        self.link_status().negotiated_link_width()
+    }
+
+    /// Waits until the link is up, for example after a reset or hotplug.
+    /// If the port supports Data Link Layer Link Active reporting, this polls that bit.
+    /// Otherwise, this polls the vendor ID of device 0 on the secondary bus, which only works for root ports and downstream switch ports.
+    ///
+    /// There is no timer in `no_std`, so `timed_out` is called between polls and should return `true` when you want to give up.
+    /// Returns `true` if the link came up, and `false` if it timed out.
+    /// Also returns `false` if the port doesn't support Data Link Layer Link Active reporting and isn't a downstream port with a bridge header, because then there is no way to poll the link.
+    pub fn wait_for_link(&mut self, mut timed_out: impl FnMut() -> bool) -> bool {
+        if self.link_capabilities().dll_link_active_reporting_capable() {
+            loop {
+                if self.link_status().dll_link_active() {
+                    return true;
+                }
+                if timed_out() {
+                    return false;
+                }
+            }
+        } else {
+            // The secondary bus number is only at 0x19 in a PCI-to-PCI bridge header
+            let is_bridge = self.pci.read_u8(self.address, 0xE) & 0x7F == 0x1;
+            if !is_bridge || !self.port_type().is_downstream_port() {
+                return false;
+            }
+            let secondary_bus = self.pci.read_u8(self.address, 0x19);
+            let downstream = PciAddress::new(self.address.segment, secondary_bus, 0, 0);
+            loop {
+                if self.pci.read_u16(downstream, 0x0) != u16::MAX {
+                    return true;
+                }
+                if timed_out() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Debug for PciExpressCap<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PciExpressCap")
+            .field("address", &format_args!("{}", self.address))
+            .field("ptr", &format_args!("0x{:X}", self.ptr))
+            .finish()
+    }
+}
+
+bitfield! {
+    /// PCI Express Base Specification -> 7.5.3.2 PCI Express Capabilities Register
+    #[derive(Clone, Copy)]
+    pub struct PciExpressCapabilities(u16);
+    impl Debug;
+
+    u8;
+    pub capability_version, _: 3, 0;
+    pub device_port_type, _: 7, 4;
+    /// If this is 1, the port is connected to a slot, so the slot registers are implemented
+    pub slot_implemented, _: 8;
+    pub interrupt_message_number, _: 13, 9;
+}
+
+/// What kind of PCIe component the function is, decoded from [`PciExpressCapabilities::device_port_type`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum PciePortType {
+    Endpoint = 0x0,
+    LegacyEndpoint = 0x1,
+    RootPort = 0x4,
+    UpstreamSwitchPort = 0x5,
+    DownstreamSwitchPort = 0x6,
+    PcieToPciBridge = 0x7,
+    PciToPcieBridge = 0x8,
+    RootComplexIntegratedEndpoint = 0x9,
+    RootComplexEventCollector = 0xA,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl PciePortType {
+    /// Root ports and downstream switch ports have a link to a device below them.
+    /// If that link is down, reads from the secondary bus return all ones, so you should skip the secondary bus instead of probing it.
+    pub fn is_downstream_port(&self) -> bool {
+        matches!(self, Self::RootPort | Self::DownstreamSwitchPort)
+    }
+
+    /// Whether the function is a bridge to another bus, so it can have functions below it
+    pub fn can_have_children(&self) -> bool {
+        matches!(
+            self,
+            Self::RootPort
+                | Self::UpstreamSwitchPort
+                | Self::DownstreamSwitchPort
+                | Self::PcieToPciBridge
+                | Self::PciToPcieBridge
+        )
+    }
+
+    /// Whether the function is a PCIe endpoint, legacy endpoint, or root complex integrated endpoint
+    pub fn is_endpoint(&self) -> bool {
+        matches!(
+            self,
+            Self::Endpoint | Self::LegacyEndpoint | Self::RootComplexIntegratedEndpoint
+        )
+    }
+}
+
+/// Payload sizes are encoded as `128 << n`, and the values above 4096 bytes are reserved
+fn decode_payload_size(encoded: u8) -> u16 {
+    128 << encoded.min(5)
+}
+
+bitfield! {
+    /// PCI Express Base Specification -> 7.5.3.3 Device Capabilities Register
+    #[derive(Clone, Copy)]
+    pub struct DeviceCapabilities(u32);
+    impl Debug;
+
+    u8;
+    pub max_payload_size_supported, _: 2, 0;
+    pub extended_tag_field_supported, _: 5;
+    pub function_level_reset_capable, _: 28;
+}
+
+bitfield! {
+    /// PCI Express Base Specification -> 7.5.3.4 Device Control Register
+    #[derive(Clone, Copy)]
+    pub struct DeviceControl(u16);
+    impl Debug;
+
+    u8;
+    pub max_payload_size, set_max_payload_size: 7, 5;
+    pub extended_tag_field_enable, set_extended_tag_field_enable: 8;
+    pub enable_no_snoop, set_enable_no_snoop: 11;
+    pub max_read_request_size, set_max_read_request_size: 14, 12;
+    /// Writing 1 starts a Function Level Reset, if [`DeviceCapabilities::function_level_reset_capable`]. This always reads as 0.
+    pub initiate_function_level_reset, set_initiate_function_level_reset: 15;
+}
+
+bitfield! {
+    /// PCI Express Base Specification -> 7.5.3.5 Device Status Register
+    ///
+    /// The error bits are latched. They are cleared by writing a 1 to them.
+    #[derive(Clone, Copy)]
+    pub struct DeviceStatus(u16);
+    impl Debug;
+
+    pub correctable_error_detected, _: 0;
+    pub non_fatal_error_detected, _: 1;
+    pub fatal_error_detected, _: 2;
+    pub unsupported_request_detected, _: 3;
+    pub aux_power_detected, _: 4;
+    pub transactions_pending, _: 5;
+}
+
+impl DeviceStatus {
+    /// The latched error bits (0-3). Write this to the register to clear all of them.
+    pub const ERROR_BITS: u16 = 0xF;
+
+    /// Whether any of the [`Self::ERROR_BITS`] are set
+    pub fn has_errors(&self) -> bool {
+        self.0 & Self::ERROR_BITS != 0
+    }
+}
+
+bitfield! {
+    /// PCI Express Base Specification -> 7.5.3.6 Link Capabilities Register
+    #[derive(Clone, Copy)]
+    pub struct LinkCapabilities(u32);
+    impl Debug;
+
+    u8;
+    pub max_link_speed, _: 3, 0;
+    pub max_link_width, _: 9, 4;
+    pub dll_link_active_reporting_capable, _: 20;
+    pub port_number, _: 31, 24;
+}
+
+bitfield! {
+    /// PCI Express Base Specification -> 7.5.3.8 Link Status Register
+    #[derive(Clone, Copy)]
+    pub struct LinkStatus(u16);
+    impl Debug;
+
+    u8;
+    pub current_link_speed, _: 3, 0;
+    pub negotiated_link_width, _: 9, 4;
+    pub link_training, _: 11;
+    pub dll_link_active, _: 13;
+}
+
+/// The link speed, decoded from the link speed fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[repr(u8)]
+pub enum LinkSpeed {
+    /// 2.5 GT/s (PCIe 1)
+    Gt2_5 = 1,
+    /// 5 GT/s (PCIe 2)
+    Gt5 = 2,
+    /// 8 GT/s (PCIe 3)
+    Gt8 = 3,
+    /// 16 GT/s (PCIe 4)
+    Gt16 = 4,
+    /// 32 GT/s (PCIe 5)
+    Gt32 = 5,
+    /// 64 GT/s (PCIe 6)
+    Gt64 = 6,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+impl LinkSpeed {
+    /// The speed in MT/s, so that 2.5 GT/s can be represented without floats
+    pub fn mega_transfers_per_second(&self) -> Option<u32> {
+        match self {
+            Self::Gt2_5 => Some(2_500),
+            Self::Gt5 => Some(5_000),
+            Self::Gt8 => Some(8_000),
+            Self::Gt16 => Some(16_000),
+            Self::Gt32 => Some(32_000),
+            Self::Gt64 => Some(64_000),
+            Self::Unknown(_) => None,
+        }
+    }
+}
+
+impl PciFunction<'_> {
+    /// Returns `None` if the header type is unknown, and `Some(None)` if the function is not PCIe
+    #[must_use]
+    pub fn pci_express(&mut self) -> Option<Option<PciExpressCap>> {
+        PciExpressCap::find(self)
+    }
+
+    /// Returns `None` if the header type is unknown or the function is not PCIe
+    #[must_use]
+    pub fn pcie_port_type(&mut self) -> Option<PciePortType> {
+        Some(self.pci_express()??.port_type())
+    }
+
+    /// If the function is a root port or downstream switch port that reports its link is down, the buses behind it.
+    /// Every read from those buses returns all ones, so there is no point in scanning them.
+    pub(super) fn buses_behind_link_down(&mut self) -> Option<RangeInclusive<u8>> {
+        if self.header_type() != HeaderType::PciToPciBridge {
+            return None;
+        }
+        let mut pci_express = self.pci_express()??;
+        let link_down = pci_express.port_type().is_downstream_port()
+            && pci_express
+                .link_capabilities()
+                .dll_link_active_reporting_capable()
+            && !pci_express.link_status().dll_link_active();
+        if !link_down {
+            return None;
+        }
+        let secondary_bus = self.read_config_u8(0x19);
+        let subordinate_bus = self.read_config_u8(0x1A);
+        // A bridge that hasn't been configured has a secondary bus number of 0
+        (secondary_bus > self.address.bus).then_some(secondary_bus..=subordinate_bus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 6,
+        function: 0,
+    };
+
+    /// A function with a PCI Express capability at `0x40`, with the raw device/port type
+    fn mock_pcie(
+        header_type: u8,
+        port_type: u8,
+        link_capabilities: u32,
+        f: impl FnOnce(&mut MockConfig),
+    ) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam).with_ecam_buses(0..=2);
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x06, 0x04, 0x00], header_type)
+            .pci_express(port_type, 0, 0, link_capabilities, 0);
+        f(&mut mock);
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn wait_for_link_of_an_endpoint_without_dll_reporting() {
+        let mut pci = mock_pcie(0x0, 0x0, 0, |_| {});
+        let mut function = pci.function_at(0, 6, 0).unwrap();
+        let mut pci_express = function.pci_express().unwrap().unwrap();
+        assert!(!pci_express.wait_for_link(|| panic!("The link can't be polled")));
+        // 0x19 is a BAR in a general device header
+        assert!(
+            !pci.mock()
+                .log()
+                .iter()
+                .any(|access| access.register_offset == 0x18 || access.register_offset == 0x19)
+        );
+    }
+
+    #[test]
+    fn wait_for_link_polls_the_secondary_bus() {
+        let mut pci = mock_pcie(0x1, 0x4, 0, |mock| {
+            mock.function(ADDRESS).set_bytes(0x18, &[0, 2, 2, 0]);
+            mock.function(PciAddress::new(0, 2, 0, 0)).header(
+                0x1234,
+                0x9ABC,
+                [0x02, 0x00, 0x00],
+                0x0,
+            );
+        });
+        let mut function = pci.function_at(0, 6, 0).unwrap();
+        let mut pci_express = function.pci_express().unwrap().unwrap();
+        assert!(pci_express.wait_for_link(|| panic!("The link is up")));
+    }
+
+    #[test]
+    fn wait_for_link_times_out_without_a_device() {
+        let mut pci = mock_pcie(0x1, 0x6, 0, |mock| {
+            mock.function(ADDRESS).set_bytes(0x18, &[0, 2, 2, 0]);
+        });
+        let mut function = pci.function_at(0, 6, 0).unwrap();
+        let mut pci_express = function.pci_express().unwrap().unwrap();
+        let mut polls = 0;
+        assert!(!pci_express.wait_for_link(|| {
+            polls += 1;
+            polls == 3
+        }));
+    }
+
+    #[test]
+    fn wait_for_link_with_dll_reporting() {
+        let mut pci = mock_pcie(0x1, 0x4, 1 << 20, |mock| {
+            mock.function(ADDRESS).set_u16(0x52, 1 << 13);
+        });
+        let mut function = pci.function_at(0, 6, 0).unwrap();
+        let mut pci_express = function.pci_express().unwrap().unwrap();
+        assert!(pci_express.wait_for_link(|| panic!("The link is up")));
+    }
+}
@@ -0,0 +1,213 @@
+use core::{ops::RangeInclusive, ptr::NonNull};
+
+use super::*;
+
+/// Creates a [`PciAccess`] for 1 ECAM window, after checking that the window and the mapping make sense.
+/// A bad mapping usually doesn't fault. Instead, every read returns all ones, which looks like there are no devices.
+/// This is the recommended way to use PCIe. [`PciAccess::new_pcie`] does no checks.
+#[derive(Debug)]
+pub struct PcieBuilder {
+    window: EcamWindow,
+    mapped_mem: NonNull<[u8]>,
+    expected_segment: Option<u16>,
+    expected_buses: Option<RangeInclusive<u8>>,
+    endianness: EcamEndianness,
+}
+
+/// Why [`PcieBuilder::build`] failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PcieSetupError {
+    /// The mapped memory is smaller than [`EcamWindow::byte_len`]
+    MappingTooSmall { needed: usize, got: usize },
+    /// The window's base address is not aligned to 1 MiB, or the mapped memory is not aligned to 4 KiB
+    Misaligned,
+    /// Reading the vendor ID of function 0 of device 0 on the first bus returned all ones, so the mapping probably doesn't point to the ECAM region
+    ProbeReadAllOnes,
+    /// The window is for a different segment group than [`PcieBuilder::expect_segment`]
+    SegmentMismatch { expected: u16, actual: u16 },
+    /// The window doesn't cover all of the buses from [`PcieBuilder::expect_buses`]
+    BusesNotCovered {
+        bus_number_start: u8,
+        bus_number_end: u8,
+    },
+}
+
+impl PcieBuilder {
+    /// Accepts an [`EcamWindow`], or an `McfgEntry` if the `acpi` feature is enabled.
+    ///
+    /// # Safety
+    /// The mapped mem must point to physical memory for the ECAM window, which you can calculate using [`EcamWindow::phys_range`].
+    /// It must stay mapped on every CPU that the [`PciAccess`] is sent to.
+    pub unsafe fn new(window: impl Into<EcamWindow>, mapped_mem: NonNull<[u8]>) -> Self {
+        Self {
+            window: window.into(),
+            mapped_mem,
+            expected_segment: None,
+            expected_buses: None,
+            endianness: EcamEndianness::Little,
+        }
+    }
+
+    /// Fail if the window is not for this segment group
+    pub fn expect_segment(mut self, segment: u16) -> Self {
+        self.expected_segment = Some(segment);
+        self
+    }
+
+    /// Fail if the window doesn't cover at least these buses
+    pub fn expect_buses(mut self, buses: RangeInclusive<u8>) -> Self {
+        self.expected_buses = Some(buses);
+        self
+    }
+
+    /// See [`PciAccess::new_pcie_with_endianness`]
+    pub fn endianness(mut self, endianness: EcamEndianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    pub fn build(self) -> Result<PciAccess, PcieSetupError> {
+        let window = self.window;
+        if let Some(expected) = self
+            .expected_segment
+            .filter(|expected| *expected != window.segment_group)
+        {
+            return Err(PcieSetupError::SegmentMismatch {
+                expected,
+                actual: window.segment_group,
+            });
+        }
+        if let Some(buses) = self.expected_buses.filter(|buses| {
+            !window.buses().contains(buses.start()) || !window.buses().contains(buses.end())
+        }) {
+            return Err(PcieSetupError::BusesNotCovered {
+                bus_number_start: *buses.start(),
+                bus_number_end: *buses.end(),
+            });
+        }
+        if !window.base_address.is_multiple_of(1 << 20)
+            || !self.mapped_mem.addr().get().is_multiple_of(0x1000)
+        {
+            return Err(PcieSetupError::Misaligned);
+        }
+        let (needed, got) = (window.byte_len(), self.mapped_mem.len());
+        if got < needed {
+            return Err(PcieSetupError::MappingTooSmall { needed, got });
+        }
+        let mut pci = unsafe {
+            PciAccess::new_pcie_with_endianness([(window, self.mapped_mem)], self.endianness)
+        };
+        let address = PciAddress::new(window.segment_group, window.bus_number_start, 0, 0);
+        if pci.read_u16(address, 0x0) == u16::MAX {
+            return Err(PcieSetupError::ProbeReadAllOnes);
+        }
+        Ok(pci)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    #[repr(align(4096))]
+    struct Page([u8; 0x1000]);
+
+    /// 1 bus of ECAM memory. Function 0 of device 0 has a vendor ID unless `empty` is `true`.
+    fn ecam_memory(empty: bool) -> Vec<Page> {
+        let mut pages = vec![Page([0xFF; 0x1000]); 256];
+        if !empty {
+            pages[0].0[..4].copy_from_slice(&0x5678_1234u32.to_le_bytes());
+        }
+        pages
+    }
+
+    fn mapping(pages: &mut [Page], offset: usize, len: usize) -> NonNull<[u8]> {
+        let ptr = NonNull::new(pages.as_mut_ptr().cast::<u8>()).unwrap();
+        NonNull::slice_from_raw_parts(unsafe { ptr.add(offset) }, len)
+    }
+
+    const WINDOW: EcamWindow = EcamWindow {
+        base_address: 0xE000_0000,
+        segment_group: 1,
+        bus_number_start: 0,
+        bus_number_end: 0,
+    };
+
+    #[test]
+    fn good_mapping() {
+        let mut pages = ecam_memory(false);
+        let mut pci = unsafe { PcieBuilder::new(WINDOW, mapping(&mut pages, 0, 1 << 20)) }
+            .expect_segment(1)
+            .expect_buses(0..=0)
+            .build()
+            .unwrap();
+        assert_eq!(pci.read_u32(PciAddress::new(1, 0, 0, 0), 0x0), 0x5678_1234);
+    }
+
+    #[test]
+    fn mapping_too_small() {
+        let mut pages = ecam_memory(false);
+        let result = unsafe { PcieBuilder::new(WINDOW, mapping(&mut pages, 0, 0x1000)) }.build();
+        assert_eq!(
+            result.unwrap_err(),
+            PcieSetupError::MappingTooSmall {
+                needed: 1 << 20,
+                got: 0x1000,
+            }
+        );
+    }
+
+    #[test]
+    fn misaligned() {
+        let mut pages = ecam_memory(false);
+        let window = EcamWindow {
+            base_address: 0xE000_1000,
+            ..WINDOW
+        };
+        let result = unsafe { PcieBuilder::new(window, mapping(&mut pages, 0, 1 << 20)) }.build();
+        assert_eq!(result.unwrap_err(), PcieSetupError::Misaligned);
+        let result =
+            unsafe { PcieBuilder::new(WINDOW, mapping(&mut pages, 0x8, (1 << 20) - 0x8)) }.build();
+        assert_eq!(result.unwrap_err(), PcieSetupError::Misaligned);
+    }
+
+    #[test]
+    fn probe_reads_all_ones() {
+        let mut pages = ecam_memory(true);
+        let result = unsafe { PcieBuilder::new(WINDOW, mapping(&mut pages, 0, 1 << 20)) }.build();
+        assert_eq!(result.unwrap_err(), PcieSetupError::ProbeReadAllOnes);
+    }
+
+    #[test]
+    fn segment_mismatch() {
+        let mut pages = ecam_memory(false);
+        let result = unsafe { PcieBuilder::new(WINDOW, mapping(&mut pages, 0, 1 << 20)) }
+            .expect_segment(0)
+            .build();
+        assert_eq!(
+            result.unwrap_err(),
+            PcieSetupError::SegmentMismatch {
+                expected: 0,
+                actual: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn buses_not_covered() {
+        let mut pages = ecam_memory(false);
+        let result = unsafe { PcieBuilder::new(WINDOW, mapping(&mut pages, 0, 1 << 20)) }
+            .expect_buses(0..=1)
+            .build();
+        assert_eq!(
+            result.unwrap_err(),
+            PcieSetupError::BusesNotCovered {
+                bus_number_start: 0,
+                bus_number_end: 1,
+            }
+        );
+    }
+}
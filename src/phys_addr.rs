@@ -0,0 +1,33 @@
+#[cfg(feature = "x86")]
+pub use x86_64::PhysAddr;
+
+#[cfg(not(feature = "x86"))]
+mod generic {
+    use core::ops::Add;
+
+    /// A physical address. This is used instead of `x86_64::PhysAddr` when the `x86` feature is disabled.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[repr(transparent)]
+    pub struct PhysAddr(u64);
+
+    impl PhysAddr {
+        pub const fn new(addr: u64) -> Self {
+            Self(addr)
+        }
+
+        pub const fn as_u64(self) -> u64 {
+            self.0
+        }
+    }
+
+    impl Add<u64> for PhysAddr {
+        type Output = Self;
+
+        fn add(self, rhs: u64) -> Self::Output {
+            Self(self.0 + rhs)
+        }
+    }
+}
+
+#[cfg(not(feature = "x86"))]
+pub use generic::PhysAddr;
@@ -0,0 +1,352 @@
+use core::fmt::Debug;
+
+use bitfield::bitfield;
+
+use super::*;
+
+/// The PCI Power Management capability (ID `0x1`)
+///
+/// PCI Bus Power Management Interface Specification Rev. 1.2 -> 3. PCI Power Management Interface
+pub struct PowerManagement<'a> {
+    pci: &'a mut PciAccess,
+    address: PciAddress,
+    ptr: u16,
+}
+
+impl<'a> PowerManagement<'a> {
+    pub(super) fn find(function: &'a mut PciFunction) -> Option<Option<Self>> {
+        Some(function.find_capability(0x1)?.map(|capability| Self {
+            pci: function.pci,
+            address: function.address,
+            ptr: capability.ptr_to_self.into(),
+        }))
+    }
+}
+
+impl PowerManagement<'_> {
+    #[must_use]
+    pub fn capabilities(&mut self) -> PowerManagementCapabilities {
+        PowerManagementCapabilities(self.pci.read_u16(self.address, self.ptr + 0x2))
+    }
+
+    /// `1` for PM 1.0, `2` for PM 1.1, and `3` for PM 1.2
+    #[must_use]
+    pub fn version(&mut self) -> u8 {
+        self.capabilities().version()
+    }
+
+    /// The power states that the function can assert PME from
+    #[must_use]
+    pub fn pme_support(&mut self) -> PmeSupport {
+        PmeSupport(self.capabilities().pme_support())
+    }
+
+    #[must_use]
+    pub fn d1_support(&mut self) -> bool {
+        self.capabilities().d1_support()
+    }
+
+    #[must_use]
+    pub fn d2_support(&mut self) -> bool {
+        self.capabilities().d2_support()
+    }
+
+    #[must_use]
+    pub fn control_status(&mut self) -> PowerManagementControlStatus {
+        PowerManagementControlStatus(self.pci.read_u16(self.address, self.ptr + 0x4))
+    }
+
+    /// Writes the register as it is. Note that writing 1 to [`PowerManagementControlStatus::pme_status`] clears it.
+    pub fn set_control_status(&mut self, control_status: PowerManagementControlStatus) {
+        self.pci
+            .write_u16(self.address, self.ptr + 0x4, control_status.0);
+    }
+
+    /// Reads the register, calls `f`, and writes it back without clearing the PME status
+    fn update_control_status(&mut self, f: impl FnOnce(&mut PowerManagementControlStatus)) {
+        let mut control_status = self.control_status();
+        control_status.set_pme_status(false);
+        f(&mut control_status);
+        self.set_control_status(control_status);
+    }
+
+    #[must_use]
+    pub fn power_state(&mut self) -> PowerState {
+        self.control_status().power_state().into()
+    }
+
+    /// Going from D3hot to D0 resets the function, unless [`PowerManagementControlStatus::no_soft_reset`] is set.
+    /// After changing to or from D3hot, wait 10 ms before accessing the function. After changing to or from D2, wait 200 µs.
+    pub fn set_power_state(&mut self, power_state: PowerState) {
+        self.update_control_status(|control_status| {
+            control_status.set_power_state(power_state.into());
+        });
+    }
+
+    /// Enables or disables asserting PME. Check [`Self::pme_support`] for the power states that PME can be asserted from.
+    pub fn set_pme_enabled(&mut self, pme_enabled: bool) {
+        self.update_control_status(|control_status| control_status.set_pme_enable(pme_enabled));
+    }
+
+    /// Clears [`PowerManagementControlStatus::pme_status`], which stops the function from asserting PME
+    pub fn clear_pme_status(&mut self) {
+        self.update_control_status(|control_status| control_status.set_pme_status(true));
+    }
+
+    /// Selects what [`Self::read_data`] reports. See the PM spec for what each value means (for example, `0` is the power consumed in D0).
+    pub fn set_data_select(&mut self, data_select: u8) {
+        self.update_control_status(|control_status| control_status.set_data_select(data_select));
+    }
+
+    /// The scale of [`Self::read_data`] for the current data select.
+    /// Returns `None` if the function doesn't report data for the current data select, which is always the case if it doesn't implement the Data register.
+    #[must_use]
+    pub fn data_scale(&mut self) -> Option<DataScale> {
+        match self.control_status().data_scale() {
+            0 => None,
+            1 => Some(DataScale::Tenth),
+            2 => Some(DataScale::Hundredth),
+            _ => Some(DataScale::Thousandth),
+        }
+    }
+
+    /// Reads the Data register for the current data select. Multiply it by [`Self::data_scale`] to get watts.
+    /// Returns `None` if the function doesn't report data for the current data select.
+    #[must_use]
+    pub fn read_data(&mut self) -> Option<u8> {
+        self.data_scale()?;
+        Some(self.pci.read_u8(self.address, self.ptr + 0x7))
+    }
+
+    /// This is only used by PCI-to-PCI bridges. For other functions it is 0.
+    #[must_use]
+    pub fn bridge_support_extensions(&mut self) -> BridgeSupportExtensions {
+        BridgeSupportExtensions(self.pci.read_u8(self.address, self.ptr + 0x6))
+    }
+}
+
+impl Debug for PowerManagement<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PowerManagement")
+            .field("address", &format_args!("{}", self.address))
+            .field("ptr", &format_args!("0x{:X}", self.ptr))
+            .finish()
+    }
+}
+
+impl PciFunction<'_> {
+    /// Returns `None` if the header type is unknown, and `Some(None)` if the function doesn't have the Power Management capability
+    #[must_use]
+    pub fn power_management(&mut self) -> Option<Option<PowerManagement>> {
+        PowerManagement::find(self)
+    }
+}
+
+bitfield! {
+    /// PCI Bus Power Management Interface Specification Rev. 1.2 -> 3.2.3. PMC - Power Management Capabilities
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PowerManagementCapabilities(u16);
+    impl Debug;
+
+    pub u8, version, _: 2, 0;
+    pub pme_clock, _: 3;
+    pub device_specific_initialization, _: 5;
+    pub u8, aux_current, _: 8, 6;
+    pub d1_support, _: 9;
+    pub d2_support, _: 10;
+    /// See [`PmeSupport`]
+    pub u8, pme_support, _: 15, 11;
+}
+
+bitfield! {
+    /// The power states that a function can assert PME from
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PmeSupport(u8);
+    impl Debug;
+
+    pub from_d0, _: 0;
+    pub from_d1, _: 1;
+    pub from_d2, _: 2;
+    pub from_d3hot, _: 3;
+    /// The function can wake the system from D3cold, if it has auxiliary power
+    pub from_d3cold, _: 4;
+}
+
+bitfield! {
+    /// PCI Bus Power Management Interface Specification Rev. 1.2 -> 3.2.4. PMCSR - Power Management Control/Status
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PowerManagementControlStatus(u16);
+    impl Debug;
+
+    /// See [`PowerState`]
+    pub u8, power_state, set_power_state: 1, 0;
+    /// If this is 1, going from D3hot to D0 doesn't reset the function
+    pub no_soft_reset, _: 3;
+    pub pme_enable, set_pme_enable: 8;
+    pub u8, data_select, set_data_select: 12, 9;
+    pub u8, data_scale, _: 14, 13;
+    /// This is set when the function asserts PME. Write 1 to clear it.
+    pub pme_status, set_pme_status: 15;
+}
+
+bitfield! {
+    /// PCI Bus Power Management Interface Specification Rev. 1.2 -> 3.2.5. PMCSR_BSE - PMCSR PCI-to-PCI Bridge Support Extensions
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct BridgeSupportExtensions(u8);
+    impl Debug;
+
+    /// If this is 1, the secondary bus is stopped (B3) when the bridge goes to D3hot. Otherwise, its clock is stopped (B2).
+    pub b2_b3, _: 6;
+    /// Bus power/clock control. If this is 0, the bridge doesn't change the secondary bus when its power state changes.
+    pub bus_power_clock_control_enable, _: 7;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerState {
+    D0,
+    D1,
+    D2,
+    D3Hot,
+}
+
+impl From<u8> for PowerState {
+    fn from(value: u8) -> Self {
+        match value & 0b11 {
+            0 => Self::D0,
+            1 => Self::D1,
+            2 => Self::D2,
+            _ => Self::D3Hot,
+        }
+    }
+}
+
+impl From<PowerState> for u8 {
+    fn from(value: PowerState) -> Self {
+        match value {
+            PowerState::D0 => 0,
+            PowerState::D1 => 1,
+            PowerState::D2 => 2,
+            PowerState::D3Hot => 3,
+        }
+    }
+}
+
+/// What to multiply [`PowerManagement::read_data`] by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataScale {
+    /// 0.1x
+    Tenth,
+    /// 0.01x
+    Hundredth,
+    /// 0.001x
+    Thousandth,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 7,
+        function: 0,
+    };
+
+    /// A function with a Power Management capability at `0x60`, whose PMCSR has PME_Status set.
+    /// Only power state, PME enable, and data select are writable, and PME_Status is RW1C.
+    fn mock_pm(capabilities: u16, data_scale: u8, data: u8) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        let control_status = 1 << 15 | u16::from(data_scale) << 13;
+        let mut body = [0; 6];
+        body[0..2].copy_from_slice(&capabilities.to_le_bytes());
+        body[2..4].copy_from_slice(&control_status.to_le_bytes());
+        body[5] = data;
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .capability(0x60, 0x1, &body)
+            .read_only(0x62, 6)
+            .writable_u8s(0x64, 1, 0b11)
+            .writable_u8s(0x65, 1, 0b0001_1111)
+            .write_1_to_clear_u16(0x64, 1 << 15);
+        mock.into_pci_access()
+    }
+
+    fn pmcsr_writes(pci: &mut PciAccess) -> Vec<u64> {
+        pci.mock()
+            .writes()
+            .filter(|access| access.register_offset == 0x64)
+            .map(|access| access.value)
+            .collect()
+    }
+
+    #[test]
+    fn pm_1_2_device() {
+        // Version 3 (PM 1.2), D1 and D2, and PME from D0, D3hot, and D3cold
+        let mut pci = mock_pm(0b11001 << 11 | 1 << 10 | 1 << 9 | 0x3, 2, 25);
+        let mut function = pci.function_at(0, 7, 0).unwrap();
+        let mut power_management = function.power_management().unwrap().unwrap();
+        assert_eq!(power_management.version(), 3);
+        assert!(power_management.d1_support());
+        assert!(power_management.d2_support());
+        let pme_support = power_management.pme_support();
+        assert!(pme_support.from_d0());
+        assert!(!pme_support.from_d1());
+        assert!(!pme_support.from_d2());
+        assert!(pme_support.from_d3hot());
+        assert!(pme_support.from_d3cold());
+        assert_eq!(power_management.power_state(), PowerState::D0);
+        power_management.set_power_state(PowerState::D3Hot);
+        assert_eq!(power_management.power_state(), PowerState::D3Hot);
+        power_management.set_pme_enabled(true);
+        power_management.set_data_select(0x3);
+        assert_eq!(power_management.data_scale(), Some(DataScale::Hundredth));
+        assert_eq!(power_management.read_data(), Some(25));
+        assert!(power_management.control_status().pme_status());
+        // None of the updates echo PME_Status back. The read-only data scale is written back as it is.
+        let data_scale = 2 << 13;
+        assert_eq!(
+            pmcsr_writes(&mut pci),
+            [
+                data_scale | 0b11,
+                data_scale | 1 << 8 | 0b11,
+                data_scale | 0x3 << 9 | 1 << 8 | 0b11
+            ]
+        );
+        let control_status = pci.mock().function(ADDRESS).u16(0x64);
+        assert_eq!(control_status, 1 << 15 | 2 << 13 | 0x3 << 9 | 1 << 8 | 0b11);
+    }
+
+    #[test]
+    fn clear_pme_status() {
+        let mut pci = mock_pm(0x3, 0, 0);
+        let mut function = pci.function_at(0, 7, 0).unwrap();
+        let mut power_management = function.power_management().unwrap().unwrap();
+        power_management.clear_pme_status();
+        assert!(!power_management.control_status().pme_status());
+        assert_eq!(pmcsr_writes(&mut pci), [1 << 15]);
+    }
+
+    #[test]
+    fn minimal_pm_1_0_device() {
+        let mut pci = mock_pm(0x1, 0, 0xFF);
+        let mut function = pci.function_at(0, 7, 0).unwrap();
+        let mut power_management = function.power_management().unwrap().unwrap();
+        assert_eq!(power_management.version(), 1);
+        assert!(!power_management.d1_support());
+        assert!(!power_management.d2_support());
+        assert_eq!(power_management.pme_support(), PmeSupport(0));
+        assert_eq!(power_management.data_scale(), None);
+        assert_eq!(power_management.read_data(), None);
+        assert_eq!(
+            power_management.bridge_support_extensions(),
+            BridgeSupportExtensions(0)
+        );
+        power_management.set_power_state(PowerState::D3Hot);
+        power_management.set_power_state(PowerState::D0);
+        assert!(power_management.control_status().pme_status());
+        assert_eq!(pmcsr_writes(&mut pci), [0b11, 0b00]);
+    }
+}
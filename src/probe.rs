@@ -0,0 +1,172 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProbeResult {
+    Present {
+        vendor_id: u16,
+        device_id: u16,
+    },
+    Absent,
+    /// The function reads all 1s, but so does the first device of the segment group, which should always be present.
+    /// This usually means that config access itself is broken, for example because the ECAM region is mapped wrong.
+    SuspiciousAllOnes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelfTestError {
+    /// The host bridge (the first function of the segment group) reads all 1s
+    HostBridgeAllOnes { address: PciAddress },
+    /// Writing to the host bridge's interrupt line register didn't read back the same value
+    ScratchWriteLost {
+        address: PciAddress,
+        wrote: u8,
+        read_back: u8,
+    },
+}
+
+impl PciAccess {
+    /// The address of the first function in the segment group, which is usually the host bridge
+    fn host_bridge_address(&self, segment: u16) -> PciAddress {
+        PciAddress::new(segment, *self.known_buses_in_segment(segment).start(), 0, 0)
+    }
+
+    /// Like [`PciBus::device`] and [`PciDevice::function`], but tells you if a function that isn't there might actually be a problem with config access
+    pub fn probe(&mut self, address: PciAddress) -> ProbeResult {
+        let ids = self.read_u32(address, 0x0);
+        let vendor_id = ids as u16;
+        if vendor_id != u16::MAX {
+            ProbeResult::Present {
+                vendor_id,
+                device_id: (ids >> 16) as u16,
+            }
+        } else if self.read_u16(self.host_bridge_address(address.segment), 0x0) == u16::MAX {
+            ProbeResult::SuspiciousAllOnes
+        } else {
+            ProbeResult::Absent
+        }
+    }
+
+    /// Checks that config access works by reading the host bridge's vendor ID, and writing to and reading back its interrupt line register.
+    /// The interrupt line register is not used by the hardware, and its original value is restored.
+    pub fn self_test(&mut self) -> Result<(), SelfTestError> {
+        let segment = self.segment_groups().next().unwrap_or_default();
+        let address = self.host_bridge_address(segment);
+        if self.read_u16(address, 0x0) == u16::MAX {
+            return Err(SelfTestError::HostBridgeAllOnes { address });
+        }
+        let original = self.read_u8(address, 0x3C);
+        let wrote = !original;
+        self.write_u8(address, 0x3C, wrote);
+        let read_back = self.read_u8(address, 0x3C);
+        self.write_u8(address, 0x3C, original);
+        if read_back == wrote {
+            Ok(())
+        } else {
+            Err(SelfTestError::ScratchWriteLost {
+                address,
+                wrote,
+                read_back,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const HOST_BRIDGE: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+
+    /// A host bridge with interrupt line `0x0B`, and a network controller at `0:3.0`
+    fn mock() -> MockConfig {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(HOST_BRIDGE)
+            .header(0x8086, 0x29C0, [0x06, 0x00, 0x00], 0x0)
+            .set_u8(0x3C, 0x0B);
+        mock.function(PciAddress::new(0, 0, 3, 0))
+            .header(0x8086, 0x10D3, [0x02, 0x00, 0x00], 0x0);
+        mock
+    }
+
+    /// The offset and value of each write
+    fn writes(pci: &mut PciAccess) -> Vec<(u16, u64)> {
+        pci.mock()
+            .writes()
+            .map(|access| (access.register_offset, access.value))
+            .collect()
+    }
+
+    #[test]
+    fn probe() {
+        let mut pci = mock().into_pci_access();
+        assert_eq!(
+            pci.probe(PciAddress::new(0, 0, 3, 0)),
+            ProbeResult::Present {
+                vendor_id: 0x8086,
+                device_id: 0x10D3,
+            }
+        );
+        assert_eq!(pci.probe(PciAddress::new(0, 0, 4, 0)), ProbeResult::Absent);
+
+        let mut mock = mock();
+        mock.remove_function(HOST_BRIDGE);
+        let mut pci = mock.into_pci_access();
+        assert_eq!(
+            pci.probe(PciAddress::new(0, 0, 4, 0)),
+            ProbeResult::SuspiciousAllOnes
+        );
+        // The function is there, so the host bridge isn't checked
+        pci.mock().clear_log();
+        assert!(matches!(
+            pci.probe(PciAddress::new(0, 0, 3, 0)),
+            ProbeResult::Present { .. }
+        ));
+        assert_eq!(pci.mock().read_count(), 1);
+    }
+
+    #[test]
+    fn self_test_passes() {
+        let mut pci = mock().into_pci_access();
+        assert_eq!(pci.self_test(), Ok(()));
+        assert_eq!(writes(&mut pci), [(0x3C, 0xF4), (0x3C, 0x0B)]);
+        assert_eq!(pci.mock().function(HOST_BRIDGE).u8(0x3C), 0x0B);
+    }
+
+    #[test]
+    fn self_test_scratch_write_lost() {
+        let mut mock = mock();
+        mock.function(HOST_BRIDGE).read_only(0x3C, 1);
+        let mut pci = mock.into_pci_access();
+        assert_eq!(
+            pci.self_test(),
+            Err(SelfTestError::ScratchWriteLost {
+                address: HOST_BRIDGE,
+                wrote: 0xF4,
+                read_back: 0x0B,
+            })
+        );
+        // The original value is written back even though the test failed
+        assert_eq!(writes(&mut pci), [(0x3C, 0xF4), (0x3C, 0x0B)]);
+    }
+
+    #[test]
+    fn self_test_host_bridge_all_ones() {
+        let mut mock = mock();
+        mock.remove_function(HOST_BRIDGE);
+        let mut pci = mock.into_pci_access();
+        assert_eq!(
+            pci.self_test(),
+            Err(SelfTestError::HostBridgeAllOnes {
+                address: HOST_BRIDGE,
+            })
+        );
+        assert_eq!(writes(&mut pci), []);
+    }
+}
@@ -0,0 +1,260 @@
+use super::*;
+
+/// Which functions a driver supports. Fields that are `None` match anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MatchEntry {
+    pub vendor: Option<u16>,
+    pub device: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub prog_if: Option<u8>,
+}
+
+impl MatchEntry {
+    pub fn matches(&self, info: &FunctionInfo) -> bool {
+        fn field_matches<T: PartialEq>(expected: Option<T>, actual: T) -> bool {
+            expected.is_none_or(|expected| expected == actual)
+        }
+        field_matches(self.vendor, info.vendor_id)
+            && field_matches(self.device, info.device_id)
+            && field_matches(self.class, info.class_code)
+            && field_matches(self.subclass, info.sub_class)
+            && field_matches(self.prog_if, info.prog_if)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MatchMode {
+    /// Only the first entry that matches a function is used
+    #[default]
+    FirstMatch,
+    /// Every entry that matches a function is used, in order
+    AllMatches,
+}
+
+/// A list of [`MatchEntry`]s. See [`PciAccess::probe_drivers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProbeTable<'a> {
+    pub entries: &'a [MatchEntry],
+    pub mode: MatchMode,
+}
+
+impl<'a> ProbeTable<'a> {
+    pub fn new(entries: &'a [MatchEntry]) -> Self {
+        Self {
+            entries,
+            mode: MatchMode::FirstMatch,
+        }
+    }
+
+    pub fn with_mode(self, mode: MatchMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// The indexes of the entries that match the function
+    pub fn matching(&self, info: &FunctionInfo) -> impl Iterator<Item = usize> + use<'a> {
+        let info = *info;
+        let matching = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(move |(_, entry)| entry.matches(&info))
+            .map(|(index, _)| index);
+        let limit = match self.mode {
+            MatchMode::FirstMatch => 1,
+            MatchMode::AllMatches => usize::MAX,
+        };
+        matching.take(limit)
+    }
+}
+
+impl PciAccess {
+    /// Scans every function and calls `on_match` with the index of each matching entry in the table and the function.
+    pub fn probe_drivers(
+        &mut self,
+        table: &ProbeTable,
+        mut on_match: impl FnMut(usize, &mut PciFunction),
+    ) {
+        self.for_each_function(|function, multi_function| {
+            let info = FunctionInfo::read(function, multi_function);
+            for index in table.matching(&info) {
+                on_match(index, function);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    const AHCI: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 3,
+        function: 0,
+    };
+
+    /// A host bridge, a multi-function ISA bridge with an IDE controller, an xHCI controller, and an AHCI controller
+    fn mock_topology() -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(PciAddress::new(0, 0, 0, 0))
+            .header(0x8086, 0x1237, [0x06, 0x00, 0x00], 0x0);
+        mock.function(PciAddress::new(0, 0, 1, 0))
+            .header(0x8086, 0x7000, [0x06, 0x01, 0x00], 0x80);
+        mock.function(PciAddress::new(0, 0, 1, 1))
+            .header(0x8086, 0x7010, [0x01, 0x01, 0x80], 0x0);
+        mock.function(PciAddress::new(0, 0, 2, 0))
+            .header(0x1B36, 0x000D, [0x0C, 0x03, 0x30], 0x0);
+        mock.function(AHCI)
+            .header(0x8086, 0x2922, [0x01, 0x06, 0x01], 0x0);
+        mock.into_pci_access()
+    }
+
+    fn probe(pci: &mut PciAccess, table: &ProbeTable) -> Vec<(usize, PciAddress)> {
+        let mut matches = Vec::new();
+        pci.probe_drivers(table, |index, function| {
+            matches.push((index, function.address()));
+        });
+        matches
+    }
+
+    fn info(
+        vendor_id: u16,
+        device_id: u16,
+        [class_code, sub_class, prog_if]: [u8; 3],
+    ) -> FunctionInfo {
+        FunctionInfo {
+            address: AHCI,
+            vendor_id,
+            device_id,
+            class_code,
+            sub_class,
+            prog_if,
+            header_type: HeaderType::GeneralDevice,
+            multi_function: false,
+        }
+    }
+
+    #[test]
+    fn every_field_must_match() {
+        let ahci = info(0x8086, 0x2922, [0x01, 0x06, 0x01]);
+        assert!(MatchEntry::default().matches(&ahci));
+        let entry = MatchEntry {
+            vendor: Some(0x8086),
+            device: Some(0x2922),
+            class: Some(0x01),
+            subclass: Some(0x06),
+            prog_if: Some(0x01),
+        };
+        assert!(entry.matches(&ahci));
+        assert!(!entry.matches(&info(0x1022, 0x2922, [0x01, 0x06, 0x01])));
+        assert!(!entry.matches(&info(0x8086, 0x2923, [0x01, 0x06, 0x01])));
+        assert!(!entry.matches(&info(0x8086, 0x2922, [0x02, 0x06, 0x01])));
+        assert!(!entry.matches(&info(0x8086, 0x2922, [0x01, 0x08, 0x01])));
+        assert!(!entry.matches(&info(0x8086, 0x2922, [0x01, 0x06, 0x00])));
+        let any_intel_storage = MatchEntry {
+            vendor: Some(0x8086),
+            class: Some(0x01),
+            ..Default::default()
+        };
+        assert!(any_intel_storage.matches(&ahci));
+        assert!(any_intel_storage.matches(&info(0x8086, 0x7010, [0x01, 0x01, 0x80])));
+        assert!(!any_intel_storage.matches(&info(0x1B36, 0x0010, [0x01, 0x08, 0x02])));
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let entries = [
+            MatchEntry {
+                class: Some(0x0C),
+                subclass: Some(0x03),
+                prog_if: Some(0x30),
+                ..Default::default()
+            },
+            MatchEntry {
+                vendor: Some(0x8086),
+                device: Some(0x2922),
+                ..Default::default()
+            },
+            MatchEntry {
+                class: Some(0x01),
+                ..Default::default()
+            },
+        ];
+        let mut pci = mock_topology();
+        assert_eq!(
+            probe(&mut pci, &ProbeTable::new(&entries)),
+            [
+                (2, PciAddress::new(0, 0, 1, 1)),
+                (0, PciAddress::new(0, 0, 2, 0)),
+                (1, AHCI),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_matches_are_in_table_order() {
+        let entries = [
+            MatchEntry {
+                class: Some(0x01),
+                ..Default::default()
+            },
+            MatchEntry::default(),
+            MatchEntry {
+                vendor: Some(0x8086),
+                device: Some(0x2922),
+                ..Default::default()
+            },
+        ];
+        let mut pci = mock_topology();
+        let table = ProbeTable::new(&entries).with_mode(MatchMode::AllMatches);
+        assert_eq!(
+            probe(&mut pci, &table),
+            [
+                (1, PciAddress::new(0, 0, 0, 0)),
+                (1, PciAddress::new(0, 0, 1, 0)),
+                (0, PciAddress::new(0, 0, 1, 1)),
+                (1, PciAddress::new(0, 0, 1, 1)),
+                (1, PciAddress::new(0, 0, 2, 0)),
+                (0, AHCI),
+                (1, AHCI),
+                (2, AHCI),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_matches() {
+        let entries = [MatchEntry {
+            vendor: Some(0x10EC),
+            ..Default::default()
+        }];
+        let mut pci = mock_topology();
+        assert_eq!(probe(&mut pci, &ProbeTable::new(&entries)), []);
+        assert_eq!(probe(&mut pci, &ProbeTable::new(&[])), []);
+    }
+
+    #[test]
+    fn callback_can_use_the_function() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(AHCI)
+            .header(0x8086, 0x2922, [0x01, 0x06, 0x01], 0x0)
+            .writable_u8s(0x4, 2, 0xFF);
+        let mut pci = mock.into_pci_access();
+        let entries = [MatchEntry {
+            class: Some(0x01),
+            subclass: Some(0x06),
+            ..Default::default()
+        }];
+        let mut device_ids = vec![];
+        pci.probe_drivers(&ProbeTable::new(&entries), |_, function| {
+            device_ids.push(function.device_id());
+            function.set_command(CommandRegister(0x6));
+        });
+        assert_eq!(device_ids, [0x2922]);
+        assert_eq!(pci.mock().function(AHCI).u16(0x4), 0x6);
+    }
+}
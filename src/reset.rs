@@ -0,0 +1,135 @@
+use super::*;
+
+/// The software reset method that [`PciFunction::reset`] used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResetKind {
+    /// A PCIe Function Level Reset (FLR). Only this function is reset.
+    FunctionLevelReset,
+    /// Putting the function into D3hot and back into D0, with the Power Management capability
+    PowerManagement,
+}
+
+impl PciFunction<'_> {
+    /// Resets the function, preferring a Function Level Reset, and then a D3hot to D0 transition.
+    /// Stop using the function (for example, clear [`CommandRegister::bus_master`]) before resetting it.
+    ///
+    /// Both methods reset the config space, including the BARs and the command register.
+    /// Call [`Self::save_config`] before resetting and [`Self::restore_config`] afterwards to keep using the function.
+    ///
+    /// There is no timer in `no_std`, so `wait_ms` is called to wait for the number of milliseconds that the spec requires:
+    /// 100 ms after a Function Level Reset, and 10 ms after each power state change.
+    /// The function is ready to use once this returns.
+    ///
+    /// Returns `None` if the function doesn't support either method.
+    /// In that case, you can reset everything behind the bridge above it with [`BridgeControl::set_secondary_bus_reset`].
+    pub fn reset(&mut self, mut wait_ms: impl FnMut(u32)) -> Option<ResetKind> {
+        let function_level_reset_started =
+            self.pci_express().flatten().is_some_and(|mut pci_express| {
+                let capable = pci_express
+                    .device_capabilities()
+                    .function_level_reset_capable();
+                if capable {
+                    let mut device_control = pci_express.device_control();
+                    device_control.set_initiate_function_level_reset(true);
+                    pci_express.set_device_control(device_control);
+                }
+                capable
+            });
+        if function_level_reset_started {
+            wait_ms(100);
+            return Some(ResetKind::FunctionLevelReset);
+        }
+        let mut power_management = self.power_management()??;
+        if power_management.control_status().no_soft_reset() {
+            return None;
+        }
+        // This doesn't write back PME_Status, so a pending PME isn't cleared
+        power_management.set_power_state(PowerState::D3Hot);
+        wait_ms(10);
+        power_management.set_power_state(PowerState::D0);
+        wait_ms(10);
+        Some(ResetKind::PowerManagement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 2,
+        function: 0,
+    };
+
+    /// An endpoint with a PCI Express capability at `0x40` and a PM 1.2 capability at `0x80` with PME_Status set
+    fn mock_function(function_level_reset_capable: bool, no_soft_reset: bool) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        let control_status = 1u16 << 15 | (no_soft_reset as u16) << 3;
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0)
+            .pci_express(0x0, (function_level_reset_capable as u32) << 28, 0, 0, 0)
+            .capability(0x80, 0x1, &[0x3, 0x0, 0x0, 0x0, 0x0, 0x0])
+            .set_u16(0x84, control_status)
+            .writable_u8s(0x84, 2, 0)
+            .writable_u8s(0x84, 1, 0b11)
+            .write_1_to_clear_u16(0x84, 1 << 15);
+        mock.into_pci_access()
+    }
+
+    fn reset(pci: &mut PciAccess) -> (Option<ResetKind>, Vec<u32>) {
+        let mut waits = vec![];
+        let kind = pci.function_at(0, 2, 0).unwrap().reset(|ms| waits.push(ms));
+        (kind, waits)
+    }
+
+    #[test]
+    fn prefers_function_level_reset() {
+        let mut pci = mock_function(true, false);
+        assert_eq!(
+            reset(&mut pci),
+            (Some(ResetKind::FunctionLevelReset), vec![100])
+        );
+        let writes = pci.mock().writes().copied().collect::<Vec<_>>();
+        assert_eq!(writes.len(), 1);
+        assert_eq!((writes[0].register_offset, writes[0].len), (0x48, 2));
+        assert_eq!(writes[0].value, 1 << 15);
+        assert!(!pci.mock().wrote_to(ADDRESS, 0x84));
+    }
+
+    #[test]
+    fn power_management_reset_keeps_pme_status() {
+        let mut pci = mock_function(false, false);
+        assert_eq!(
+            reset(&mut pci),
+            (Some(ResetKind::PowerManagement), vec![10, 10])
+        );
+        let writes = pci
+            .mock()
+            .writes()
+            .map(|access| (access.register_offset, access.value))
+            .collect::<Vec<_>>();
+        assert_eq!(writes, [(0x84, 0b11), (0x84, 0b00)]);
+        assert_eq!(pci.mock().function(ADDRESS).u16(0x84), 1 << 15);
+    }
+
+    #[test]
+    fn no_soft_reset_means_no_reset() {
+        let mut pci = mock_function(false, true);
+        assert_eq!(reset(&mut pci), (None, vec![]));
+        assert_eq!(pci.mock().writes().count(), 0);
+    }
+
+    #[test]
+    fn no_capabilities_means_no_reset() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x1234, 0x5678, [0x02, 0x00, 0x00], 0x0);
+        let mut pci = mock.into_pci_access();
+        assert_eq!(reset(&mut pci), (None, vec![]));
+        assert_eq!(pci.mock().writes().count(), 0);
+    }
+}
@@ -0,0 +1,183 @@
+use core::{convert::Infallible, mem::MaybeUninit};
+
+use super::*;
+
+/// A lightweight copy of the identifying registers of a function.
+/// It does not borrow [`PciAccess`], so you can collect these first and bind drivers later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FunctionInfo {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class_code: u8,
+    pub sub_class: u8,
+    pub prog_if: u8,
+    pub header_type: HeaderType,
+    /// Whether the device that this function belongs to is multi-function
+    pub multi_function: bool,
+}
+
+impl FunctionInfo {
+    /// `multi_function` is whether the device is multi-function, which is only in the header type of function 0
+    pub(super) fn read(function: &mut PciFunction, multi_function: bool) -> Self {
+        Self {
+            address: function.address(),
+            vendor_id: function.vendor_id(),
+            device_id: function.device_id(),
+            class_code: function.class_code(),
+            sub_class: function.sub_class(),
+            prog_if: function.prog_if(),
+            header_type: function.header_type(),
+            multi_function,
+        }
+    }
+
+    pub fn class(&self) -> ClassInfo {
+        ClassInfo {
+            class_code: self.class_code,
+            sub_class: self.sub_class,
+            prog_if: self.prog_if,
+        }
+    }
+}
+
+impl PciAccess {
+    /// Walks every function in every segment group, and calls `f` with each function and whether its device is multi-function.
+    /// The buses behind root ports and downstream switch ports whose link is down are skipped.
+    /// Stops at the first error that `f` returns.
+    pub(super) fn try_for_each_function<E>(
+        &mut self,
+        mut f: impl FnMut(&mut PciFunction, bool) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for segment in self.segment_groups() {
+            let mut segment = self.segment(segment);
+            let mut skipped_buses = BusBitmap::default();
+            for bus_number in segment.known_buses() {
+                if skipped_buses.contains(bus_number) {
+                    continue;
+                }
+                let mut bus = segment.bus(bus_number);
+                let mut devices = bus.devices();
+                while let Some(mut device) = devices.next() {
+                    let multi_function = device.is_multi_function();
+                    let mut functions = device.functions();
+                    while let Some(mut function) = functions.next() {
+                        for bus in function.buses_behind_link_down().into_iter().flatten() {
+                            skipped_buses.insert(bus);
+                        }
+                        f(&mut function, multi_function)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// See [`Self::try_for_each_function`]
+    pub(super) fn for_each_function(&mut self, mut f: impl FnMut(&mut PciFunction, bool)) {
+        let Ok(()) = self.try_for_each_function(|function, multi_function| {
+            f(function, multi_function);
+            Ok::<_, Infallible>(())
+        });
+    }
+
+    /// Walks every bus, device, and function that this access method can see, and writes a [`FunctionInfo`] for each one into `out`.
+    /// Once `out` is full, the rest of the functions are counted but not written.
+    /// The buses behind root ports and downstream switch ports whose link is down are skipped.
+    ///
+    /// Returns the total number of functions found. If this is more than `out.len()`, you can retry with a bigger buffer.
+    /// The first `min(returned, out.len())` elements of `out` are initialized.
+    pub fn scan_into(&mut self, out: &mut [MaybeUninit<FunctionInfo>]) -> usize {
+        let mut found = 0;
+        self.for_each_function(|function, multi_function| {
+            if let Some(slot) = out.get_mut(found) {
+                slot.write(FunctionInfo::read(function, multi_function));
+            }
+            found += 1;
+        });
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT_PORT: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 1,
+        function: 0,
+    };
+
+    /// A root port on bus 0 to bus 1, which has an endpoint. The root port supports Data Link Layer Link Active reporting.
+    fn mock_root_port(link_up: bool) -> PciAccess {
+        let mut mock = MockConfig::new(AccessKind::Ecam).with_ecam_buses(0..=1);
+        mock.function(ROOT_PORT)
+            .header(0x8086, 0x1111, [0x06, 0x04, 0x00], 0x1)
+            .set_bytes(0x18, &[0, 1, 1, 0])
+            .pci_express(0x4, 0, 0, 1 << 20, (link_up as u16) << 13);
+        mock.function(PciAddress::new(0, 0, 2, 0))
+            .header(0x8086, 0x2222, [0x02, 0x00, 0x00], 0x0);
+        mock.function(PciAddress::new(0, 1, 0, 0))
+            .header(0x10EC, 0x8168, [0x02, 0x00, 0x00], 0x0);
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn scan_into_finds_functions_behind_a_link_that_is_up() {
+        let mut pci = mock_root_port(true);
+        let mut out = [MaybeUninit::uninit(); 4];
+        assert_eq!(pci.scan_into(&mut out), 3);
+        let addresses = out[..3]
+            .iter()
+            .map(|info| unsafe { info.assume_init() }.address);
+        assert!(addresses.eq([
+            ROOT_PORT,
+            PciAddress::new(0, 0, 2, 0),
+            PciAddress::new(0, 1, 0, 0)
+        ]));
+    }
+
+    #[test]
+    fn scan_into_skips_the_buses_behind_a_link_that_is_down() {
+        let mut pci = mock_root_port(false);
+        let mut out = [MaybeUninit::uninit(); 4];
+        assert_eq!(pci.scan_into(&mut out), 2);
+        assert!(
+            !pci.mock()
+                .log()
+                .iter()
+                .any(|access| access.address.bus == 1)
+        );
+    }
+
+    #[test]
+    fn discover_finds_functions_behind_bridges() {
+        let mut mock = MockConfig::new(AccessKind::LegacyPortIo);
+        mock.function(PciAddress::new(0, 0, 1, 0))
+            .header(0x8086, 0x1111, [0x06, 0x04, 0x00], 0x1)
+            .set_bytes(0x18, &[0, 2, 2, 0]);
+        mock.function(PciAddress::new(0, 0, 2, 0))
+            .header(0x104C, 0xAC50, [0x06, 0x07, 0x00], 0x2)
+            .set_bytes(0x18, &[0, 5, 5, 0]);
+        mock.function(PciAddress::new(0, 2, 0, 0))
+            .header(0x10EC, 0x8168, [0x02, 0x00, 0x00], 0x0);
+        mock.function(PciAddress::new(0, 5, 0, 0))
+            .header(0x1180, 0x0476, [0x0C, 0x00, 0x10], 0x0);
+        let mut pci = mock.into_pci_access();
+        let mut out = [MaybeUninit::uninit(); 4];
+        assert_eq!(pci.scan_into(&mut out), 2);
+        pci.discover();
+        assert!(pci.discovered_buses().eq([0, 2, 5]));
+        assert_eq!(pci.known_buses(), 0..=5);
+        assert_eq!(pci.scan_into(&mut out), 4);
+        let addresses = out.iter().map(|info| unsafe { info.assume_init() }.address);
+        assert!(addresses.eq([
+            PciAddress::new(0, 0, 1, 0),
+            PciAddress::new(0, 0, 2, 0),
+            PciAddress::new(0, 2, 0, 0),
+            PciAddress::new(0, 5, 0, 0),
+        ]));
+    }
+}
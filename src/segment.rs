@@ -0,0 +1,72 @@
+use core::ops::RangeInclusive;
+
+use super::*;
+
+/// A PCI segment group. Each segment group has its own set of up to 256 buses.
+pub struct PciSegment<'a> {
+    pub(super) pci: &'a mut PciAccess,
+    pub(super) segment: u16,
+}
+
+impl<'a> PciSegment<'a> {
+    /// The buses in this segment group that are covered by the access method.
+    /// If the segment group is split across multiple MCFG entries, this is the range from the lowest to the highest bus.
+    pub fn known_buses(&self) -> RangeInclusive<u8> {
+        self.pci.known_buses_in_segment(self.segment)
+    }
+
+    pub fn bus(&mut self, bus_number: u8) -> PciBus {
+        PciBus {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number,
+        }
+    }
+
+    /// Iterates through [`Self::known_buses`]
+    pub fn buses(&mut self) -> PciBuses {
+        let buses = self.known_buses();
+        PciBuses {
+            pci: self.pci,
+            segment: self.segment,
+            buses,
+        }
+    }
+
+    pub(super) fn into_buses(self) -> PciBuses<'a> {
+        let buses = self.known_buses();
+        PciBuses {
+            pci: self.pci,
+            segment: self.segment,
+            buses,
+        }
+    }
+
+    pub(super) fn into_bus(self, bus_number: u8) -> PciBus<'a> {
+        PciBus {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number,
+        }
+    }
+}
+
+/// A lending iterator over buses. Because every [`PciBus`] borrows the [`PciAccess`], this can't implement [`Iterator`].
+/// Use it with `while let Some(bus) = buses.next()`.
+pub struct PciBuses<'a> {
+    pci: &'a mut PciAccess,
+    segment: u16,
+    buses: RangeInclusive<u8>,
+}
+
+impl PciBuses<'_> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<PciBus> {
+        let bus_number = self.buses.next()?;
+        Some(PciBus {
+            pci: self.pci,
+            segment: self.segment,
+            bus_number,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use core::cell::{RefCell, UnsafeCell};
+
+use core::fmt::Debug;
+
+use super::*;
+
+/// A [`PciAccess`] that can be shared with `&` references instead of `&mut`.
+/// This lets you keep multiple [`PciFunctionOwned`] handles at the same time, for example one per driver.
+///
+/// This uses a [`RefCell`], so it is only for single-threaded use.
+/// Accessing it again from inside one of the closures will panic.
+#[derive(Debug)]
+pub struct PciAccessShared {
+    pci: RefCell<PciAccess>,
+}
+
+impl PciAccessShared {
+    pub fn new(pci: PciAccess) -> Self {
+        Self {
+            pci: RefCell::new(pci),
+        }
+    }
+
+    pub fn into_inner(self) -> PciAccess {
+        self.pci.into_inner()
+    }
+
+    /// Temporarily use the [`PciAccess`], for example to scan for devices
+    pub fn with<R>(&self, f: impl FnOnce(&mut PciAccess) -> R) -> R {
+        f(&mut self.pci.borrow_mut())
+    }
+
+    /// Get a handle to a function that doesn't borrow the [`PciAccess`] mutably.
+    /// Returns `None` if the function is not present.
+    pub fn split_function(&self, address: PciAddress) -> Option<PciFunctionOwned> {
+        let identity = self.with(|pci| FunctionIdentity::read(pci, address))?;
+        Some(PciFunctionOwned {
+            pci: self,
+            address,
+            identity,
+        })
+    }
+}
+
+/// A lock that [`SyncPciAccess`] uses to serialize config accesses, such as a spinlock from your kernel
+///
+/// # Safety
+/// [`Self::lock`] must not return while the lock is held by anyone, including the current CPU or thread.
+/// This means the lock can't be re-entrant. Locking it again on the same thread must deadlock or panic.
+pub unsafe trait RawPciLock {
+    fn lock(&self);
+
+    /// # Safety
+    /// The lock must be held by the caller
+    unsafe fn unlock(&self);
+}
+
+/// A [`PciAccess`] that can be shared between CPUs.
+/// Every access holds the lock, so the legacy config address and config data ports can't be used by 2 CPUs at the same time.
+pub struct SyncPciAccess<L> {
+    lock: L,
+    pci: UnsafeCell<PciAccess>,
+}
+
+// Safety: the PciAccess is only accessed while the lock is held, and it can be used from any CPU because it is `Send`
+unsafe impl<L: RawPciLock + Sync> Sync for SyncPciAccess<L> where PciAccess: Send {}
+
+impl<L: RawPciLock> SyncPciAccess<L> {
+    pub fn new(pci: PciAccess, lock: L) -> Self {
+        Self {
+            lock,
+            pci: UnsafeCell::new(pci),
+        }
+    }
+
+    pub fn into_inner(self) -> PciAccess {
+        self.pci.into_inner()
+    }
+
+    /// Holds the lock while using the [`PciAccess`].
+    /// Calling this again from inside `f` deadlocks or panics, depending on the [`RawPciLock`].
+    pub fn with<R>(&self, f: impl FnOnce(&mut PciAccess) -> R) -> R {
+        /// Unlocks even if `f` panics
+        struct Guard<'a, L: RawPciLock>(&'a L);
+        impl<L: RawPciLock> Drop for Guard<'_, L> {
+            fn drop(&mut self) {
+                // Safety: the lock was locked when the guard was created
+                unsafe { self.0.unlock() }
+            }
+        }
+
+        self.lock.lock();
+        let _guard = Guard(&self.lock);
+        // Safety: the lock is held and isn't re-entrant, so nothing else has a reference to the PciAccess
+        f(unsafe { &mut *self.pci.get() })
+    }
+}
+
+impl<L> Debug for SyncPciAccess<L> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SyncPciAccess").finish_non_exhaustive()
+    }
+}
+
+/// A handle to a function which only needs a shared reference to [`PciAccessShared`]
+#[derive(Debug, Clone, Copy)]
+pub struct PciFunctionOwned<'a> {
+    pci: &'a PciAccessShared,
+    address: PciAddress,
+    identity: FunctionIdentity,
+}
+
+impl PciFunctionOwned<'_> {
+    pub fn address(&self) -> PciAddress {
+        self.address
+    }
+
+    /// Temporarily use the function. Anything that borrows the function, such as [`Msi`], must be used inside the closure.
+    pub fn with<R>(&self, f: impl FnOnce(&mut PciFunction) -> R) -> R {
+        self.pci.with(|pci| {
+            f(&mut PciFunction {
+                pci,
+                address: self.address,
+                identity: self.identity,
+            })
+        })
+    }
+}
@@ -0,0 +1,198 @@
+use core::fmt::Debug;
+
+use super::*;
+
+/// Single Root I/O Virtualization. This lets a physical function (PF) create virtual functions (VFs).
+///
+/// PCI Express Base Specification -> 9.3.3 SR-IOV Extended Capability
+pub struct SrIov<'a> {
+    pci: &'a mut PciAccess,
+    address: PciAddress,
+    ptr: u16,
+}
+
+impl<'a> SrIov<'a> {
+    pub(super) fn find(function: &'a mut PciFunction) -> Option<Option<Self>> {
+        if let Some(capability) = function
+            .extended_capabilities()?
+            .find(|capability| capability.id == 0x10)
+        {
+            Some(Some(Self {
+                pci: function.pci,
+                address: function.address,
+                ptr: capability.ptr_to_self,
+            }))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn control(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0x8)
+    }
+
+    pub fn vf_enable(&mut self) -> bool {
+        self.control() & 1 != 0
+    }
+
+    /// Set [`Self::set_num_vfs`] before enabling VFs
+    pub fn set_vf_enable(&mut self, vf_enable: bool) {
+        let control = self.control() & !1 | vf_enable as u16;
+        self.pci.write_u16(self.address, self.ptr + 0x8, control);
+    }
+
+    pub fn initial_vfs(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0xC)
+    }
+
+    /// The maximum number of VFs
+    pub fn total_vfs(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0xE)
+    }
+
+    pub fn num_vfs(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0x10)
+    }
+
+    /// This can only be changed while VFs are disabled.
+    /// The VF offset and stride can change after this is set.
+    ///
+    /// # Panics
+    /// Panics if `num_vfs` is more than [`Self::total_vfs`]
+    pub fn set_num_vfs(&mut self, num_vfs: u16) {
+        let total_vfs = self.total_vfs();
+        assert!(
+            num_vfs <= total_vfs,
+            "Can't enable {num_vfs} VFs, because the PF only supports {total_vfs}"
+        );
+        self.pci.write_u16(self.address, self.ptr + 0x10, num_vfs);
+    }
+
+    /// The routing ID offset of the first VF from the PF
+    pub fn vf_offset(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0x14)
+    }
+
+    /// The routing ID offset between consecutive VFs
+    pub fn vf_stride(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0x16)
+    }
+
+    pub fn vf_device_id(&mut self) -> u16 {
+        self.pci.read_u16(self.address, self.ptr + 0x1A)
+    }
+
+    /// The address of a VF. VFs can be on a different bus than the PF.
+    /// Call this after setting [`Self::set_num_vfs`], because the offset and stride depend on it.
+    ///
+    /// # Panics
+    /// Panics if `vf_index` is not less than [`Self::num_vfs`]
+    pub fn vf_address(&mut self, vf_index: u16) -> PciAddress {
+        let num_vfs = self.num_vfs();
+        assert!(
+            vf_index < num_vfs,
+            "VF {vf_index} doesn't exist, because there are {num_vfs} VFs"
+        );
+        let pf_routing_id = (self.address.bus as u16) << 8
+            | (self.address.device as u16) << 3
+            | self.address.function as u16;
+        let routing_id = pf_routing_id
+            .wrapping_add(self.vf_offset())
+            .wrapping_add(vf_index.wrapping_mul(self.vf_stride()));
+        PciAddress::new(
+            self.address.segment,
+            (routing_id >> 8) as u8,
+            ((routing_id >> 3) & 0b11111) as u8,
+            (routing_id & 0b111) as u8,
+        )
+    }
+}
+
+impl Debug for SrIov<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SrIov")
+            .field("address", &format_args!("{}", self.address))
+            .field("ptr", &format_args!("0x{:X}", self.ptr))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PF: PciAddress = PciAddress {
+        segment: 0,
+        bus: 3,
+        device: 0,
+        function: 0,
+    };
+
+    /// A PF that supports 128 VFs, with a VF offset of `0x80` and a VF stride of 2
+    fn mock_pf() -> PciAccess {
+        let mut body = [0; 0x3C];
+        // Initial VFs and Total VFs
+        body[0x8..0xC].copy_from_slice(&[128, 0, 128, 0]);
+        // VF offset and VF stride
+        body[0x10..0x14].copy_from_slice(&[0x80, 0, 2, 0]);
+        // VF device ID
+        body[0x16..0x18].copy_from_slice(&0x10ED_u16.to_le_bytes());
+        let mut mock = MockConfig::new(AccessKind::Ecam).with_ecam_buses(0..=4);
+        mock.function(PF)
+            .header(0x8086, 0x10FB, [0x02, 0x00, 0x00], 0x0)
+            .extended_capability(0x100, 0x10, 1, &body);
+        mock.into_pci_access()
+    }
+
+    #[test]
+    fn vf_addresses() {
+        let mut pci = mock_pf();
+        let mut function = pci.function_at(3, 0, 0).unwrap();
+        let mut sr_iov = function.sr_iov().unwrap().unwrap();
+        assert_eq!(sr_iov.total_vfs(), 128);
+        assert_eq!(sr_iov.vf_device_id(), 0x10ED);
+        sr_iov.set_num_vfs(100);
+        assert_eq!(sr_iov.num_vfs(), 100);
+        // The PF's routing ID is 0x300, so the first VF's routing ID is 0x380
+        assert_eq!(sr_iov.vf_address(0), PciAddress::new(0, 3, 16, 0));
+        assert_eq!(sr_iov.vf_address(1), PciAddress::new(0, 3, 16, 2));
+        assert_eq!(sr_iov.vf_address(4), PciAddress::new(0, 3, 17, 0));
+        assert_eq!(sr_iov.vf_address(63), PciAddress::new(0, 3, 31, 6));
+        // Routing ID 0x400 is on the next bus
+        assert_eq!(sr_iov.vf_address(64), PciAddress::new(0, 4, 0, 0));
+        assert_eq!(sr_iov.vf_address(99), PciAddress::new(0, 4, 8, 6));
+    }
+
+    #[test]
+    fn enabling_vfs() {
+        let mut pci = mock_pf();
+        let mut function = pci.function_at(3, 0, 0).unwrap();
+        let mut sr_iov = function.sr_iov().unwrap().unwrap();
+        assert!(!sr_iov.vf_enable());
+        sr_iov.set_num_vfs(8);
+        sr_iov.set_vf_enable(true);
+        assert!(sr_iov.vf_enable());
+        assert_eq!(pci.mock().function(PF).u16(0x108), 1);
+        assert_eq!(pci.mock().function(PF).u16(0x110), 8);
+    }
+
+    #[test]
+    fn no_sr_iov() {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(PciAddress::new(0, 0, 0, 0))
+            .header(0x8086, 0x10FB, [0x02, 0x00, 0x00], 0x0)
+            .extended_capability(0x100, 0x3, 1, &[0; 8]);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 0, 0).unwrap();
+        assert!(function.sr_iov().unwrap().is_none());
+        let mut pci = MockConfig::new(AccessKind::LegacyPortIo).into_pci_access();
+        pci.mock().function(PciAddress::new(0, 0, 0, 0)).header(
+            0x8086,
+            0x10FB,
+            [0x02, 0x00, 0x00],
+            0x0,
+        );
+        let mut function = pci.function_at(0, 0, 0).unwrap();
+        assert!(function.sr_iov().is_none());
+    }
+}
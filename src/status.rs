@@ -0,0 +1,84 @@
+use bitfield::bitfield;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+bitfield! {
+    /// The error bits are latched. They are cleared by writing a 1 to them.
+    #[derive(Clone, Copy)]
+    pub struct StatusRegister(u16);
+    impl Debug;
+
+    // bits 0..=2 are reserved
+    /// If this is 1, the function is asserting its INTx interrupt
+    pub interrupt_status, _: 3;
+    /// If this is 1, the function has a capabilities list
+    pub capabilities_list, _: 4;
+    pub capable_66_mhz, _: 5;
+    // bit 6 is reserved
+    pub fast_back_to_back_capable, _: 7;
+    pub master_data_parity_error, _: 8;
+    u8; devsel_timing_raw, _: 10, 9;
+    pub signaled_target_abort, _: 11;
+    pub received_target_abort, _: 12;
+    pub received_master_abort, _: 13;
+    pub signaled_system_error, _: 14;
+    pub detected_parity_error, _: 15;
+}
+
+/// The defined single bits of [`StatusRegister`], for reading them by name with [`StatusRegister::get`].
+/// Convert from and to the bit index with [`TryFrom<u8>`] and [`Into<u8>`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum StatusBit {
+    InterruptStatus = 3,
+    CapabilitiesList = 4,
+    Capable66Mhz = 5,
+    FastBackToBackCapable = 7,
+    MasterDataParityError = 8,
+    SignaledTargetAbort = 11,
+    ReceivedTargetAbort = 12,
+    ReceivedMasterAbort = 13,
+    SignaledSystemError = 14,
+    DetectedParityError = 15,
+}
+
+impl StatusBit {
+    pub const ALL: [Self; 10] = [
+        Self::InterruptStatus,
+        Self::CapabilitiesList,
+        Self::Capable66Mhz,
+        Self::FastBackToBackCapable,
+        Self::MasterDataParityError,
+        Self::SignaledTargetAbort,
+        Self::ReceivedTargetAbort,
+        Self::ReceivedMasterAbort,
+        Self::SignaledSystemError,
+        Self::DetectedParityError,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum DevselTiming {
+    Fast = 0b00,
+    Medium = 0b01,
+    Slow = 0b10,
+}
+
+impl StatusRegister {
+    /// The latched error bits (8 and 11-15). Write this to the register to clear all of them.
+    pub const ERROR_BITS: u16 = 1 << 8 | 0xF800;
+
+    /// Whether any of the [`Self::ERROR_BITS`] are set
+    pub fn has_errors(&self) -> bool {
+        self.0 & Self::ERROR_BITS != 0
+    }
+
+    pub fn get(&self, bit: StatusBit) -> bool {
+        self.0 & (1 << u8::from(bit)) != 0
+    }
+
+    /// Returns `None` if the value is reserved
+    pub fn devsel_timing(&self) -> Option<DevselTiming> {
+        self.devsel_timing_raw().try_into().ok()
+    }
+}
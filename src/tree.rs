@@ -0,0 +1,128 @@
+use alloc::vec::Vec;
+
+use super::*;
+
+/// An owned copy of the whole PCI hierarchy. See [`PciAccess::build_tree`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PciTree {
+    /// Only buses that have at least 1 device
+    pub buses: Vec<BusNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BusNode {
+    pub segment: u16,
+    pub bus_number: u8,
+    pub devices: Vec<DeviceNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceNode {
+    pub device_number: u8,
+    pub multi_function: bool,
+    pub functions: Vec<FunctionNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FunctionNode {
+    pub info: FunctionInfo,
+    /// The BARs that are present, with their index
+    pub bars: Vec<(u8, BarWithSize)>,
+    pub capabilities: Vec<Capability>,
+}
+
+impl FunctionNode {
+    fn read(function: &mut PciFunction, multi_function: bool) -> Self {
+        let info = FunctionInfo::read(function, multi_function);
+        let bars = function.bars().collect();
+        let capabilities = function
+            .capabilities()
+            .map(|capabilities| capabilities.collect())
+            .unwrap_or_default();
+        Self {
+            info,
+            bars,
+            capabilities,
+        }
+    }
+}
+
+impl PciAccess {
+    /// Scans everything and returns an owned tree of buses, devices, and functions.
+    /// Note that this sizes every BAR, which briefly writes to the BAR registers.
+    pub fn build_tree(&mut self) -> PciTree {
+        let mut tree = PciTree { buses: Vec::new() };
+        // Functions are walked in order, so a new bus or device starts whenever the address changes
+        self.for_each_function(|function, multi_function| {
+            let address = function.address();
+            if tree
+                .buses
+                .last()
+                .is_none_or(|bus| (bus.segment, bus.bus_number) != (address.segment, address.bus))
+            {
+                tree.buses.push(BusNode {
+                    segment: address.segment,
+                    bus_number: address.bus,
+                    devices: Vec::new(),
+                });
+            }
+            let bus = tree.buses.last_mut().expect("A bus was just pushed");
+            if bus
+                .devices
+                .last()
+                .is_none_or(|device| device.device_number != address.device)
+            {
+                bus.devices.push(DeviceNode {
+                    device_number: address.device,
+                    multi_function,
+                    functions: Vec::new(),
+                });
+            }
+            let device = bus.devices.last_mut().expect("A device was just pushed");
+            device
+                .functions
+                .push(FunctionNode::read(function, multi_function));
+        });
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn build_tree_groups_functions_by_bus_and_device() {
+        let mut mock = MockConfig::new(AccessKind::Ecam).with_ecam_buses(0..=1);
+        mock.function(PciAddress::new(0, 0, 0, 0))
+            .header(0x8086, 0x1111, [0x06, 0x04, 0x00], 0x81)
+            .set_bytes(0x18, &[0, 1, 1, 0]);
+        mock.function(PciAddress::new(0, 0, 0, 3))
+            .header(0x8086, 0x2222, [0x0C, 0x03, 0x30], 0x0);
+        mock.function(PciAddress::new(0, 1, 4, 0))
+            .header(0x10EC, 0x8168, [0x02, 0x00, 0x00], 0x0);
+        let mut pci = mock.into_pci_access();
+        let tree = pci.build_tree();
+        let shape = tree
+            .buses
+            .iter()
+            .map(|bus| {
+                (
+                    bus.bus_number,
+                    bus.devices
+                        .iter()
+                        .map(|device| (device.device_number, device.functions.len()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(shape, [(0, vec![(0, 2)]), (1, vec![(4, 1)])]);
+        assert!(tree.buses[0].devices[0].multi_function);
+        assert_eq!(
+            tree.buses[0].devices[0].functions[1].info.address,
+            PciAddress::new(0, 0, 0, 3)
+        );
+    }
+}
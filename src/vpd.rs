@@ -0,0 +1,132 @@
+use core::fmt::Debug;
+
+use super::*;
+
+/// The Vital Product Data capability (ID `0x3`), which has things like the serial number and part number.
+///
+/// PCI Local Bus Specification Rev. 3.0 -> 6.4. Vital Product Data
+pub struct Vpd<'a> {
+    pci: &'a mut PciAccess,
+    address: PciAddress,
+    ptr: u16,
+}
+
+impl<'a> Vpd<'a> {
+    pub(super) fn find(function: &'a mut PciFunction) -> Option<Option<Self>> {
+        Some(function.find_capability(0x3)?.map(|capability| Self {
+            pci: function.pci,
+            address: function.address,
+            ptr: capability.ptr_to_self.into(),
+        }))
+    }
+}
+
+impl Vpd<'_> {
+    /// The F flag in the VPD address register. It is set by the function when a read is done.
+    const FLAG: u16 = 1 << 15;
+
+    /// Reads the 4 bytes of VPD at `offset`.
+    /// Reads are 4 bytes at a time, so you have to put together the resource and keyword structures yourself.
+    ///
+    /// There is no timer in `no_std`, so the completion flag is polled up to `max_tries` times.
+    /// Returns `None` if the read didn't complete.
+    ///
+    /// # Panics
+    /// Panics if `offset` is not a multiple of 4 or doesn't fit in 15 bits
+    pub fn read(&mut self, offset: u16, max_tries: usize) -> Option<u32> {
+        assert!(
+            offset.is_multiple_of(size_of::<u32>() as u16) && offset & Self::FLAG == 0,
+            "VPD offset 0x{offset:X} must be a multiple of 4 and less than 0x8000"
+        );
+        // Writing the address with the flag cleared starts the read
+        self.pci.write_u16(self.address, self.ptr + 0x2, offset);
+        (0..max_tries)
+            .any(|_| self.pci.read_u16(self.address, self.ptr + 0x2) & Self::FLAG != 0)
+            .then(|| self.pci.read_u32(self.address, self.ptr + 0x4))
+    }
+}
+
+impl Debug for Vpd<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Vpd")
+            .field("address", &format_args!("{}", self.address))
+            .field("ptr", &format_args!("0x{:X}", self.ptr))
+            .finish()
+    }
+}
+
+impl PciFunction<'_> {
+    /// Returns `None` if the header type is unknown, and `Some(None)` if the function doesn't have VPD
+    #[must_use]
+    pub fn vpd(&mut self) -> Option<Option<Vpd>> {
+        Vpd::find(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress {
+        segment: 0,
+        bus: 0,
+        device: 8,
+        function: 0,
+    };
+
+    /// Reads VPD from a function with VPD at `0x60`, which sets the F flag after it is polled `polls` times.
+    /// Returns what was read, the write that started the read, and the offsets of the reads after it.
+    fn read_vpd(
+        offset: u16,
+        polls: usize,
+        max_tries: usize,
+    ) -> (Option<u32>, MockAccess, Vec<u16>) {
+        let mut mock = MockConfig::new(AccessKind::Ecam);
+        mock.function(ADDRESS)
+            .header(0x8086, 0x10D3, [0x02, 0x00, 0x00], 0x0)
+            .capability(0x60, 0x3, &[0; 6])
+            .set_u32(0x64, 0x1234_5678)
+            .completion_flag_u16(0x62, Vpd::FLAG, polls);
+        let mut pci = mock.into_pci_access();
+        let mut function = pci.function_at(0, 8, 0).unwrap();
+        let data = function.vpd().unwrap().unwrap().read(offset, max_tries);
+        let mock = pci.mock();
+        let mut writes = mock.writes().copied();
+        let write = writes.next().unwrap();
+        assert_eq!(writes.next(), None);
+        let reads = mock
+            .log()
+            .iter()
+            .skip_while(|access| !access.is_write)
+            .skip(1)
+            .map(|access| access.register_offset)
+            .collect();
+        (data, write, reads)
+    }
+
+    #[test]
+    fn read_completes() {
+        let (data, write, reads) = read_vpd(0x10, 3, 10);
+        assert_eq!(data, Some(0x1234_5678));
+        // The address is written with the flag clear, which starts the read
+        assert_eq!(
+            (write.register_offset, write.len, write.value),
+            (0x62, 2, 0x10)
+        );
+        assert_eq!(reads, [0x62, 0x62, 0x62, 0x64]);
+    }
+
+    #[test]
+    fn read_times_out() {
+        let (data, write, reads) = read_vpd(0x7FFC, usize::MAX, 5);
+        assert_eq!(data, None);
+        assert_eq!(
+            (write.register_offset, write.len, write.value),
+            (0x62, 2, 0x7FFC)
+        );
+        // The data register isn't read
+        assert_eq!(reads, [0x62; 5]);
+    }
+}